@@ -0,0 +1,130 @@
+// Derive macro for the rust-rbf record module.
+//
+// #[derive(Record)] on a struct with named fields implements
+// rust_rbf::record::Record, with one attribute per field, in field order.
+// Field types must implement rust_rbf::record::RecordField.
+//
+// Field options, given as #[record(...)]:
+// - rename = "Name": use Name as the attribute name instead of the field name
+// - len = 20: max length for String (Varchar) and Vec<u8> (Varbinary) fields
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitInt, LitStr};
+
+#[proc_macro_derive(Record, attributes(record))]
+pub fn derive_record(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+struct FieldOpts {
+    name: String,
+    len: Option<u32>,
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    input,
+                    "Record can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "Record can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut attributes = vec![];
+    let mut to_values = vec![];
+    let mut from_values = vec![];
+    for field in fields.iter() {
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let opts = field_opts(field)?;
+        let name = &opts.name;
+        let len = match opts.len {
+            Some(len) => quote!(::std::option::Option::Some(#len)),
+            None => quote!(::std::option::Option::None),
+        };
+        attributes.push(quote! {
+            ::rust_rbf::record::field_attribute::<#ty>(#name, #len)
+        });
+        to_values.push(quote! {
+            vals.insert(
+                #name.to_string(),
+                ::rust_rbf::record::RecordField::to_value(&self.#ident, #len),
+            );
+        });
+        from_values.push(quote! {
+            #ident: ::rust_rbf::record::take_value::<#ty>(&mut vals, #name)?,
+        });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::rust_rbf::record::Record for #ident #ty_generics #where_clause {
+            fn attributes() -> ::std::vec::Vec<::rust_rbf::attribute::Attribute> {
+                ::std::vec![#(#attributes),*]
+            }
+
+            fn to_values(
+                &self,
+            ) -> ::std::collections::HashMap<
+                ::std::string::String,
+                ::rust_rbf::attribute::AttributeValue,
+            > {
+                let mut vals = ::std::collections::HashMap::new();
+                #(#to_values)*
+                vals
+            }
+
+            fn from_values(
+                mut vals: ::std::collections::HashMap<
+                    ::std::string::String,
+                    ::rust_rbf::attribute::AttributeValue,
+                >,
+            ) -> ::rust_rbf::error::Result<Self> {
+                ::std::result::Result::Ok(Self {
+                    #(#from_values)*
+                })
+            }
+        }
+    })
+}
+
+fn field_opts(field: &syn::Field) -> syn::Result<FieldOpts> {
+    let mut opts = FieldOpts {
+        name: field.ident.as_ref().unwrap().to_string(),
+        len: None,
+    };
+    for attr in field.attrs.iter() {
+        if !attr.path().is_ident("record") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                opts.name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else if meta.path.is_ident("len") {
+                opts.len = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unknown record option"))
+            }
+        })?;
+    }
+    Ok(opts)
+}
@@ -1,16 +1,128 @@
+use crate::json::Json;
+use crate::uuid::Uuid;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter;
+
+// Stored in each record file's schema page, so new variants go at the end
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum AttributeType {
     Int,
     Real,
     Varchar { len: u32 },
+    Varbinary { len: u32 },
+    // Unbounded binary data. Large values are stored in overflow pages.
+    Blob,
+    // Fixed length string, blank padded to len bytes
+    Char { len: u32 },
+    // Stored as 16 raw bytes
+    Uuid,
+    // Stored in the compact binary form from the json module
+    Json,
+    // Elements all have the given type, and may be null
+    Array(Box<AttributeType>),
+}
+
+/// Formats the type as it would be declared, e.g. Varchar(20) or Array(Int).
+impl fmt::Display for AttributeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AttributeType::Int => write!(f, "Int"),
+            AttributeType::Real => write!(f, "Real"),
+            AttributeType::Varchar { len } => write!(f, "Varchar({})", len),
+            AttributeType::Varbinary { len } => write!(f, "Varbinary({})", len),
+            AttributeType::Blob => write!(f, "Blob"),
+            AttributeType::Char { len } => write!(f, "Char({})", len),
+            AttributeType::Uuid => write!(f, "Uuid"),
+            AttributeType::Json => write!(f, "Json"),
+            AttributeType::Array(elem_type) => write!(f, "Array({})", elem_type),
+        }
+    }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Attribute {
     pub name: String,
     pub attribute_type: AttributeType,
+    // False for NOT NULL attributes
+    pub nullable: bool,
+    // Filled in by insert when no value is given for the attribute
+    pub default: Option<AttributeValue>,
+}
+
+impl Attribute {
+    /// A nullable attribute with no default.
+    pub fn new(name: &str, attribute_type: AttributeType) -> Self {
+        Attribute {
+            name: name.to_string(),
+            attribute_type,
+            nullable: true,
+            default: None,
+        }
+    }
+
+    pub fn not_null(mut self) -> Self {
+        self.nullable = false;
+        self
+    }
+
+    pub fn with_default(mut self, default: AttributeValue) -> Self {
+        self.default = Some(default);
+        self
+    }
 }
-#[derive(Debug, PartialEq)]
+
+// Comparison and hashing are implemented in the compare module
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AttributeValue {
+    // SQL NULL. Valid for any nullable attribute.
+    Null,
     Int(i32),
     Real(f64),
     Varchar(String),
+    Varbinary(Vec<u8>),
+    Blob(Vec<u8>),
+    Char(String),
+    Uuid(Uuid),
+    Json(Json),
+    // None for null elements
+    Array(Vec<Option<AttributeValue>>),
+}
+
+/// Compare two Char values using SQL blank-padding semantics.
+/// The shorter value is treated as if padded with blanks to the longer length.
+pub fn char_cmp(a: &str, b: &str) -> Ordering {
+    let len = a.len().max(b.len());
+    let padded = |s: &str| {
+        s.bytes()
+            .chain(iter::repeat(b' '))
+            .take(len)
+            .collect::<Vec<_>>()
+    };
+    padded(a).cmp(&padded(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn char_cmp_test() {
+        assert_eq!(char_cmp("US", "US  "), Ordering::Equal);
+        assert_eq!(char_cmp("", "   "), Ordering::Equal);
+        assert_eq!(char_cmp("AB", "AC"), Ordering::Less);
+        // Tab sorts before the blank the shorter value is padded with
+        assert_eq!(char_cmp("AB\t", "AB"), Ordering::Less);
+        assert_eq!(char_cmp("ABC", "AB"), Ordering::Greater);
+
+        assert_eq!(
+            AttributeValue::Char("US".to_string()),
+            AttributeValue::Char("US ".to_string())
+        );
+        assert_ne!(
+            AttributeValue::Char("US".to_string()),
+            AttributeValue::Varchar("US".to_string())
+        );
+    }
 }
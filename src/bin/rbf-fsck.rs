@@ -0,0 +1,56 @@
+// Check a record file for corruption
+//
+// Usage: rbf-fsck [--repair] FILE
+//
+// Prints each problem found. Exits with 0 if the file is clean, or every
+// problem was repaired, 1 if problems remain, and 2 if the file couldn't be
+// checked.
+
+use rust_rbf::fsck;
+use std::env;
+use std::path::Path;
+use std::process;
+
+fn usage() -> ! {
+    eprintln!("Usage: rbf-fsck [--repair] FILE");
+    process::exit(2);
+}
+
+fn main() {
+    let mut repair = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--repair" => repair = true,
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+
+    let report = match fsck::check_file(Path::new(&path), repair) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(2);
+        }
+    };
+    for problem in report.problems.iter() {
+        println!("{}: {}", path, problem);
+    }
+    println!(
+        "{}: {} pages, {} records, {} problems, {} repaired",
+        path,
+        report.pages,
+        report.records,
+        report.problems.len(),
+        report
+            .problems
+            .iter()
+            .filter(|problem| problem.repaired)
+            .count()
+    );
+    if !report.is_clean() {
+        process::exit(1);
+    }
+}
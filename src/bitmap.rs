@@ -1,3 +1,5 @@
+use crate::error::{Error, Result};
+
 pub struct Bitmap {
     bmp: Vec<u8>,
     size: usize,
@@ -9,12 +11,10 @@ impl Bitmap {
         Self { bmp, size }
     }
 
-    pub fn new_with_vec(size: usize, bmp: Vec<u8>) -> Self {
-        let size_check = Self::bmp_size_in_bytes(size);
-        if size_check != bmp.len() {
-            panic!()
-        }
-        Self { bmp, size }
+    /// Fails with Corruption if bmp isn't the right length for size bits.
+    pub fn new_with_vec(size: usize, bmp: Vec<u8>) -> Result<Self> {
+        BitmapRef::new(size, &bmp)?;
+        Ok(Self { bmp, size })
     }
 
     pub fn bmp_size_in_bytes(size: usize) -> usize {
@@ -38,6 +38,44 @@ impl Bitmap {
         self.bmp[byte_idx] |= mask;
     }
 
+    pub fn get(&self, idx: usize) -> bool {
+        self.as_bitmap_ref().get(idx)
+    }
+
+    /// Count the set bits before idx
+    pub fn count_ones_before(&self, idx: usize) -> Result<usize> {
+        self.as_bitmap_ref().count_ones_before(idx)
+    }
+
+    pub fn as_bitmap_ref(&self) -> BitmapRef<'_> {
+        BitmapRef {
+            bmp: &self.bmp,
+            size: self.size,
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bmp
+    }
+}
+
+/// A bitmap borrowed from a buffer, e.g. a record's null bitmap.
+#[derive(Clone, Copy)]
+pub struct BitmapRef<'a> {
+    bmp: &'a [u8],
+    size: usize,
+}
+
+impl<'a> BitmapRef<'a> {
+    /// Fails with Corruption if bmp isn't the right length for size bits,
+    /// since bitmaps are usually read from a file.
+    pub fn new(size: usize, bmp: &'a [u8]) -> Result<Self> {
+        if Bitmap::bmp_size_in_bytes(size) != bmp.len() {
+            return Err(Error::corrupt("Invalid bitmap length"));
+        }
+        Ok(Self { bmp, size })
+    }
+
     pub fn get(&self, idx: usize) -> bool {
         if idx >= self.size {
             panic!()
@@ -48,7 +86,49 @@ impl Bitmap {
         self.bmp[byte_idx] & mask != 0
     }
 
-    pub fn into_bytes(self) -> Vec<u8> {
-        self.bmp
+    /// Count the set bits before idx, which can be at most the size.
+    pub fn count_ones_before(&self, idx: usize) -> Result<usize> {
+        if idx > self.size {
+            return Err(Error::corrupt("Bitmap index out of bounds"));
+        }
+        let full_bytes = idx / 8;
+        let rem = idx % 8;
+        let mut count: usize = self.bmp[..full_bytes]
+            .iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum();
+        if rem != 0 {
+            let mask = (1 << rem) - 1;
+            count += (self.bmp[full_bytes] & mask).count_ones() as usize;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitmap_test() {
+        let mut bmp = Bitmap::new(10);
+        bmp.set(1);
+        bmp.set(9);
+        assert!(bmp.get(1) && bmp.get(9) && !bmp.get(2));
+        assert_eq!(bmp.count_ones_before(9).unwrap(), 1);
+        assert_eq!(bmp.count_ones_before(10).unwrap(), 2);
+
+        // Bad sizes, e.g. from a corrupt page, are errors rather than panics
+        let bytes = bmp.into_bytes();
+        assert!(matches!(
+            BitmapRef::new(17, &bytes),
+            Err(Error::Corruption { .. })
+        ));
+        assert!(Bitmap::new_with_vec(8, bytes.clone()).is_err());
+        let bmp = BitmapRef::new(10, &bytes).unwrap();
+        assert!(matches!(
+            bmp.count_ones_before(11),
+            Err(Error::Corruption { .. })
+        ));
     }
 }
@@ -0,0 +1,354 @@
+// Casting
+//
+// Converts AttributeValues to other AttributeTypes.
+//
+// Implicit coercions (coerce) never lose information:
+// - Any value to its own type, if it fits (e.g. a Varchar within the max len)
+// - Int to Real
+// - Varchar to Char and Char to Varchar, if the value fits
+// - Varbinary to Blob, and Blob to Varbinary if the value fits
+// - Arrays, element by element
+// - Null to any type, staying Null
+//
+// Explicit casts (cast) additionally allow:
+// - Real to Int, rounding to the nearest integer (halfway cases away from zero)
+// - Varchar or Char to Int, Real, Uuid or Json, by parsing the text after
+//   trimming surrounding whitespace (Json text is not trimmed)
+// - Int, Real, Uuid or Json to Varchar or Char, by formatting the value
+// - Varchar or Char to Varbinary or Blob as UTF-8 bytes, and back if the bytes are UTF-8
+// - Uuid to Varbinary or Blob as its 16 bytes, and back from exactly 16 bytes
+// - Int, Real, Varchar, Char and Arrays to Json
+// - Json numbers and strings to Int, Real, Varchar or Char, and Json arrays to Arrays
+//
+// Anything else, or a value that doesn't fit the target type, is an error
+// describing why the cast failed.
+
+use crate::attribute::*;
+use crate::error::{Error, Result};
+use crate::json::Json;
+use crate::uuid::Uuid;
+use std::num::IntErrorKind;
+
+/// Convert val to attr_type using only the implicit coercion rules.
+pub fn coerce(val: &AttributeValue, attr_type: &AttributeType) -> Result<AttributeValue> {
+    convert(val, attr_type, false)
+}
+
+/// Convert val to attr_type using the explicit casting rules.
+pub fn cast(val: &AttributeValue, attr_type: &AttributeType) -> Result<AttributeValue> {
+    convert(val, attr_type, true)
+}
+
+/// Short name of the value's type, for error messages.
+pub(crate) fn type_name(val: &AttributeValue) -> &'static str {
+    match val {
+        AttributeValue::Null => "Null",
+        AttributeValue::Int(_) => "Int",
+        AttributeValue::Real(_) => "Real",
+        AttributeValue::Varchar(_) => "Varchar",
+        AttributeValue::Varbinary(_) => "Varbinary",
+        AttributeValue::Blob(_) => "Blob",
+        AttributeValue::Char(_) => "Char",
+        AttributeValue::Uuid(_) => "Uuid",
+        AttributeValue::Json(_) => "Json",
+        AttributeValue::Array(_) => "Array",
+    }
+}
+
+fn cast_error(val: &AttributeValue, attr_type: &AttributeType, reason: &str) -> Error {
+    Error::invalid_input(format!(
+        "Cannot cast {} to {}: {}",
+        type_name(val),
+        attr_type,
+        reason
+    ))
+}
+
+fn convert(
+    val: &AttributeValue,
+    attr_type: &AttributeType,
+    explicit: bool,
+) -> Result<AttributeValue> {
+    use AttributeValue as V;
+    let err = |reason: &str| cast_error(val, attr_type, reason);
+    let too_long = |len: usize, max: u32| {
+        err(&format!(
+            "value is {} bytes, longer than the max of {}",
+            len, max
+        ))
+    };
+    let needs_explicit = || err("requires an explicit cast");
+    // Fit text to a Varchar or Char target
+    let fit_text = |s: &str| match attr_type {
+        AttributeType::Char { len } => {
+            let trimmed = s.trim_end_matches(' ');
+            if trimmed.len() > *len as usize {
+                return Err(too_long(trimmed.len(), *len));
+            }
+            Ok(V::Char(trimmed.to_string()))
+        }
+        AttributeType::Varchar { len } => {
+            if s.len() > *len as usize {
+                return Err(too_long(s.len(), *len));
+            }
+            Ok(V::Varchar(s.to_string()))
+        }
+        _ => unreachable!(),
+    };
+    // Fit bytes to a Varbinary or Blob target
+    let fit_bytes = |b: &[u8]| match attr_type {
+        AttributeType::Varbinary { len } => {
+            if b.len() > *len as usize {
+                return Err(too_long(b.len(), *len));
+            }
+            Ok(V::Varbinary(b.to_vec()))
+        }
+        AttributeType::Blob => Ok(V::Blob(b.to_vec())),
+        _ => unreachable!(),
+    };
+
+    match (attr_type, val) {
+        // NULL converts to NULL of any type
+        (_, V::Null) => Ok(V::Null),
+        (AttributeType::Int, V::Int(i)) => Ok(V::Int(*i)),
+        (AttributeType::Int, V::Real(r)) if explicit => {
+            let rounded = r.round();
+            if rounded.is_nan() || rounded < i32::MIN as f64 || rounded > i32::MAX as f64 {
+                return Err(err(&format!("{} is out of range", r)));
+            }
+            Ok(V::Int(rounded as i32))
+        }
+        (AttributeType::Int, V::Varchar(s)) | (AttributeType::Int, V::Char(s)) if explicit => s
+            .trim()
+            .parse::<i32>()
+            .map(V::Int)
+            .map_err(|e| match e.kind() {
+                IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => {
+                    err(&format!("\"{}\" is out of range", s))
+                }
+                _ => err(&format!("\"{}\" is not an integer", s)),
+            }),
+
+        (AttributeType::Real, V::Real(r)) => Ok(V::Real(*r)),
+        (AttributeType::Real, V::Int(i)) => Ok(V::Real(*i as f64)),
+        (AttributeType::Real, V::Varchar(s)) | (AttributeType::Real, V::Char(s)) if explicit => s
+            .trim()
+            .parse::<f64>()
+            .map(V::Real)
+            .map_err(|_| err(&format!("\"{}\" is not a number", s))),
+
+        (AttributeType::Varchar { .. }, V::Varchar(s))
+        | (AttributeType::Varchar { .. }, V::Char(s))
+        | (AttributeType::Char { .. }, V::Varchar(s))
+        | (AttributeType::Char { .. }, V::Char(s)) => fit_text(s),
+        (AttributeType::Varchar { .. }, _) | (AttributeType::Char { .. }, _) if explicit => {
+            let text = match val {
+                V::Int(i) => i.to_string(),
+                V::Real(r) => r.to_string(),
+                V::Uuid(u) => u.to_string(),
+                // Json strings cast to their contents rather than quoted text
+                V::Json(Json::String(s)) => s.clone(),
+                V::Json(j) => j.to_string(),
+                V::Varbinary(b) | V::Blob(b) => std::str::from_utf8(b)
+                    .map_err(|_| err("bytes are not valid UTF-8"))?
+                    .to_string(),
+                _ => return Err(err("unsupported cast")),
+            };
+            fit_text(&text)
+        }
+
+        (AttributeType::Varbinary { .. }, V::Varbinary(b))
+        | (AttributeType::Varbinary { .. }, V::Blob(b))
+        | (AttributeType::Blob, V::Varbinary(b))
+        | (AttributeType::Blob, V::Blob(b)) => fit_bytes(b),
+        (AttributeType::Varbinary { .. }, _) | (AttributeType::Blob, _) if explicit => match val {
+            V::Varchar(s) | V::Char(s) => fit_bytes(s.as_bytes()),
+            V::Uuid(u) => fit_bytes(u.as_bytes()),
+            _ => Err(err("unsupported cast")),
+        },
+
+        (AttributeType::Uuid, V::Uuid(u)) => Ok(V::Uuid(*u)),
+        (AttributeType::Uuid, V::Varchar(s)) | (AttributeType::Uuid, V::Char(s)) if explicit => s
+            .trim()
+            .parse::<Uuid>()
+            .map(V::Uuid)
+            .map_err(|_| err(&format!("\"{}\" is not a UUID", s))),
+        (AttributeType::Uuid, V::Varbinary(b)) | (AttributeType::Uuid, V::Blob(b)) if explicit => {
+            if b.len() != 16 {
+                return Err(err(&format!("expected 16 bytes, found {}", b.len())));
+            }
+            let mut bytes = [0; 16];
+            bytes.copy_from_slice(b);
+            Ok(V::Uuid(Uuid::from_bytes(bytes)))
+        }
+
+        (AttributeType::Json, V::Json(j)) => Ok(V::Json(j.clone())),
+        (AttributeType::Json, V::Varchar(s)) | (AttributeType::Json, V::Char(s)) if explicit => s
+            .parse::<Json>()
+            .map(V::Json)
+            .map_err(|e| err(&e.to_string())),
+        (AttributeType::Json, _) if explicit => to_json(val)
+            .map(V::Json)
+            .ok_or_else(|| err("unsupported cast")),
+
+        (AttributeType::Array(elem_type), V::Array(elems)) => {
+            let elems = elems
+                .iter()
+                .map(|elem| match elem {
+                    Some(elem) => convert(elem, elem_type, explicit).map(Some),
+                    None => Ok(None),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(V::Array(elems))
+        }
+        (AttributeType::Array(_), V::Json(Json::Array(elems))) if explicit => {
+            let elems = elems.iter().map(|elem| elem.to_attribute_value()).collect();
+            convert(&V::Array(elems), attr_type, explicit)
+        }
+
+        // Json scalars cast like the equivalent value
+        (_, V::Json(j)) if explicit => match j {
+            Json::Int(_) | Json::Real(_) | Json::String(_) => {
+                convert(&j.to_attribute_value().unwrap(), attr_type, explicit)
+            }
+            _ => Err(err("unsupported cast")),
+        },
+
+        _ if !explicit && cast(val, attr_type).is_ok() => Err(needs_explicit()),
+        _ => Err(err("unsupported cast")),
+    }
+}
+
+fn to_json(val: &AttributeValue) -> Option<Json> {
+    match val {
+        AttributeValue::Null => Some(Json::Null),
+        AttributeValue::Int(i) => Some(Json::Int(*i as i64)),
+        AttributeValue::Real(r) if r.is_finite() => Some(Json::Real(*r)),
+        AttributeValue::Varchar(s) | AttributeValue::Char(s) => Some(Json::String(s.clone())),
+        AttributeValue::Json(j) => Some(j.clone()),
+        AttributeValue::Array(elems) => elems
+            .iter()
+            .map(|elem| match elem {
+                Some(elem) => to_json(elem),
+                None => Some(Json::Null),
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(Json::Array),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varchar(s: &str) -> AttributeValue {
+        AttributeValue::Varchar(s.to_string())
+    }
+
+    #[test]
+    fn coerce_test() {
+        use AttributeValue::*;
+        assert_eq!(coerce(&Int(3), &AttributeType::Real).unwrap(), Real(3.0));
+        assert_eq!(
+            coerce(&Char("ab".to_string()), &AttributeType::Varchar { len: 2 }).unwrap(),
+            varchar("ab")
+        );
+        assert_eq!(
+            coerce(&Varbinary(vec![1]), &AttributeType::Blob).unwrap(),
+            Blob(vec![1])
+        );
+        assert!(coerce(&varchar("abc"), &AttributeType::Varchar { len: 2 }).is_err());
+
+        // Lossy or parsing conversions need an explicit cast
+        let err = coerce(&Real(1.5), &AttributeType::Int).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cannot cast Real to Int: requires an explicit cast"
+        );
+        assert!(coerce(&varchar("1"), &AttributeType::Int).is_err());
+        assert_eq!(
+            coerce(
+                &Uuid(crate::uuid::Uuid::from_bytes([0; 16])),
+                &AttributeType::Int
+            )
+            .unwrap_err()
+            .to_string(),
+            "Cannot cast Uuid to Int: unsupported cast"
+        );
+    }
+
+    #[test]
+    fn cast_numeric_test() {
+        use AttributeValue::*;
+        assert_eq!(
+            cast(&varchar(" 42 "), &AttributeType::Int).unwrap(),
+            Int(42)
+        );
+        assert_eq!(cast(&Real(2.5), &AttributeType::Int).unwrap(), Int(3));
+        assert_eq!(cast(&Real(-2.5), &AttributeType::Int).unwrap(), Int(-3));
+        assert_eq!(
+            cast(&varchar("1e3"), &AttributeType::Real).unwrap(),
+            Real(1000.0)
+        );
+
+        assert_eq!(
+            cast(&varchar("abc"), &AttributeType::Int)
+                .unwrap_err()
+                .to_string(),
+            "Cannot cast Varchar to Int: \"abc\" is not an integer"
+        );
+        assert_eq!(
+            cast(&varchar("99999999999"), &AttributeType::Int)
+                .unwrap_err()
+                .to_string(),
+            "Cannot cast Varchar to Int: \"99999999999\" is out of range"
+        );
+        assert!(cast(&Real(3e10), &AttributeType::Int).is_err());
+        assert!(cast(&Real(f64::NAN), &AttributeType::Int).is_err());
+    }
+
+    #[test]
+    fn cast_text_test() {
+        use AttributeValue::*;
+        let uuid_text = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        let uuid = cast(&varchar(uuid_text), &AttributeType::Uuid).unwrap();
+        assert_eq!(
+            cast(&uuid, &AttributeType::Varchar { len: 36 }).unwrap(),
+            varchar(uuid_text)
+        );
+        assert_eq!(
+            cast(&uuid, &AttributeType::Varchar { len: 35 })
+                .unwrap_err()
+                .to_string(),
+            "Cannot cast Uuid to Varchar(35): value is 36 bytes, longer than the max of 35"
+        );
+        assert_eq!(
+            cast(&Real(1.5), &AttributeType::Char { len: 4 }).unwrap(),
+            Char("1.5".to_string())
+        );
+        assert!(cast(&Varbinary(vec![0xff]), &AttributeType::Varchar { len: 4 }).is_err());
+        assert_eq!(
+            cast(&varchar("hi"), &AttributeType::Blob).unwrap(),
+            Blob(b"hi".to_vec())
+        );
+
+        // Json text is validated
+        let doc = cast(&varchar(r#"{"a": [1, 2]}"#), &AttributeType::Json).unwrap();
+        assert!(cast(&varchar("{"), &AttributeType::Json).is_err());
+        let array_type = AttributeType::Array(Box::new(AttributeType::Int));
+        let path = "$.a".parse().unwrap();
+        let elems = match &doc {
+            Json(doc) => doc.extract(&path).unwrap(),
+            _ => panic!(),
+        };
+        assert_eq!(
+            cast(&elems, &array_type).unwrap(),
+            Array(vec![Some(Int(1)), Some(Int(2))])
+        );
+        assert_eq!(
+            cast(&Array(vec![Some(Int(1)), None]), &AttributeType::Json).unwrap(),
+            Json(r#"[1, null]"#.parse().unwrap())
+        );
+    }
+}
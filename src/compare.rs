@@ -0,0 +1,639 @@
+// Comparison
+//
+// Ordering, equality and hashing for AttributeValues, shared by sorting,
+// grouping and index code.
+//
+// All values are totally ordered:
+// - Ints and Reals compare by numeric value, so Int(1) == Real(1.0).
+// - NaN equals itself and sorts after every other number. -0.0 equals 0.0.
+// - Varchars compare using a Collation. The Ord impl uses Collation::Binary.
+// - Chars compare using blank-padding semantics.
+// - Varbinary, Blob and Uuid values compare byte-wise.
+// - Json values compare by type (null, bool, number, string, array, object),
+//   then by value. Object members compare in key order, so the order they
+//   were written in doesn't matter.
+// - Arrays compare element-wise, with null elements first.
+// - Values of different types are ordered by type, with Null first.
+//
+// Hashing agrees with equality, so values that compare equal hash the same.
+//
+// SQL comparisons use three-valued logic instead: comparing with NULL gives
+// Truth::Unknown.
+
+use crate::attribute::*;
+use crate::error::Result;
+use crate::json::Json;
+use crate::record_ref::ValueRef;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+/// Determines how Varchar values compare.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Default)]
+pub enum Collation {
+    /// Byte-wise comparison of the UTF-8 encoding
+    #[default]
+    Binary,
+    /// Compares the lower case forms of the strings
+    CaseInsensitive,
+}
+
+/// Compare two values, using collation for Varchars.
+pub fn compare(a: &AttributeValue, b: &AttributeValue, collation: Collation) -> Ordering {
+    use AttributeValue::*;
+    match (a, b) {
+        (Null, Null) => Ordering::Equal,
+        (Int(a), Int(b)) => a.cmp(b),
+        (Int(a), Real(b)) => cmp_int_real(*a as i64, *b),
+        (Real(a), Int(b)) => cmp_int_real(*b as i64, *a).reverse(),
+        (Real(a), Real(b)) => cmp_real(*a, *b),
+        (Varchar(a), Varchar(b)) => cmp_str(a, b, collation),
+        (Char(a), Char(b)) => char_cmp(a, b),
+        (Varbinary(a), Varbinary(b)) => a.cmp(b),
+        (Blob(a), Blob(b)) => a.cmp(b),
+        (Uuid(a), Uuid(b)) => a.cmp(b),
+        (Json(a), Json(b)) => cmp_json(a, b),
+        (Array(a), Array(b)) => {
+            for (a, b) in a.iter().zip(b.iter()) {
+                let ord = match (a, b) {
+                    (Some(a), Some(b)) => compare(a, b, collation),
+                    (a, b) => a.is_some().cmp(&b.is_some()),
+                };
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            a.len().cmp(&b.len())
+        }
+        (a, b) => type_rank(a).cmp(&type_rank(b)),
+    }
+}
+
+/// Compare a value borrowed from a record with an owned value, consistently
+/// with compare. Strings and bytes are compared in place. Fails for spilled
+/// values.
+pub fn compare_ref(a: &ValueRef, b: &AttributeValue, collation: Collation) -> Result<Ordering> {
+    use AttributeValue::*;
+    Ok(match (a, b) {
+        (ValueRef::Varchar(a), Varchar(b)) => cmp_str(a, b, collation),
+        (ValueRef::Char(a), Char(b)) => char_cmp(a, b),
+        (ValueRef::Varbinary(a), Varbinary(b)) => (*a).cmp(b.as_slice()),
+        (ValueRef::Blob(a), Blob(b)) => (*a).cmp(b.as_slice()),
+        (ValueRef::Null, b) => compare(&Null, b, collation),
+        (ValueRef::Int(a), b) => compare(&Int(*a), b, collation),
+        (ValueRef::Real(a), b) => compare(&Real(*a), b, collation),
+        (ValueRef::Uuid(a), b) => compare(&Uuid(*a), b, collation),
+        (ValueRef::Value(a), b) => compare(a, b, collation),
+        // Values of different types compare by type, and empty values don't
+        // allocate
+        (ValueRef::Varchar(_), b) => type_rank(&Varchar(String::new())).cmp(&type_rank(b)),
+        (ValueRef::Char(_), b) => type_rank(&Char(String::new())).cmp(&type_rank(b)),
+        (ValueRef::Varbinary(_), b) => type_rank(&Varbinary(vec![])).cmp(&type_rank(b)),
+        (ValueRef::Blob(_), b) => type_rank(&Blob(vec![])).cmp(&type_rank(b)),
+        (a, b) => compare(&a.to_value()?, b, collation),
+    })
+}
+
+/// Feed a value into state, consistently with compare using the same collation.
+pub fn hash_value<H: Hasher>(val: &AttributeValue, collation: Collation, state: &mut H) {
+    use AttributeValue::*;
+    type_rank(val).hash(state);
+    match val {
+        Null => {}
+        Int(val) => hash_int(*val as i64, state),
+        Real(val) => hash_real(*val, state),
+        Varchar(val) => match collation {
+            Collation::Binary => val.hash(state),
+            // Lower case each char the same way cmp_str does
+            Collation::CaseInsensitive => {
+                val.chars()
+                    .flat_map(char::to_lowercase)
+                    .for_each(|c| c.hash(state));
+                0xff_u8.hash(state);
+            }
+        },
+        // Trailing blanks don't affect equality
+        Char(val) => val.trim_end_matches(' ').hash(state),
+        Varbinary(val) => val.hash(state),
+        Blob(val) => val.hash(state),
+        Uuid(val) => val.hash(state),
+        Json(val) => hash_json(val, state),
+        Array(elems) => {
+            elems.len().hash(state);
+            for elem in elems.iter() {
+                match elem {
+                    Some(elem) => {
+                        1_u8.hash(state);
+                        hash_value(elem, collation, state);
+                    }
+                    None => 0_u8.hash(state),
+                }
+            }
+        }
+    }
+}
+
+// Ints and Reals share a rank so they compare by value
+fn type_rank(val: &AttributeValue) -> u8 {
+    use AttributeValue::*;
+    match val {
+        Null => 0,
+        Int(_) | Real(_) => 1,
+        Varchar(_) => 2,
+        Char(_) => 3,
+        Varbinary(_) => 4,
+        Blob(_) => 5,
+        Uuid(_) => 6,
+        Json(_) => 7,
+        Array(_) => 8,
+    }
+}
+
+fn cmp_str(a: &str, b: &str, collation: Collation) -> Ordering {
+    match collation {
+        Collation::Binary => a.cmp(b),
+        Collation::CaseInsensitive => a
+            .chars()
+            .flat_map(char::to_lowercase)
+            .cmp(b.chars().flat_map(char::to_lowercase)),
+    }
+}
+
+fn cmp_real(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        // Neither is NaN, so they're ordered. This also treats -0.0 as 0.0.
+        (false, false) => a.partial_cmp(&b).unwrap(),
+    }
+}
+
+/// Compare an integer with a real exactly, without rounding the integer.
+fn cmp_int_real(a: i64, b: f64) -> Ordering {
+    if b.is_nan() {
+        return Ordering::Less;
+    }
+    // 2^63 is exactly representable, and every i64 is below it
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    if b >= LIMIT {
+        return Ordering::Less;
+    }
+    if b < -LIMIT {
+        return Ordering::Greater;
+    }
+    // b is within i64 range, so its integer part converts exactly
+    let b_int = b.trunc() as i64;
+    match a.cmp(&b_int) {
+        Ordering::Equal => 0.0.partial_cmp(&b.fract()).unwrap(),
+        ord => ord,
+    }
+}
+
+fn hash_int<H: Hasher>(val: i64, state: &mut H) {
+    val.hash(state);
+}
+
+fn hash_real<H: Hasher>(val: f64, state: &mut H) {
+    // Integral reals must hash like the equal int
+    if val.fract() == 0.0 && val >= i64::MIN as f64 && val < i64::MAX as f64 {
+        hash_int(val as i64, state);
+    } else if val.is_nan() {
+        f64::NAN.to_bits().hash(state);
+    } else {
+        val.to_bits().hash(state);
+    }
+}
+
+fn json_rank(val: &Json) -> u8 {
+    match val {
+        Json::Null => 0,
+        Json::Bool(_) => 1,
+        Json::Int(_) | Json::Real(_) => 2,
+        Json::String(_) => 3,
+        Json::Array(_) => 4,
+        Json::Object(_) => 5,
+    }
+}
+
+fn cmp_json(a: &Json, b: &Json) -> Ordering {
+    match (a, b) {
+        (Json::Bool(a), Json::Bool(b)) => a.cmp(b),
+        (Json::Int(a), Json::Int(b)) => a.cmp(b),
+        (Json::Int(a), Json::Real(b)) => cmp_int_real(*a, *b),
+        (Json::Real(a), Json::Int(b)) => cmp_int_real(*b, *a).reverse(),
+        (Json::Real(a), Json::Real(b)) => cmp_real(*a, *b),
+        (Json::String(a), Json::String(b)) => a.cmp(b),
+        (Json::Array(a), Json::Array(b)) => {
+            for (a, b) in a.iter().zip(b.iter()) {
+                let ord = cmp_json(a, b);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            a.len().cmp(&b.len())
+        }
+        (Json::Object(a), Json::Object(b)) => {
+            let (a, b) = (sorted_members(a), sorted_members(b));
+            for ((a_key, a_val), (b_key, b_val)) in a.iter().zip(b.iter()) {
+                let ord = a_key.cmp(b_key).then_with(|| cmp_json(a_val, b_val));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            a.len().cmp(&b.len())
+        }
+        (a, b) => json_rank(a).cmp(&json_rank(b)),
+    }
+}
+
+/// Object members ordered by key. Keys are unique, so the order is total.
+fn sorted_members(members: &[(String, Json)]) -> Vec<&(String, Json)> {
+    let mut sorted: Vec<_> = members.iter().collect();
+    sorted.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    sorted
+}
+
+fn hash_json<H: Hasher>(val: &Json, state: &mut H) {
+    json_rank(val).hash(state);
+    match val {
+        Json::Null => {}
+        Json::Bool(val) => val.hash(state),
+        Json::Int(val) => hash_int(*val, state),
+        Json::Real(val) => hash_real(*val, state),
+        Json::String(val) => val.hash(state),
+        Json::Array(elems) => {
+            elems.len().hash(state);
+            elems.iter().for_each(|elem| hash_json(elem, state));
+        }
+        Json::Object(members) => {
+            members.len().hash(state);
+            for (key, val) in sorted_members(members).iter() {
+                key.hash(state);
+                hash_json(val, state);
+            }
+        }
+    }
+}
+
+impl PartialEq for AttributeValue {
+    fn eq(&self, other: &Self) -> bool {
+        compare(self, other, Collation::Binary) == Ordering::Equal
+    }
+}
+
+impl Eq for AttributeValue {}
+
+impl PartialOrd for AttributeValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for AttributeValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(self, other, Collation::Binary)
+    }
+}
+
+impl Hash for AttributeValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_value(self, Collation::Binary, state)
+    }
+}
+
+/// A value paired with the collation to compare it with.
+/// Useful as a sort or map key when Varchars shouldn't compare byte-wise.
+#[derive(Debug, Clone)]
+pub struct CollatedValue {
+    pub value: AttributeValue,
+    pub collation: Collation,
+}
+
+impl PartialEq for CollatedValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for CollatedValue {}
+
+impl PartialOrd for CollatedValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CollatedValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(&self.value, &other.value, self.collation)
+    }
+}
+
+impl Hash for CollatedValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_value(&self.value, self.collation, state)
+    }
+}
+
+/// Result of a SQL comparison under three-valued logic.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Truth {
+    True,
+    False,
+    Unknown,
+}
+
+impl Truth {
+    pub fn and(self, other: Truth) -> Truth {
+        match (self, other) {
+            (Truth::False, _) | (_, Truth::False) => Truth::False,
+            (Truth::True, Truth::True) => Truth::True,
+            _ => Truth::Unknown,
+        }
+    }
+
+    pub fn or(self, other: Truth) -> Truth {
+        match (self, other) {
+            (Truth::True, _) | (_, Truth::True) => Truth::True,
+            (Truth::False, Truth::False) => Truth::False,
+            _ => Truth::Unknown,
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Truth {
+        match self {
+            Truth::True => Truth::False,
+            Truth::False => Truth::True,
+            Truth::Unknown => Truth::Unknown,
+        }
+    }
+
+    /// True only for Truth::True. A WHERE clause keeps a row only if this holds.
+    pub fn is_true(self) -> bool {
+        self == Truth::True
+    }
+}
+
+impl From<bool> for Truth {
+    fn from(val: bool) -> Self {
+        if val {
+            Truth::True
+        } else {
+            Truth::False
+        }
+    }
+}
+
+/// A SQL comparison operator.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum CompOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompOp {
+    /// Evaluate a op b, where None or Some(Null) is NULL.
+    /// Any comparison involving NULL is Unknown.
+    pub fn eval(
+        self,
+        a: Option<&AttributeValue>,
+        b: Option<&AttributeValue>,
+        collation: Collation,
+    ) -> Truth {
+        match (a, b) {
+            (Some(AttributeValue::Null), _) | (_, Some(AttributeValue::Null)) => Truth::Unknown,
+            (Some(a), Some(b)) => self.test(compare(a, b, collation)).into(),
+            _ => Truth::Unknown,
+        }
+    }
+
+    /// Evaluate a op b for a value borrowed from a record, like eval.
+    /// Fails if a is spilled.
+    pub fn eval_ref(
+        self,
+        a: &ValueRef,
+        b: Option<&AttributeValue>,
+        collation: Collation,
+    ) -> Result<Truth> {
+        match (a, b) {
+            (ValueRef::Null, _) | (_, Some(AttributeValue::Null)) | (_, None) => Ok(Truth::Unknown),
+            (a, Some(b)) => Ok(self.test(compare_ref(a, b, collation)?).into()),
+        }
+    }
+
+    /// True if an ordering of a relative to b satisfies a op b.
+    pub fn test(self, ord: Ordering) -> bool {
+        match self {
+            CompOp::Eq => ord == Ordering::Equal,
+            CompOp::Ne => ord != Ordering::Equal,
+            CompOp::Lt => ord == Ordering::Less,
+            CompOp::Le => ord != Ordering::Greater,
+            CompOp::Gt => ord == Ordering::Greater,
+            CompOp::Ge => ord != Ordering::Less,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashSet;
+
+    fn hash_of(val: &AttributeValue, collation: Collation) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        hash_value(val, collation, &mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn compare_numeric_test() {
+        use AttributeValue::*;
+        let mut vals = vec![
+            Real(f64::NAN),
+            Int(3),
+            Real(2.5),
+            Real(f64::NEG_INFINITY),
+            Int(-1),
+            Real(f64::INFINITY),
+        ];
+        vals.sort();
+        assert_eq!(
+            vals,
+            vec![
+                Real(f64::NEG_INFINITY),
+                Int(-1),
+                Real(2.5),
+                Int(3),
+                Real(f64::INFINITY),
+                Real(f64::NAN),
+            ]
+        );
+
+        assert_eq!(Real(f64::NAN), Real(f64::NAN));
+        assert_eq!(Real(-0.0), Real(0.0));
+        assert_eq!(Int(1), Real(1.0));
+        assert!(Int(1) < Real(1.5));
+        assert!(Real(0.5) < Int(1));
+        assert_eq!(cmp_int_real(i64::MAX, 9.3e18), Ordering::Less);
+        assert_eq!(cmp_int_real(i64::MAX, i64::MAX as f64), Ordering::Less);
+        assert_eq!(cmp_int_real(i64::MIN, i64::MIN as f64), Ordering::Equal);
+
+        // Equal values hash the same
+        assert_eq!(
+            hash_of(&Int(1), Collation::Binary),
+            hash_of(&Real(1.0), Collation::Binary)
+        );
+        assert_eq!(
+            hash_of(&Real(-0.0), Collation::Binary),
+            hash_of(&Real(0.0), Collation::Binary)
+        );
+        let set: HashSet<AttributeValue> =
+            vec![Real(f64::NAN), Real(-f64::NAN), Int(0), Real(-0.0)]
+                .into_iter()
+                .collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn compare_string_test() {
+        use AttributeValue::*;
+        let a = Varchar("apple".to_string());
+        let b = Varchar("Banana".to_string());
+        assert_eq!(compare(&a, &b, Collation::Binary), Ordering::Greater);
+        assert_eq!(compare(&a, &b, Collation::CaseInsensitive), Ordering::Less);
+
+        let upper = Varchar("ÉCOLE".to_string());
+        let lower = Varchar("école".to_string());
+        assert_ne!(upper, lower);
+        assert_eq!(
+            compare(&upper, &lower, Collation::CaseInsensitive),
+            Ordering::Equal
+        );
+        assert_eq!(
+            hash_of(&upper, Collation::CaseInsensitive),
+            hash_of(&lower, Collation::CaseInsensitive)
+        );
+        let set: HashSet<CollatedValue> = vec![upper, lower, b]
+            .into_iter()
+            .map(|value| CollatedValue {
+                value,
+                collation: Collation::CaseInsensitive,
+            })
+            .collect();
+        assert_eq!(set.len(), 2);
+
+        // Blank padding for chars
+        let c = Char("ab".to_string());
+        let padded = Char("ab  ".to_string());
+        assert_eq!(c, padded);
+        assert_eq!(
+            hash_of(&c, Collation::Binary),
+            hash_of(&padded, Collation::Binary)
+        );
+
+        // Different types order by type rather than by value
+        assert!(Int(100) < Varchar("".to_string()));
+        assert_ne!(Varchar("ab".to_string()), c);
+    }
+
+    #[test]
+    fn compare_nested_test() {
+        use AttributeValue::*;
+        let a = Array(vec![Some(Int(1)), None]);
+        let b = Array(vec![Some(Int(1)), Some(Int(0))]);
+        let c = Array(vec![Some(Int(1))]);
+        assert!(a < b);
+        assert!(c < a);
+
+        let x: crate::json::Json = r#"{"a": [1, null]}"#.parse().unwrap();
+        let y: crate::json::Json = r#"{"a": [1.0, null]}"#.parse().unwrap();
+        let z: crate::json::Json = r#"{"a": [1, false]}"#.parse().unwrap();
+        assert_eq!(Json(x.clone()), Json(y.clone()));
+        assert_eq!(
+            hash_of(&Json(x.clone()), Collation::Binary),
+            hash_of(&Json(y), Collation::Binary)
+        );
+        assert!(Json(x) < Json(z));
+
+        // Key order doesn't matter
+        let x: crate::json::Json = r#"{"a": 1, "b": {"c": 2, "d": 3}}"#.parse().unwrap();
+        let y: crate::json::Json = r#"{"b": {"d": 3, "c": 2.0}, "a": 1}"#.parse().unwrap();
+        let z: crate::json::Json = r#"{"b": {"d": 3, "c": 2}, "a": 0}"#.parse().unwrap();
+        assert_eq!(Json(x.clone()), Json(y.clone()));
+        assert_eq!(
+            hash_of(&Json(x.clone()), Collation::Binary),
+            hash_of(&Json(y), Collation::Binary)
+        );
+        assert!(Json(z) < Json(x));
+    }
+
+    #[test]
+    fn compare_ref_test() {
+        use AttributeValue::*;
+        let vals = vec![
+            Null,
+            Int(1),
+            Real(1.5),
+            Varchar("apple".to_string()),
+            Varchar("Banana".to_string()),
+            Char("ab".to_string()),
+            Varbinary(vec![1, 2]),
+            Blob(vec![3]),
+            Array(vec![Some(Int(1)), None]),
+        ];
+        for a in vals.iter() {
+            for b in vals.iter() {
+                for collation in [Collation::Binary, Collation::CaseInsensitive].iter() {
+                    assert_eq!(
+                        compare_ref(&ValueRef::from(a), b, *collation).unwrap(),
+                        compare(a, b, *collation)
+                    );
+                }
+            }
+        }
+        assert!(compare_ref(&ValueRef::Spilled, &Int(1), Collation::Binary).is_err());
+
+        let c = Collation::Binary;
+        assert_eq!(
+            CompOp::Eq
+                .eval_ref(&ValueRef::Char("ab"), Some(&Char("ab  ".to_string())), c)
+                .unwrap(),
+            Truth::True
+        );
+        assert_eq!(
+            CompOp::Eq
+                .eval_ref(&ValueRef::Null, Some(&Null), c)
+                .unwrap(),
+            Truth::Unknown
+        );
+    }
+
+    #[test]
+    fn three_valued_logic_test() {
+        use AttributeValue::*;
+        let one = Int(1);
+        let two = Int(2);
+        let c = Collation::Binary;
+        assert_eq!(CompOp::Lt.eval(Some(&one), Some(&two), c), Truth::True);
+        assert_eq!(CompOp::Ge.eval(Some(&one), Some(&two), c), Truth::False);
+        assert_eq!(CompOp::Eq.eval(Some(&one), None, c), Truth::Unknown);
+        assert_eq!(CompOp::Ne.eval(None, None, c), Truth::Unknown);
+        assert_eq!(CompOp::Eq.eval(Some(&Null), Some(&Null), c), Truth::Unknown);
+        // As a value, Null equals itself and sorts first
+        assert_eq!(Null, Null);
+        assert!(Null < one);
+
+        assert_eq!(Truth::Unknown.and(Truth::False), Truth::False);
+        assert_eq!(Truth::Unknown.and(Truth::True), Truth::Unknown);
+        assert_eq!(Truth::Unknown.or(Truth::True), Truth::True);
+        assert_eq!(Truth::Unknown.or(Truth::False), Truth::Unknown);
+        assert_eq!(Truth::Unknown.not(), Truth::Unknown);
+        assert!(!Truth::Unknown.is_true());
+    }
+}
@@ -0,0 +1,183 @@
+// Errors
+//
+// Every fallible API in the crate returns this Error, so callers can match on
+// what went wrong instead of parsing io::Error messages. Invalid data read
+// from a file is always reported as Corruption rather than a panic.
+
+use crate::record_based_file_mgr::RecordId;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The RecordId's page or slot doesn't exist
+    RecordNotFound(RecordId),
+    /// The RecordId's record was deleted
+    RecordDeleted(RecordId),
+    /// A page past the end of the file
+    PageNotFound {
+        page: u64,
+        num_pages: u64,
+    },
+    /// A value doesn't have its attribute's type
+    TypeMismatch {
+        attribute: String,
+        message: String,
+    },
+    /// A Varchar value is longer than its attribute's max len
+    VarcharOverflow {
+        attribute: String,
+        len: usize,
+        max: u32,
+    },
+    /// NULL for a NOT NULL attribute, or no value for a NOT NULL attribute
+    /// without a default
+    NotNullViolation {
+        attribute: String,
+    },
+    /// A name that isn't an attribute in the schema
+    UnknownAttribute {
+        attribute: String,
+    },
+    /// Any other invalid argument, e.g. a schema mismatch or a value that
+    /// can't be cast
+    InvalidInput(String),
+    /// The file isn't a record file in a format this crate reads, e.g. it was
+    /// written before the format was versioned
+    UnsupportedFormat(String),
+    /// Invalid data in the file, at page and slot where they're known
+    Corruption {
+        page: Option<u64>,
+        slot: Option<u32>,
+        message: String,
+    },
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub(crate) fn invalid_input<S: Into<String>>(message: S) -> Self {
+        Error::InvalidInput(message.into())
+    }
+
+    /// Corruption at an unknown location, for callers to locate with at.
+    pub(crate) fn corrupt<S: Into<String>>(message: S) -> Self {
+        Error::Corruption {
+            page: None,
+            slot: None,
+            message: message.into(),
+        }
+    }
+
+    pub(crate) fn corrupt_page<S: Into<String>>(page: u64, message: S) -> Self {
+        Error::Corruption {
+            page: Some(page),
+            slot: None,
+            message: message.into(),
+        }
+    }
+
+    /// Fill in the location of a Corruption error that doesn't have one.
+    /// Other errors are returned unchanged.
+    pub(crate) fn at(self, at_page: u64, at_slot: Option<u32>) -> Self {
+        match self {
+            Error::Corruption {
+                page: None,
+                slot: None,
+                message,
+            } => Error::Corruption {
+                page: Some(at_page),
+                slot: at_slot,
+                message,
+            },
+            e => e,
+        }
+    }
+
+    /// Locate a Corruption error in the record stored at rid.
+    pub(crate) fn at_record(self, rid: &RecordId) -> Self {
+        self.at(rid.page_num as u64, Some(rid.slot_num))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::RecordNotFound(rid) => {
+                write!(f, "Record {}:{} does not exist", rid.page_num, rid.slot_num)
+            }
+            Error::RecordDeleted(rid) => {
+                write!(f, "Record {}:{} was deleted", rid.page_num, rid.slot_num)
+            }
+            Error::PageNotFound { page, num_pages } => write!(
+                f,
+                "Page {} does not exist. Total pages: {}",
+                page, num_pages
+            ),
+            Error::TypeMismatch { attribute, message } => {
+                write!(f, "Attribute {}: {}", attribute, message)
+            }
+            Error::VarcharOverflow {
+                attribute,
+                len,
+                max,
+            } => write!(
+                f,
+                "Attribute {}: value of length {} is longer than the max len {}",
+                attribute, len, max
+            ),
+            Error::NotNullViolation { attribute } => {
+                write!(f, "Attribute {} can't be NULL", attribute)
+            }
+            Error::UnknownAttribute { attribute } => write!(f, "Unknown attribute {}", attribute),
+            Error::InvalidInput(message) => write!(f, "{}", message),
+            Error::UnsupportedFormat(message) => write!(f, "Unsupported file format: {}", message),
+            Error::Corruption {
+                page,
+                slot,
+                message,
+            } => {
+                write!(f, "Corrupted data")?;
+                if let Some(page) = page {
+                    write!(f, " in page {}", page)?;
+                }
+                if let Some(slot) = slot {
+                    write!(f, ", slot {}", slot)?;
+                }
+                write!(f, ": {}", message)
+            }
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// For callers that still work in io::Error.
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        let kind = match e {
+            Error::Io(e) => return e,
+            Error::RecordNotFound(_) | Error::RecordDeleted(_) | Error::PageNotFound { .. } => {
+                io::ErrorKind::NotFound
+            }
+            Error::Corruption { .. } | Error::UnsupportedFormat(_) => io::ErrorKind::InvalidData,
+            _ => io::ErrorKind::InvalidInput,
+        };
+        io::Error::new(kind, e)
+    }
+}
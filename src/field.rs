@@ -0,0 +1,373 @@
+// Field encoding
+//
+// Converts single attribute values to and from the bytes stored for them in a
+// record. The length of every field is either fixed by its type or given by
+// its offset header.
+// Int: 4 bytes
+// Real: 8 bytes
+// Varchar, Varbinary, Blob: the value's bytes
+// Char: len bytes, blank padded
+// Uuid: 16 bytes
+// Json: the binary form from the json module
+// Array: Num_Elements: 4 byte unsigned int
+//        Null_Bitmap: Variable length bitmap, byte length is ceil(num_elements / 8)
+//        Offset_Headers: 4 bytes for each non-null element, pointing to the END of the element
+//        Data: each non-null element, encoded as above
+//
+// Arrays mirror the record format, but with wider headers so they aren't
+// limited to the size of a page.
+
+use crate::attribute::*;
+use crate::bitmap::*;
+use crate::error::{Error, Result};
+use crate::json::*;
+use crate::record_ref::ValueRef;
+use crate::uuid::*;
+use std::convert::TryInto;
+
+const ARRAY_COUNT_SIZE: usize = 4;
+const ARRAY_OFFSET_SIZE: usize = 4;
+
+/// True if AttributeValue matches the expected type based on AttributeType
+pub fn type_matches_value(attr_type: &AttributeType, attr_val: &AttributeValue) -> bool {
+    match attr_type {
+        AttributeType::Int => {
+            matches!(attr_val, AttributeValue::Int(_))
+        }
+        AttributeType::Real => {
+            matches!(attr_val, AttributeValue::Real(_))
+        }
+        AttributeType::Varchar { len: max } => match attr_val {
+            AttributeValue::Varchar(s) => s.len() <= *max as usize,
+            _ => false,
+        },
+        AttributeType::Varbinary { len: max } => match attr_val {
+            AttributeValue::Varbinary(b) => b.len() <= *max as usize,
+            _ => false,
+        },
+        AttributeType::Blob => {
+            matches!(attr_val, AttributeValue::Blob(b) if b.len() <= u32::MAX as usize)
+        }
+        // Trailing blanks beyond len are dropped rather than rejected
+        AttributeType::Char { len } => match attr_val {
+            AttributeValue::Char(s) => s.trim_end_matches(' ').len() <= *len as usize,
+            _ => false,
+        },
+        AttributeType::Uuid => {
+            matches!(attr_val, AttributeValue::Uuid(_))
+        }
+        // JSON has no text for NaN or infinity
+        AttributeType::Json => {
+            matches!(attr_val, AttributeValue::Json(doc) if doc.is_finite())
+        }
+        // Null elements are None rather than Some(Null)
+        AttributeType::Array(elem_type) => match attr_val {
+            AttributeValue::Array(elems) => elems
+                .iter()
+                .flatten()
+                .all(|elem| type_matches_value(elem_type, elem)),
+            _ => false,
+        },
+    }
+}
+
+/// Length of the encoded value in bytes.
+pub fn encoded_len(attr_type: &AttributeType, attr_val: &AttributeValue) -> Result<usize> {
+    Ok(match (attr_type, attr_val) {
+        // All ints are 4 bytes
+        (_, AttributeValue::Int(_)) => 4,
+        // All reals are 8 bytes
+        (_, AttributeValue::Real(_)) => 8,
+        // Get the length of the actual value
+        (_, AttributeValue::Varchar(val)) => val.len(),
+        (_, AttributeValue::Varbinary(val)) => val.len(),
+        (_, AttributeValue::Blob(val)) => val.len(),
+        // Chars are always padded to their full length
+        (AttributeType::Char { len }, _) => *len as usize,
+        (_, AttributeValue::Uuid(_)) => UUID_SIZE,
+        _ => encode(attr_type, attr_val)?.len(),
+    })
+}
+
+/// Encode a value of the given type.
+/// The value should already have been checked with type_matches_value, but
+/// values that can't be encoded as the type are still an error rather than a
+/// panic.
+pub fn encode(attr_type: &AttributeType, attr_val: &AttributeValue) -> Result<Vec<u8>> {
+    let mismatch =
+        || Error::invalid_input(format!("Can't encode {:?} as {:?}", attr_val, attr_type));
+    Ok(match attr_val {
+        // Nulls are only stored in the null bitmap
+        AttributeValue::Null => return Err(mismatch()),
+        AttributeValue::Int(val) => val.to_le_bytes().to_vec(),
+        AttributeValue::Real(val) => val.to_le_bytes().to_vec(),
+        // Inverse is from_utf8 for reading
+        AttributeValue::Varchar(val) => val.as_bytes().to_vec(),
+        AttributeValue::Varbinary(val) => val.clone(),
+        AttributeValue::Blob(val) => val.clone(),
+        // Like ints and reals, a char's size is known from the schema.
+        // It still gets an offset header like every other non-null field,
+        // so fields are found the same way whatever their type.
+        AttributeValue::Char(val) => match attr_type {
+            AttributeType::Char { len } => pad_char(val, *len as usize),
+            _ => return Err(mismatch()),
+        },
+        AttributeValue::Uuid(val) => val.as_bytes().to_vec(),
+        AttributeValue::Json(val) => val.to_bytes(),
+        AttributeValue::Array(elems) => match attr_type {
+            AttributeType::Array(elem_type) => encode_array(elem_type, elems)?,
+            _ => return Err(mismatch()),
+        },
+    })
+}
+
+/// Pad a char value with blanks to exactly len bytes.
+fn pad_char(val: &str, len: usize) -> Vec<u8> {
+    let mut bytes = val.trim_end_matches(' ').as_bytes().to_vec();
+    bytes.resize(len, b' ');
+    bytes
+}
+
+fn encode_array(elem_type: &AttributeType, elems: &[Option<AttributeValue>]) -> Result<Vec<u8>> {
+    let mut bmp = Bitmap::new(elems.len());
+    let mut offset_hdrs = vec![];
+    let mut data = vec![];
+    for (i, elem) in elems.iter().enumerate() {
+        if let Some(elem) = elem {
+            bmp.set(i);
+            data.extend_from_slice(&encode(elem_type, elem)?);
+            offset_hdrs.push(data.len());
+        }
+    }
+
+    let bmp = bmp.into_bytes();
+    // Offsets are from the start of the array, like a record's
+    let data_start = ARRAY_COUNT_SIZE + bmp.len() + offset_hdrs.len() * ARRAY_OFFSET_SIZE;
+    let mut bytes = Vec::with_capacity(data_start + data.len());
+    bytes.extend_from_slice(&(elems.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&bmp);
+    for offset in offset_hdrs.iter() {
+        bytes.extend_from_slice(&((data_start + offset) as u32).to_le_bytes());
+    }
+    bytes.extend_from_slice(&data);
+    Ok(bytes)
+}
+
+/// Decode a value of the given type from its encoded bytes.
+pub fn decode(attr_type: &AttributeType, bytes: &[u8]) -> Result<AttributeValue> {
+    Ok(match attr_type {
+        AttributeType::Json => AttributeValue::Json(Json::from_bytes(bytes)?),
+        AttributeType::Array(elem_type) => {
+            let hdr = ArrayHeader::parse(bytes)?;
+            let mut elems = Vec::with_capacity(hdr.num_elements);
+            for i in 0..hdr.num_elements {
+                let elem = match hdr.element_bytes(bytes, i)? {
+                    Some(elem_bytes) => Some(decode(elem_type, elem_bytes)?),
+                    None => None,
+                };
+                elems.push(elem);
+            }
+            if hdr.data_end(bytes)? != bytes.len() {
+                return Err(Error::corrupt("Invalid array"));
+            }
+            AttributeValue::Array(elems)
+        }
+        _ => decode_ref(attr_type, bytes)?.to_value()?,
+    })
+}
+
+/// Decode a value of the given type without copying it out of bytes.
+/// Json and array values are checked when they're converted with to_value.
+pub fn decode_ref<'a>(attr_type: &'a AttributeType, bytes: &'a [u8]) -> Result<ValueRef<'a>> {
+    let fixed_len = |len: usize| {
+        if bytes.len() != len {
+            Err(Error::corrupt("Invalid field length"))
+        } else {
+            Ok(bytes)
+        }
+    };
+    Ok(match attr_type {
+        AttributeType::Int => ValueRef::Int(i32::from_le_bytes(fixed_len(4)?.try_into().unwrap())),
+        AttributeType::Real => {
+            ValueRef::Real(f64::from_le_bytes(fixed_len(8)?.try_into().unwrap()))
+        }
+        AttributeType::Varchar { len: max } => {
+            if bytes.len() > *max as usize {
+                return Err(Error::corrupt("Varchar larger than max len"));
+            }
+            let val =
+                std::str::from_utf8(bytes).map_err(|_| Error::corrupt("Varchar is not UTF-8"))?;
+            ValueRef::Varchar(val)
+        }
+        AttributeType::Varbinary { len: max } => {
+            if bytes.len() > *max as usize {
+                return Err(Error::corrupt("Varbinary larger than max len"));
+            }
+            ValueRef::Varbinary(bytes)
+        }
+        AttributeType::Blob => ValueRef::Blob(bytes),
+        AttributeType::Char { len } => {
+            let val = std::str::from_utf8(fixed_len(*len as usize)?)
+                .map_err(|_| Error::corrupt("Char is not UTF-8"))?;
+            // Trailing pad bytes aren't part of the value
+            ValueRef::Char(val.trim_end_matches(' '))
+        }
+        AttributeType::Uuid => {
+            ValueRef::Uuid(Uuid::from_bytes(fixed_len(UUID_SIZE)?.try_into().unwrap()))
+        }
+        AttributeType::Json => ValueRef::Json(bytes),
+        AttributeType::Array(_) => ValueRef::Array(attr_type, bytes),
+    })
+}
+
+/// Number of elements in an encoded array.
+pub fn array_len(bytes: &[u8]) -> Result<usize> {
+    Ok(ArrayHeader::parse(bytes)?.num_elements)
+}
+
+/// Decode the element at idx of an encoded array, without decoding the other
+/// elements. Returns None if the element is null.
+pub fn array_element(
+    elem_type: &AttributeType,
+    bytes: &[u8],
+    idx: usize,
+) -> Result<Option<AttributeValue>> {
+    let hdr = ArrayHeader::parse(bytes)?;
+    if idx >= hdr.num_elements {
+        return Err(Error::invalid_input("Array index out of bounds"));
+    }
+    match hdr.element_bytes(bytes, idx)? {
+        Some(elem_bytes) => Ok(Some(decode(elem_type, elem_bytes)?)),
+        None => Ok(None),
+    }
+}
+
+struct ArrayHeader {
+    num_elements: usize,
+    bmp: Bitmap,
+    // Offset of the first offset header
+    offset_hdrs_start: usize,
+    // Offset of the first element
+    data_start: usize,
+}
+
+impl ArrayHeader {
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        let invalid = || Error::corrupt("Invalid array");
+        let count_bytes = bytes.get(..ARRAY_COUNT_SIZE).ok_or_else(invalid)?;
+        let num_elements = u32::from_le_bytes(count_bytes.try_into().unwrap()) as usize;
+
+        let bmp_len = Bitmap::bmp_size_in_bytes(num_elements);
+        let offset_hdrs_start = ARRAY_COUNT_SIZE + bmp_len;
+        let bmp_bytes = bytes
+            .get(ARRAY_COUNT_SIZE..offset_hdrs_start)
+            .ok_or_else(invalid)?;
+        let bmp = Bitmap::new_with_vec(num_elements, bmp_bytes.to_vec())?;
+
+        let num_valid = bmp.count_ones_before(num_elements)?;
+        let data_start = offset_hdrs_start + num_valid * ARRAY_OFFSET_SIZE;
+        if data_start > bytes.len() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            num_elements,
+            bmp,
+            offset_hdrs_start,
+            data_start,
+        })
+    }
+
+    fn offset_hdr(&self, bytes: &[u8], valid_idx: usize) -> usize {
+        let start = self.offset_hdrs_start + valid_idx * ARRAY_OFFSET_SIZE;
+        u32::from_le_bytes(bytes[start..start + ARRAY_OFFSET_SIZE].try_into().unwrap()) as usize
+    }
+
+    /// Offset of the end of the last element
+    fn data_end(&self, bytes: &[u8]) -> Result<usize> {
+        Ok(match self.bmp.count_ones_before(self.num_elements)? {
+            0 => self.data_start,
+            num_valid => self.offset_hdr(bytes, num_valid - 1),
+        })
+    }
+
+    /// Bytes of the element at idx, or None if it's null
+    fn element_bytes<'a>(&self, bytes: &'a [u8], idx: usize) -> Result<Option<&'a [u8]>> {
+        if !self.bmp.get(idx) {
+            return Ok(None);
+        }
+        // Offset headers point to the end of each element, so an element
+        // starts where the previous non-null element ends
+        let valid_idx = self.bmp.count_ones_before(idx)?;
+        let start = match valid_idx {
+            0 => self.data_start,
+            _ => self.offset_hdr(bytes, valid_idx - 1),
+        };
+        let end = self.offset_hdr(bytes, valid_idx);
+        if start > end || end > bytes.len() {
+            return Err(Error::corrupt("Invalid array offset"));
+        }
+        Ok(Some(&bytes[start..end]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_array_test() {
+        let elem_type = AttributeType::Varchar { len: 10 };
+        let array_type = AttributeType::Array(Box::new(elem_type));
+        let elems = vec![
+            Some(AttributeValue::Varchar("red".to_string())),
+            None,
+            Some(AttributeValue::Varchar("".to_string())),
+            Some(AttributeValue::Varchar("blue".to_string())),
+        ];
+        let val = AttributeValue::Array(elems);
+        assert!(type_matches_value(&array_type, &val));
+        assert!(!type_matches_value(
+            &array_type,
+            &AttributeValue::Array(vec![Some(AttributeValue::Int(1))])
+        ));
+
+        let bytes = encode(&array_type, &val).unwrap();
+        // Count + bitmap + 3 offset headers + data
+        assert_eq!(bytes.len(), 4 + 1 + 3 * 4 + 7);
+        assert_eq!(encoded_len(&array_type, &val).unwrap(), bytes.len());
+        assert_eq!(decode(&array_type, &bytes).unwrap(), val);
+
+        let elem_type = AttributeType::Varchar { len: 10 };
+        assert_eq!(array_len(&bytes).unwrap(), 4);
+        assert_eq!(
+            array_element(&elem_type, &bytes, 3).unwrap(),
+            Some(AttributeValue::Varchar("blue".to_string()))
+        );
+        assert_eq!(array_element(&elem_type, &bytes, 1).unwrap(), None);
+        assert!(array_element(&elem_type, &bytes, 4).is_err());
+
+        // Empty arrays and truncated encodings
+        let empty = AttributeValue::Array(vec![]);
+        assert_eq!(
+            decode(&array_type, &encode(&array_type, &empty).unwrap()).unwrap(),
+            empty
+        );
+        assert!(decode(&array_type, &bytes[..bytes.len() - 1]).is_err());
+        assert!(decode(&array_type, &bytes[..6]).is_err());
+
+        // Values that don't match the type are an error rather than a panic
+        let chars = AttributeValue::Char("US".to_string());
+        assert!(encode(&AttributeType::Int, &chars).is_err());
+        assert!(encode(&AttributeType::Int, &val).is_err());
+        assert!(encode(&elem_type, &AttributeValue::Null).is_err());
+        assert!(encode(
+            &array_type,
+            &AttributeValue::Array(vec![Some(chars.clone())])
+        )
+        .is_err());
+        assert_eq!(
+            encode(&AttributeType::Char { len: 3 }, &chars).unwrap(),
+            b"US "
+        );
+    }
+}
@@ -0,0 +1,234 @@
+// Free space map
+//
+// Records how much free space each page of a record file has, so insert can
+// find a page with room without reading every page. Each page gets a 2 byte
+// entry holding its free space in bytes, so a page with room is never passed
+// over. Pages that don't hold records, and pages past the end of the map,
+// have 0 bytes free.
+//
+// The entries are stored in dedicated pages. The first is at a fixed page
+// number, and each links to the next, which covers the following
+// entries_per_page::<PAGE_SIZE>() pages of the file. Map pages are appended
+// as the file grows.
+//
+// The map can be stale in one direction only: a page may have more free
+// space than its entry says, e.g. after a crash, but callers must check
+// that a page really has room before using it.
+
+use crate::error::{Error, Result};
+use crate::page::*;
+use crate::paged_file::*;
+use crate::record_based_file_mgr::PageKind;
+use serde::{Deserialize, Serialize};
+
+const FSM_HDR_SIZE: usize = 8;
+const FSM_ENTRY_SIZE: usize = 2;
+
+#[derive(Serialize, Deserialize)]
+struct FreeSpaceMapPageHeader {
+    page_kind: PageKind,
+    // Next page of the map, 0 for the last page.
+    // Page 0 is never a map page.
+    next_page: u32,
+}
+
+/// Number of pages whose entry fits in a single map page.
+fn entries_per_page<const PAGE_SIZE: usize>() -> usize {
+    (PAGE_SIZE - FSM_HDR_SIZE) / FSM_ENTRY_SIZE
+}
+
+/// Entry for a page with free_space bytes free. Pages too large for a 2 byte
+/// entry saturate, and have to be checked when they're found.
+fn entry_value(free_space: usize) -> u16 {
+    free_space.min(u16::MAX as usize) as u16
+}
+
+pub struct FreeSpaceMap<const PAGE_SIZE: usize> {
+    // Page numbers of the map pages, in order
+    pages: Vec<u64>,
+}
+
+impl<const PAGE_SIZE: usize> FreeSpaceMap<PAGE_SIZE> {
+    /// Append the first map page to the end of the file.
+    pub fn create(paged_file: &mut PagedFile<PAGE_SIZE>) -> Result<Self> {
+        let page_num = paged_file.num_pages()?;
+        paged_file.append_page(&Self::new_page())?;
+        Ok(Self {
+            pages: vec![page_num],
+        })
+    }
+
+    /// Load the map whose first page is first_page.
+    pub fn open(paged_file: &mut PagedFile<PAGE_SIZE>, first_page: u64) -> Result<Self> {
+        let mut pages = vec![];
+        let mut page = Page::<PAGE_SIZE>::new();
+        let mut page_num = first_page;
+        loop {
+            // The map can't have more pages than the file, so this catches loops
+            if pages.len() as u64 >= paged_file.num_pages()? {
+                return Err(Error::corrupt_page(page_num, "Invalid free space map"));
+            }
+            let hdr = Self::read_map_page(paged_file, page_num, &mut page)?;
+            pages.push(page_num);
+            if hdr.next_page == 0 {
+                return Ok(Self { pages });
+            }
+            page_num = hdr.next_page as u64;
+        }
+    }
+
+    /// Record that page page_num has free_space bytes free.
+    pub fn set(
+        &mut self,
+        paged_file: &mut PagedFile<PAGE_SIZE>,
+        page_num: u64,
+        free_space: usize,
+    ) -> Result<()> {
+        let value = entry_value(free_space);
+        let map_idx = page_num as usize / entries_per_page::<PAGE_SIZE>();
+        if map_idx >= self.pages.len() {
+            // Pages past the end of the map already have 0 bytes free
+            if value == 0 {
+                return Ok(());
+            }
+            while map_idx >= self.pages.len() {
+                self.append_map_page(paged_file)?;
+            }
+        }
+
+        let mut page = Page::<PAGE_SIZE>::new();
+        Self::read_map_page(paged_file, self.pages[map_idx], &mut page)?;
+        let entry = page_num as usize % entries_per_page::<PAGE_SIZE>();
+        let start = FSM_HDR_SIZE + entry * FSM_ENTRY_SIZE;
+        let bytes = &mut page.as_mut_buf()[start..start + FSM_ENTRY_SIZE];
+        if *bytes != value.to_le_bytes() {
+            bytes.copy_from_slice(&value.to_le_bytes());
+            paged_file.write_page(self.pages[map_idx], &page)?;
+        }
+        Ok(())
+    }
+
+    /// Find the first page at or after start that might have required_space
+    /// bytes free. The caller must check the page, and call set to correct
+    /// the map if it doesn't have room.
+    pub fn find(
+        &self,
+        paged_file: &mut PagedFile<PAGE_SIZE>,
+        required_space: usize,
+        start: u64,
+    ) -> Result<Option<u64>> {
+        let required = entry_value(required_space);
+        let per_page = entries_per_page::<PAGE_SIZE>();
+        let mut page = Page::<PAGE_SIZE>::new();
+        let first_map_idx = start as usize / per_page;
+        for (map_idx, map_page_num) in self.pages.iter().enumerate().skip(first_map_idx) {
+            Self::read_map_page(paged_file, *map_page_num, &mut page)?;
+            let bytes = &page.as_buf()[FSM_HDR_SIZE..];
+            let first_entry = if map_idx == first_map_idx {
+                start as usize % per_page
+            } else {
+                0
+            };
+            for entry in first_entry..per_page {
+                let start = entry * FSM_ENTRY_SIZE;
+                let value = u16::from_le_bytes([bytes[start], bytes[start + 1]]);
+                if value >= required {
+                    return Ok(Some((map_idx * per_page + entry) as u64));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Page numbers of the map pages, in order.
+    pub fn pages(&self) -> &[u64] {
+        &self.pages
+    }
+
+    fn new_page() -> Page<PAGE_SIZE> {
+        let mut page = Page::<PAGE_SIZE>::new();
+        let hdr = FreeSpaceMapPageHeader {
+            page_kind: PageKind::FreeSpaceMap,
+            next_page: 0,
+        };
+        bincode::serialize_into(&mut page.as_mut_buf()[..FSM_HDR_SIZE], &hdr).unwrap();
+        page
+    }
+
+    /// Append a map page to the end of the file and link it from the last one.
+    fn append_map_page(&mut self, paged_file: &mut PagedFile<PAGE_SIZE>) -> Result<()> {
+        let page_num = paged_file.num_pages()?;
+        paged_file.append_page(&Self::new_page())?;
+
+        let last_page_num = *self.pages.last().unwrap();
+        let mut page = Page::<PAGE_SIZE>::new();
+        let mut hdr = Self::read_map_page(paged_file, last_page_num, &mut page)?;
+        hdr.next_page = page_num as u32;
+        bincode::serialize_into(&mut page.as_mut_buf()[..FSM_HDR_SIZE], &hdr).unwrap();
+        paged_file.write_page(last_page_num, &page)?;
+        self.pages.push(page_num);
+        Ok(())
+    }
+
+    /// Read a map page and check its header.
+    fn read_map_page(
+        paged_file: &mut PagedFile<PAGE_SIZE>,
+        page_num: u64,
+        page: &mut Page<PAGE_SIZE>,
+    ) -> Result<FreeSpaceMapPageHeader> {
+        let invalid = || Error::corrupt_page(page_num, "Invalid free space map page");
+        // A map that links past the end of the file is corrupt
+        match paged_file.read_page(page_num, page) {
+            Err(Error::PageNotFound { .. }) => return Err(invalid()),
+            result => result?,
+        }
+        let hdr: FreeSpaceMapPageHeader =
+            bincode::deserialize(page.as_buf()).map_err(|_| invalid())?;
+        if hdr.page_kind != PageKind::FreeSpaceMap {
+            return Err(invalid());
+        }
+        Ok(hdr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    type Pf = PagedFile<64>;
+
+    #[test]
+    fn free_space_map_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let mut handle = Pf::create(&file_path).unwrap();
+        // Page 0 is never part of the map
+        handle.append_page(&Page::new()).unwrap();
+        let mut fsm = FreeSpaceMap::create(&mut handle).unwrap();
+        assert_eq!(fsm.pages(), [1]);
+
+        // Free space is recorded to the byte
+        assert_eq!(fsm.find(&mut handle, 1, 0).unwrap(), None);
+        fsm.set(&mut handle, 5, 20).unwrap();
+        fsm.set(&mut handle, 9, 64).unwrap();
+        assert_eq!(fsm.find(&mut handle, 20, 0).unwrap(), Some(5));
+        assert_eq!(fsm.find(&mut handle, 21, 0).unwrap(), Some(9));
+        assert_eq!(fsm.find(&mut handle, 20, 6).unwrap(), Some(9));
+        assert_eq!(fsm.find(&mut handle, 64, 0).unwrap(), Some(9));
+        assert_eq!(fsm.find(&mut handle, 65, 0).unwrap(), None);
+
+        // 28 entries fit in a map page, so later pages need another map page
+        fsm.set(&mut handle, 50, 0).unwrap();
+        assert_eq!(fsm.pages(), [1]);
+        fsm.set(&mut handle, 50, 30).unwrap();
+        assert_eq!(fsm.pages(), [1, 2]);
+        fsm.set(&mut handle, 9, 0).unwrap();
+        assert_eq!(fsm.find(&mut handle, 21, 0).unwrap(), Some(50));
+
+        let fsm = FreeSpaceMap::open(&mut handle, 1).unwrap();
+        assert_eq!(fsm.pages(), [1, 2]);
+        assert_eq!(fsm.find(&mut handle, 21, 0).unwrap(), Some(50));
+        assert!(FreeSpaceMap::<64>::open(&mut handle, 0).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+// File checks
+//
+// Walks a record file and reports what's wrong with it, rather than failing
+// at the first invalid page like reads do. RecordBasedFileMgr::check checks
+// every data page's slot directory, the bounds and overlap of its records,
+// each record's header and fields against the schema, and that forwarding
+// stubs and moved records point at each other.
+//
+// Repair only fixes problems that lose no data: a slot directory whose data
+// start offset is past its records, and a moved record whose forwarding
+// stub is gone, which becomes a record of its own. Pages are only rewritten
+// if their slot directory is otherwise valid. Everything else is left for
+// the caller to deal with.
+
+use crate::error::{Error, Result};
+use crate::record_based_file_mgr::RecordBasedFileMgr;
+use std::fmt;
+use std::path::Path;
+
+/// A problem found in a file, at page and slot where they're known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub page: Option<u64>,
+    pub slot: Option<u32>,
+    pub message: String,
+    pub repaired: bool,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.page, self.slot) {
+            (Some(page), Some(slot)) => write!(f, "page {}, slot {}: ", page, slot)?,
+            (Some(page), None) => write!(f, "page {}: ", page)?,
+            _ => (),
+        }
+        write!(f, "{}", self.message)?;
+        if self.repaired {
+            write!(f, " (repaired)")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    /// Number of pages in the file
+    pub pages: u64,
+    /// Number of records checked
+    pub records: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl FsckReport {
+    /// True if every problem found was repaired.
+    pub fn is_clean(&self) -> bool {
+        self.problems.iter().all(|problem| problem.repaired)
+    }
+
+    pub(crate) fn add<S: Into<String>>(&mut self, page: u64, slot: Option<u32>, message: S) {
+        self.problems.push(Problem {
+            page: Some(page),
+            slot,
+            message: message.into(),
+            repaired: false,
+        });
+    }
+
+    /// Mark the last problem added as repaired.
+    pub(crate) fn repaired(&mut self) {
+        if let Some(problem) = self.problems.last_mut() {
+            problem.repaired = true;
+        }
+    }
+}
+
+/// Check the record file at path. Files that can't be opened, e.g. because
+/// their page size or schema page is invalid, are reported as a problem
+/// rather than an error.
+pub fn check_file(path: &Path, repair: bool) -> Result<FsckReport> {
+    match RecordBasedFileMgr::open_with_stored_schema(path) {
+        Ok(mut file) => file.check(repair),
+        Err(e @ Error::UnsupportedFormat(_)) => Ok(FsckReport {
+            problems: vec![Problem {
+                page: Some(0),
+                slot: None,
+                message: e.to_string(),
+                repaired: false,
+            }],
+            ..FsckReport::default()
+        }),
+        Err(Error::Corruption {
+            page,
+            slot,
+            message,
+        }) => Ok(FsckReport {
+            problems: vec![Problem {
+                page,
+                slot,
+                message,
+                repaired: false,
+            }],
+            ..FsckReport::default()
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute::*;
+    use crate::page::Page;
+    use crate::paged_file::PagedFile;
+    use crate::record_based_file_mgr::PageKind;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    #[test]
+    fn fsck_check_file_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![Attribute::new("Name", AttributeType::Varchar { len: 20 })];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+        let mut attr_vals = HashMap::new();
+        attr_vals.insert(
+            "Name".to_string(),
+            AttributeValue::Varchar("Cow".to_string()),
+        );
+        file.insert(&attr_vals).unwrap();
+        drop(file);
+
+        let report = check_file(&file_path, false).unwrap();
+        assert!(report.is_clean());
+        assert_eq!((report.pages, report.records), (3, 1));
+
+        // An unreadable schema page stops the check
+        let mut paged_file = PagedFile::<{ 8 * 1024 }>::open(&file_path).unwrap();
+        let mut page = Page::new();
+        page.as_mut_buf().iter_mut().for_each(|b| *b = 0xff);
+        paged_file.write_page(0, &page).unwrap();
+        let report = check_file(&file_path, true).unwrap();
+        assert!(!report.is_clean());
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!(report.problems[0].page, Some(0));
+
+        // And a file written before formats were versioned, whose free space
+        // map page would otherwise look corrupt
+        let mut page = Page::new();
+        bincode::serialize_into(&mut page.as_mut_buf()[..], &(PageKind::Schema, 0u64)).unwrap();
+        paged_file.write_page(0, &page).unwrap();
+        let report = check_file(&file_path, false).unwrap();
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.problems[0]
+            .message
+            .starts_with("Unsupported file format"));
+
+        // So does a page size that doesn't match
+        let mut paged_file = PagedFile::<1024>::create(&dir.path().join("small")).unwrap();
+        paged_file.append_page(&Page::new()).unwrap();
+        let report = check_file(&dir.path().join("small"), false).unwrap();
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.problems[0].message.contains("Page size mismatch"));
+    }
+}
@@ -0,0 +1,904 @@
+// Json
+//
+// Json documents are parsed and validated up front, then stored in records
+// using a compact binary encoding:
+// Tag: 1 byte
+// Null, False, True: no payload
+// Int: 8 byte signed int
+// Real: 8 byte float
+// String: 4 byte length + UTF-8 bytes
+// Array: 4 byte payload length + 4 byte count + each element
+// Object: 4 byte payload length + 4 byte count + each (4 byte key length + key + value)
+//
+// Arrays and objects store their payload length so path extraction can skip
+// over them without decoding.
+
+use crate::attribute::AttributeValue;
+use crate::error::{Error, Result};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryFrom;
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+
+// Documents nested deeper than this are rejected
+const MAX_DEPTH: usize = 128;
+
+const TAG_NULL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_REAL: u8 = 4;
+const TAG_STRING: u8 = 5;
+const TAG_ARRAY: u8 = 6;
+const TAG_OBJECT: u8 = 7;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    // Numbers without a fraction or exponent that fit in an i64
+    Int(i64),
+    Real(f64),
+    String(String),
+    Array(Vec<Json>),
+    // Keys are unique and kept in document order
+    Object(Vec<(String, Json)>),
+}
+
+fn invalid_json(msg: &str, pos: usize) -> Error {
+    Error::invalid_input(format!("Invalid JSON at byte {}: {}", pos, msg))
+}
+
+fn corrupt_json() -> Error {
+    Error::corrupt("Invalid binary JSON")
+}
+
+impl Json {
+    /// True if every number in the document is finite. JSON has no NaN or
+    /// infinity, so only these documents can be stored.
+    pub fn is_finite(&self) -> bool {
+        match self {
+            Json::Real(val) => val.is_finite(),
+            Json::Array(elems) => elems.iter().all(Json::is_finite),
+            Json::Object(members) => members.iter().all(|(_, val)| val.is_finite()),
+            _ => true,
+        }
+    }
+
+    /// Encode the document in the compact binary form.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        self.encode_into(&mut bytes);
+        bytes
+    }
+
+    fn encode_into(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Json::Null => bytes.push(TAG_NULL),
+            Json::Bool(false) => bytes.push(TAG_FALSE),
+            Json::Bool(true) => bytes.push(TAG_TRUE),
+            Json::Int(val) => {
+                bytes.push(TAG_INT);
+                bytes.extend_from_slice(&val.to_le_bytes());
+            }
+            Json::Real(val) => {
+                bytes.push(TAG_REAL);
+                bytes.extend_from_slice(&val.to_le_bytes());
+            }
+            Json::String(val) => {
+                bytes.push(TAG_STRING);
+                Self::encode_str(val, bytes);
+            }
+            Json::Array(elems) => {
+                bytes.push(TAG_ARRAY);
+                let len_pos = Self::begin_container(elems.len(), bytes);
+                for elem in elems.iter() {
+                    elem.encode_into(bytes);
+                }
+                Self::end_container(len_pos, bytes);
+            }
+            Json::Object(members) => {
+                bytes.push(TAG_OBJECT);
+                let len_pos = Self::begin_container(members.len(), bytes);
+                for (key, val) in members.iter() {
+                    Self::encode_str(key, bytes);
+                    val.encode_into(bytes);
+                }
+                Self::end_container(len_pos, bytes);
+            }
+        }
+    }
+
+    fn encode_str(val: &str, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&(val.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(val.as_bytes());
+    }
+
+    /// Reserve space for the payload length and write the count.
+    /// Returns the position of the payload length.
+    fn begin_container(count: usize, bytes: &mut Vec<u8>) -> usize {
+        let len_pos = bytes.len();
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&(count as u32).to_le_bytes());
+        len_pos
+    }
+
+    fn end_container(len_pos: usize, bytes: &mut [u8]) {
+        let payload_len = (bytes.len() - len_pos - 4) as u32;
+        bytes[len_pos..len_pos + 4].copy_from_slice(&payload_len.to_le_bytes());
+    }
+
+    /// Decode a document from the compact binary form.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = BinReader { bytes, pos: 0 };
+        let val = reader.read_value(0)?;
+        if reader.pos != bytes.len() {
+            return Err(corrupt_json());
+        }
+        Ok(val)
+    }
+
+    /// Find the value at path, if there is one.
+    pub fn get_path(&self, path: &JsonPath) -> Option<&Json> {
+        let mut curr = self;
+        for step in path.steps.iter() {
+            curr = match (step, curr) {
+                (PathStep::Key(key), Json::Object(members)) => {
+                    &members.iter().find(|(k, _)| k == key)?.1
+                }
+                (PathStep::Index(idx), Json::Array(elems)) => elems.get(*idx)?,
+                _ => return None,
+            };
+        }
+        Some(curr)
+    }
+
+    /// Find the value at path and convert it to an AttributeValue.
+    /// Json nulls and missing values both give None.
+    pub fn extract(&self, path: &JsonPath) -> Option<AttributeValue> {
+        self.get_path(path).and_then(Json::to_attribute_value)
+    }
+
+    /// Convert to the closest AttributeValue.
+    /// Strings become Varchars, and numbers become Ints when they fit and Reals
+    /// otherwise. Bools, arrays and objects stay Json.
+    pub fn to_attribute_value(&self) -> Option<AttributeValue> {
+        match self {
+            Json::Null => None,
+            Json::Int(val) => Some(match i32::try_from(*val) {
+                Ok(val) => AttributeValue::Int(val),
+                Err(_) => AttributeValue::Real(*val as f64),
+            }),
+            Json::Real(val) => Some(AttributeValue::Real(*val)),
+            Json::String(val) => Some(AttributeValue::Varchar(val.clone())),
+            Json::Bool(_) | Json::Array(_) | Json::Object(_) => {
+                Some(AttributeValue::Json(self.clone()))
+            }
+        }
+    }
+}
+
+/// Find the value at path directly in the binary form of a document, without
+/// decoding the parts of the document that aren't on the path.
+pub fn extract_from_bytes(bytes: &[u8], path: &JsonPath) -> Result<Option<AttributeValue>> {
+    let mut reader = BinReader { bytes, pos: 0 };
+    for step in path.steps.iter() {
+        let tag = reader.read_u8()?;
+        match (step, tag) {
+            (PathStep::Key(key), TAG_OBJECT) => {
+                let _payload_len = reader.read_u32()?;
+                let count = reader.read_u32()?;
+                let mut found = false;
+                for _ in 0..count {
+                    if reader.read_str()? == key {
+                        found = true;
+                        break;
+                    }
+                    reader.skip_value()?;
+                }
+                if !found {
+                    return Ok(None);
+                }
+            }
+            (PathStep::Index(idx), TAG_ARRAY) => {
+                let _payload_len = reader.read_u32()?;
+                let count = reader.read_u32()? as usize;
+                if *idx >= count {
+                    return Ok(None);
+                }
+                for _ in 0..*idx {
+                    reader.skip_value()?;
+                }
+            }
+            (_, TAG_NULL..=TAG_OBJECT) => return Ok(None),
+            _ => return Err(corrupt_json()),
+        }
+    }
+    Ok(reader.read_value(0)?.to_attribute_value())
+}
+
+struct BinReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BinReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or_else(corrupt_json)?;
+        let bytes = self.bytes.get(self.pos..end).ok_or_else(corrupt_json)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn read_8_bytes(&mut self) -> Result<[u8; 8]> {
+        Ok(self.take(8)?.try_into().unwrap())
+    }
+
+    fn read_str(&mut self) -> Result<&'a str> {
+        let len = self.read_u32()? as usize;
+        std::str::from_utf8(self.take(len)?).map_err(|_| corrupt_json())
+    }
+
+    fn read_value(&mut self, depth: usize) -> Result<Json> {
+        if depth > MAX_DEPTH {
+            return Err(corrupt_json());
+        }
+        Ok(match self.read_u8()? {
+            TAG_NULL => Json::Null,
+            TAG_FALSE => Json::Bool(false),
+            TAG_TRUE => Json::Bool(true),
+            TAG_INT => Json::Int(i64::from_le_bytes(self.read_8_bytes()?)),
+            TAG_REAL => match f64::from_le_bytes(self.read_8_bytes()?) {
+                val if val.is_finite() => Json::Real(val),
+                _ => return Err(corrupt_json()),
+            },
+            TAG_STRING => Json::String(self.read_str()?.to_string()),
+            TAG_ARRAY => {
+                let end = self.container_end()?;
+                let count = self.read_u32()?;
+                let mut elems = vec![];
+                for _ in 0..count {
+                    elems.push(self.read_value(depth + 1)?);
+                }
+                self.check_container_end(end)?;
+                Json::Array(elems)
+            }
+            TAG_OBJECT => {
+                let end = self.container_end()?;
+                let count = self.read_u32()?;
+                let mut members = vec![];
+                for _ in 0..count {
+                    let key = self.read_str()?.to_string();
+                    members.push((key, self.read_value(depth + 1)?));
+                }
+                self.check_container_end(end)?;
+                Json::Object(members)
+            }
+            _ => return Err(corrupt_json()),
+        })
+    }
+
+    /// Read a container's payload length and return where the container ends.
+    fn container_end(&mut self) -> Result<usize> {
+        let payload_len = self.read_u32()? as usize;
+        Ok(self.pos + payload_len)
+    }
+
+    fn check_container_end(&self, end: usize) -> Result<()> {
+        if self.pos != end {
+            return Err(corrupt_json());
+        }
+        Ok(())
+    }
+
+    fn skip_value(&mut self) -> Result<()> {
+        match self.read_u8()? {
+            TAG_NULL | TAG_FALSE | TAG_TRUE => {}
+            TAG_INT | TAG_REAL => {
+                self.take(8)?;
+            }
+            TAG_STRING => {
+                self.read_str()?;
+            }
+            TAG_ARRAY | TAG_OBJECT => {
+                let payload_len = self.read_u32()? as usize;
+                self.take(payload_len)?;
+            }
+            _ => return Err(corrupt_json()),
+        }
+        Ok(())
+    }
+}
+
+/// Parses JSON text, rejecting objects with duplicate keys.
+impl FromStr for Json {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parser = TextParser {
+            bytes: s.as_bytes(),
+            pos: 0,
+        };
+        let val = parser.parse_value(0)?;
+        parser.skip_whitespace();
+        if parser.pos != parser.bytes.len() {
+            return Err(invalid_json("trailing characters", parser.pos));
+        }
+        Ok(val)
+    }
+}
+
+struct TextParser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> TextParser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        if self.peek() != Some(byte) {
+            return Err(invalid_json(
+                &format!("expected '{}'", byte as char),
+                self.pos,
+            ));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn expect_literal(&mut self, literal: &str, val: Json) -> Result<Json> {
+        if !self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            return Err(invalid_json("unexpected character", self.pos));
+        }
+        self.pos += literal.len();
+        Ok(val)
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<Json> {
+        if depth > MAX_DEPTH {
+            return Err(invalid_json("nested too deeply", self.pos));
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.expect_literal("null", Json::Null),
+            Some(b't') => self.expect_literal("true", Json::Bool(true)),
+            Some(b'f') => self.expect_literal("false", Json::Bool(false)),
+            Some(b'"') => Ok(Json::String(self.parse_string()?)),
+            Some(b'[') => self.parse_array(depth),
+            Some(b'{') => self.parse_object(depth),
+            Some(b'-') | Some(b'0'..=b'9') => self.parse_number(),
+            Some(_) => Err(invalid_json("unexpected character", self.pos)),
+            None => Err(invalid_json("unexpected end of input", self.pos)),
+        }
+    }
+
+    fn parse_array(&mut self, depth: usize) -> Result<Json> {
+        self.expect(b'[')?;
+        let mut elems = vec![];
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(elems));
+        }
+        loop {
+            elems.push(self.parse_value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                _ => break,
+            }
+        }
+        self.expect(b']')?;
+        Ok(Json::Array(elems))
+    }
+
+    fn parse_object(&mut self, depth: usize) -> Result<Json> {
+        self.expect(b'{')?;
+        let mut members: Vec<(String, Json)> = vec![];
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key_pos = self.pos;
+            let key = self.parse_string()?;
+            if members.iter().any(|(k, _)| *k == key) {
+                return Err(invalid_json("duplicate key", key_pos));
+            }
+            self.skip_whitespace();
+            self.expect(b':')?;
+            members.push((key, self.parse_value(depth + 1)?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                _ => break,
+            }
+        }
+        self.expect(b'}')?;
+        Ok(Json::Object(members))
+    }
+
+    fn parse_number(&mut self) -> Result<Json> {
+        let start = self.pos;
+        let mut is_int = true;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        // Leading zeros aren't allowed
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => self.skip_digits(),
+            _ => return Err(invalid_json("invalid number", self.pos)),
+        }
+        if self.peek() == Some(b'.') {
+            is_int = false;
+            self.pos += 1;
+            self.expect_digits()?;
+        }
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            is_int = false;
+            self.pos += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.pos += 1;
+            }
+            self.expect_digits()?;
+        }
+
+        // Only ASCII has been consumed, so this is valid UTF-8
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap();
+        if is_int {
+            if let Ok(val) = text.parse::<i64>() {
+                return Ok(Json::Int(val));
+            }
+        }
+        match text.parse::<f64>().unwrap() {
+            val if val.is_finite() => Ok(Json::Real(val)),
+            _ => Err(invalid_json("number out of range", start)),
+        }
+    }
+
+    fn skip_digits(&mut self) {
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect_digits(&mut self) -> Result<()> {
+        let start = self.pos;
+        self.skip_digits();
+        if self.pos == start {
+            return Err(invalid_json("invalid number", self.pos));
+        }
+        Ok(())
+    }
+
+    fn parse_string(&mut self) -> Result<String> {
+        self.expect(b'"')?;
+        let mut bytes = vec![];
+        loop {
+            match self.peek() {
+                None => return Err(invalid_json("unterminated string", self.pos)),
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.pos += 1;
+                            let c = self.parse_unicode_escape()?;
+                            let mut buf = [0; 4];
+                            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                            continue;
+                        }
+                        _ => return Err(invalid_json("invalid escape", self.pos)),
+                    };
+                    self.pos += 1;
+                    bytes.push(escaped as u8);
+                }
+                Some(0..=0x1f) => {
+                    return Err(invalid_json("control character in string", self.pos))
+                }
+                Some(byte) => {
+                    self.pos += 1;
+                    bytes.push(byte);
+                }
+            }
+        }
+        // The input was a &str, and escapes produce valid UTF-8
+        Ok(String::from_utf8(bytes).unwrap())
+    }
+
+    /// Parse the hex digits of a \u escape, combining surrogate pairs.
+    fn parse_unicode_escape(&mut self) -> Result<char> {
+        let high = self.parse_hex4()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if !self.bytes[self.pos..].starts_with(b"\\u") {
+                return Err(invalid_json("unpaired surrogate", self.pos));
+            }
+            self.pos += 2;
+            let low = self.parse_hex4()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(invalid_json("unpaired surrogate", self.pos));
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| invalid_json("unpaired surrogate", self.pos))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32> {
+        let digits = self
+            .bytes
+            .get(self.pos..self.pos + 4)
+            .filter(|d| d.iter().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| invalid_json("invalid unicode escape", self.pos))?;
+        self.pos += 4;
+        Ok(u32::from_str_radix(std::str::from_utf8(digits).unwrap(), 16).unwrap())
+    }
+}
+
+/// Formats as compact JSON text.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(val) => write!(f, "{}", val),
+            Json::Int(val) => write!(f, "{}", val),
+            // Debug formatting keeps a fraction so the value reads back as a Real.
+            // Documents built with a non-finite number can't be stored, see
+            // is_finite, and JSON has no text for them.
+            Json::Real(val) if val.is_finite() => write!(f, "{:?}", val),
+            Json::Real(_) => write!(f, "null"),
+            Json::String(val) => write_json_str(f, val),
+            Json::Array(elems) => {
+                write!(f, "[")?;
+                for (i, elem) in elems.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, val)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_json_str(f, key)?;
+                    write!(f, ":{}", val)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_json_str(f: &mut fmt::Formatter, val: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in val.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Serializes as the equivalent serde data for human readable formats, so
+/// serde_json output matches Display. Other formats get the binary encoding.
+impl Serialize for Json {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serializer.serialize_bytes(&self.to_bytes());
+        }
+        match self {
+            Json::Null => serializer.serialize_unit(),
+            Json::Bool(val) => serializer.serialize_bool(*val),
+            Json::Int(val) => serializer.serialize_i64(*val),
+            Json::Real(val) => serializer.serialize_f64(*val),
+            Json::String(val) => serializer.serialize_str(val),
+            Json::Array(elems) => serializer.collect_seq(elems),
+            Json::Object(members) => {
+                serializer.collect_map(members.iter().map(|(key, val)| (key, val)))
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Json {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(JsonVisitor)
+        } else {
+            deserializer.deserialize_bytes(JsonVisitor)
+        }
+    }
+}
+
+struct JsonVisitor;
+
+impl<'de> Visitor<'de> for JsonVisitor {
+    type Value = Json;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a JSON value")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> std::result::Result<Json, E> {
+        Ok(Json::Null)
+    }
+
+    fn visit_none<E: de::Error>(self) -> std::result::Result<Json, E> {
+        Ok(Json::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, d: D) -> std::result::Result<Json, D::Error> {
+        Json::deserialize(d)
+    }
+
+    fn visit_bool<E: de::Error>(self, val: bool) -> std::result::Result<Json, E> {
+        Ok(Json::Bool(val))
+    }
+
+    fn visit_i64<E: de::Error>(self, val: i64) -> std::result::Result<Json, E> {
+        Ok(Json::Int(val))
+    }
+
+    fn visit_u64<E: de::Error>(self, val: u64) -> std::result::Result<Json, E> {
+        Ok(match i64::try_from(val) {
+            Ok(val) => Json::Int(val),
+            Err(_) => Json::Real(val as f64),
+        })
+    }
+
+    fn visit_f64<E: de::Error>(self, val: f64) -> std::result::Result<Json, E> {
+        if !val.is_finite() {
+            return Err(E::custom("JSON numbers must be finite"));
+        }
+        Ok(Json::Real(val))
+    }
+
+    fn visit_str<E: de::Error>(self, val: &str) -> std::result::Result<Json, E> {
+        Ok(Json::String(val.to_string()))
+    }
+
+    fn visit_bytes<E: de::Error>(self, val: &[u8]) -> std::result::Result<Json, E> {
+        Json::from_bytes(val).map_err(E::custom)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Json, A::Error> {
+        let mut elems = vec![];
+        while let Some(elem) = seq.next_element()? {
+            elems.push(elem);
+        }
+        Ok(Json::Array(elems))
+    }
+
+    // Duplicate keys are rejected, as when parsing text
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<Json, A::Error> {
+        let mut members: Vec<(String, Json)> = vec![];
+        while let Some((key, val)) = map.next_entry::<String, Json>()? {
+            if members.iter().any(|(k, _)| *k == key) {
+                return Err(de::Error::custom(format!("duplicate key {}", key)));
+            }
+            members.push((key, val));
+        }
+        Ok(Json::Object(members))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum PathStep {
+    Key(String),
+    Index(usize),
+}
+
+/// A path into a Json document, such as $.a.b[2] or $["a"].b
+#[derive(Debug, PartialEq, Clone)]
+pub struct JsonPath {
+    steps: Vec<PathStep>,
+}
+
+impl FromStr for JsonPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::invalid_input(format!("Invalid JSON path: {}", s));
+        let mut rest = s.strip_prefix('$').ok_or_else(invalid)?;
+        let mut steps = vec![];
+        while !rest.is_empty() {
+            if let Some(after_dot) = rest.strip_prefix('.') {
+                let end = after_dot.find(['.', '[']).unwrap_or(after_dot.len());
+                if end == 0 {
+                    return Err(invalid());
+                }
+                steps.push(PathStep::Key(after_dot[..end].to_string()));
+                rest = &after_dot[end..];
+            } else if let Some(after_bracket) = rest.strip_prefix('[') {
+                let end = after_bracket.find(']').ok_or_else(invalid)?;
+                let inner = &after_bracket[..end];
+                let step = if inner.starts_with('"') {
+                    match inner.parse::<Json>() {
+                        Ok(Json::String(key)) => PathStep::Key(key),
+                        _ => return Err(invalid()),
+                    }
+                } else if !inner.is_empty() && inner.bytes().all(|b| b.is_ascii_digit()) {
+                    PathStep::Index(inner.parse().map_err(|_| invalid())?)
+                } else {
+                    return Err(invalid());
+                };
+                steps.push(step);
+                rest = &after_bracket[end + 1..];
+            } else {
+                return Err(invalid());
+            }
+        }
+        Ok(JsonPath { steps })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_parse_test() {
+        let doc: Json =
+            r#" {"a": {"b": [1, -2.5, "x\n\u00e9\ud83d\ude00"]}, "c": null, "d": true} "#
+                .parse()
+                .unwrap();
+        assert_eq!(
+            doc,
+            Json::Object(vec![
+                (
+                    "a".to_string(),
+                    Json::Object(vec![(
+                        "b".to_string(),
+                        Json::Array(vec![
+                            Json::Int(1),
+                            Json::Real(-2.5),
+                            Json::String("x\né😀".to_string()),
+                        ])
+                    )])
+                ),
+                ("c".to_string(), Json::Null),
+                ("d".to_string(), Json::Bool(true)),
+            ])
+        );
+        // Formatting and parsing again gives the same document
+        assert_eq!(doc.to_string().parse::<Json>().unwrap(), doc);
+
+        for bad in [
+            "",
+            "{",
+            "[1,]",
+            "[1 2]",
+            "01",
+            "1.",
+            "-",
+            "{\"a\":1,\"a\":2}",
+            "\"\\x\"",
+            "nul",
+            "\"\\ud83d\"",
+            "1 2",
+            "{1:2}",
+        ]
+        .iter()
+        {
+            assert!(bad.parse::<Json>().is_err(), "{}", bad);
+        }
+        assert!("[".repeat(MAX_DEPTH + 2).parse::<Json>().is_err());
+    }
+
+    #[test]
+    fn json_binary_test() {
+        let doc: Json = r#"{"a": [1, 2.5, "s", [], {}], "b": false, "c": 9007199254740993}"#
+            .parse()
+            .unwrap();
+        let bytes = doc.to_bytes();
+        assert_eq!(Json::from_bytes(&bytes).unwrap(), doc);
+
+        // Truncated or extended encodings are rejected
+        assert!(Json::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut extended = bytes.clone();
+        extended.push(TAG_NULL);
+        assert!(Json::from_bytes(&extended).is_err());
+        assert!(Json::from_bytes(&[42]).is_err());
+
+        // Numbers must be finite
+        let mut nan = vec![TAG_REAL];
+        nan.extend_from_slice(&f64::NAN.to_le_bytes());
+        assert!(Json::from_bytes(&nan).is_err());
+        assert!("1e999".parse::<Json>().is_err());
+        assert!(doc.is_finite());
+        assert!(!Json::Array(vec![Json::Real(f64::INFINITY)]).is_finite());
+    }
+
+    #[test]
+    fn json_path_test() {
+        let doc: Json = r#"{"a": {"b": [10, 20, "thirty", 3000000000], "c.d": 1.5}, "e": [true]}"#
+            .parse()
+            .unwrap();
+        let bytes = doc.to_bytes();
+        let cases = vec![
+            ("$.a.b[1]", Some(AttributeValue::Int(20))),
+            (
+                "$.a.b[2]",
+                Some(AttributeValue::Varchar("thirty".to_string())),
+            ),
+            ("$.a.b[3]", Some(AttributeValue::Real(3000000000.0))),
+            ("$.a[\"c.d\"]", Some(AttributeValue::Real(1.5))),
+            ("$[\"e\"][0]", Some(AttributeValue::Json(Json::Bool(true)))),
+            (
+                "$.e",
+                Some(AttributeValue::Json(Json::Array(vec![Json::Bool(true)]))),
+            ),
+            ("$.a.b[4]", None),
+            ("$.a.x", None),
+            ("$.a.b.c", None),
+            ("$.e[0].f", None),
+        ];
+        for (path, expected) in cases.into_iter() {
+            let path: JsonPath = path.parse().unwrap();
+            assert_eq!(doc.extract(&path), expected);
+            assert_eq!(extract_from_bytes(&bytes, &path).unwrap(), expected);
+        }
+
+        for bad in ["", "a.b", "$.", "$..a", "$[x]", "$[1", "$a"].iter() {
+            assert!(bad.parse::<JsonPath>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn json_serde_test() {
+        let text = r#"{"a":[1,2.5,null,true],"b":{"c":"d"}}"#;
+        let json: Json = text.parse().unwrap();
+        // Human readable formats get the equivalent JSON
+        assert_eq!(serde_json::to_string(&json).unwrap(), text);
+        assert_eq!(serde_json::from_str::<Json>(text).unwrap(), json);
+        assert!(serde_json::from_str::<Json>(r#"{"a":1,"a":2}"#).is_err());
+        // Others get the binary encoding
+        let bytes = bincode::serialize(&json).unwrap();
+        assert_eq!(bincode::deserialize::<Json>(&bytes).unwrap(), json);
+        assert!(serde_json::from_str::<Json>("1e999").is_err());
+    }
+}
@@ -1,5 +1,21 @@
+// Lets #[derive(Record)] refer to rust_rbf paths from inside this crate
+extern crate self as rust_rbf;
+
 pub mod attribute;
 pub mod bitmap;
+pub mod cast;
+pub mod compare;
+pub mod error;
+pub mod field;
+pub mod free_space_map;
+pub mod fsck;
+pub mod json;
+pub mod overflow;
 pub mod page;
 pub mod paged_file;
+pub mod record;
 pub mod record_based_file_mgr;
+pub mod record_ref;
+pub mod record_serde;
+pub mod row;
+pub mod uuid;
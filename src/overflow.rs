@@ -0,0 +1,209 @@
+// Overflow pages
+//
+// Values that don't fit in their record's page are written to a chain of
+// overflow pages. The record stores an OverflowPointer in place of the value.
+// The caller chooses the chain's pages, so pages of freed chains can be
+// reused.
+
+use crate::error::{Error, Result};
+use crate::page::*;
+use crate::paged_file::*;
+use crate::record_based_file_mgr::PageKind;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+const OVERFLOW_HDR_SIZE: usize = 12;
+pub const OVERFLOW_POINTER_SIZE: usize = 8;
+
+#[derive(Serialize, Deserialize)]
+struct OverflowPageHeader {
+    page_kind: PageKind,
+    // Next page in the chain, 0 for the last page.
+    // Page 0 is never an overflow page.
+    next_page: u32,
+    // Number of bytes of the value stored in this page
+    data_len: u32,
+}
+
+/// Location of a value stored in a chain of overflow pages.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct OverflowPointer {
+    // Total length of the value in bytes
+    pub len: u32,
+    pub first_page: u32,
+}
+
+impl OverflowPointer {
+    pub fn to_bytes(self) -> [u8; OVERFLOW_POINTER_SIZE] {
+        let mut bytes = [0; OVERFLOW_POINTER_SIZE];
+        bytes[..4].copy_from_slice(&self.len.to_le_bytes());
+        bytes[4..].copy_from_slice(&self.first_page.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != OVERFLOW_POINTER_SIZE {
+            return Err(Error::corrupt("Invalid overflow pointer"));
+        }
+        Ok(Self {
+            len: u32::from_le_bytes(bytes[..4].try_into().unwrap()),
+            first_page: u32::from_le_bytes(bytes[4..].try_into().unwrap()),
+        })
+    }
+}
+
+/// Number of value bytes that fit in a single overflow page.
+fn chunk_size<const PAGE_SIZE: usize>() -> usize {
+    PAGE_SIZE - OVERFLOW_HDR_SIZE
+}
+
+/// Number of overflow pages needed to hold len bytes.
+pub fn num_chunks<const PAGE_SIZE: usize>(len: usize) -> usize {
+    len.div_ceil(chunk_size::<PAGE_SIZE>())
+}
+
+/// Write a chain of overflow pages holding data to pages, which must have
+/// num_chunks(data.len()) entries. Pages may be reused ones, or the end of
+/// the file, which is extended.
+pub fn write_chain<const PAGE_SIZE: usize>(
+    paged_file: &mut PagedFile<PAGE_SIZE>,
+    data: &[u8],
+    pages: &[u32],
+) -> Result<OverflowPointer> {
+    if pages.len() != num_chunks::<PAGE_SIZE>(data.len()) {
+        return Err(Error::invalid_input("Wrong number of overflow pages"));
+    }
+
+    let mut page = Page::<PAGE_SIZE>::new();
+    for (i, chunk) in data.chunks(chunk_size::<PAGE_SIZE>()).enumerate() {
+        let next_page = pages.get(i + 1).copied().unwrap_or(0);
+        let hdr = OverflowPageHeader {
+            page_kind: PageKind::Overflow,
+            next_page,
+            data_len: chunk.len() as u32,
+        };
+        let buf = page.as_mut_buf();
+        buf.iter_mut().for_each(|b| *b = 0);
+        bincode::serialize_into(&mut buf[..OVERFLOW_HDR_SIZE], &hdr).unwrap();
+        buf[OVERFLOW_HDR_SIZE..OVERFLOW_HDR_SIZE + chunk.len()].copy_from_slice(chunk);
+        if pages[i] as u64 == paged_file.num_pages()? {
+            paged_file.append_page(&page)?;
+        } else {
+            paged_file.write_page(pages[i] as u64, &page)?;
+        }
+    }
+
+    Ok(OverflowPointer {
+        len: data.len() as u32,
+        first_page: pages.first().copied().unwrap_or(0),
+    })
+}
+
+/// Read an overflow page and check its header.
+fn read_overflow_page<const PAGE_SIZE: usize>(
+    paged_file: &mut PagedFile<PAGE_SIZE>,
+    page_num: u32,
+    page: &mut Page<PAGE_SIZE>,
+) -> Result<OverflowPageHeader> {
+    let invalid = || Error::corrupt_page(page_num as u64, "Invalid overflow page");
+    // A chain that leads past the end of the file is corrupt
+    match paged_file.read_page(page_num as u64, page) {
+        Err(Error::PageNotFound { .. }) => return Err(invalid()),
+        result => result?,
+    }
+    let hdr: OverflowPageHeader = bincode::deserialize(page.as_buf()).map_err(|_| invalid())?;
+    if hdr.page_kind != PageKind::Overflow || hdr.data_len as usize > chunk_size::<PAGE_SIZE>() {
+        return Err(invalid());
+    }
+    Ok(hdr)
+}
+
+/// The page numbers of the chain of overflow pages starting at ptr, in order.
+/// Used to free the pages when the value is removed.
+pub fn chain_pages<const PAGE_SIZE: usize>(
+    paged_file: &mut PagedFile<PAGE_SIZE>,
+    ptr: &OverflowPointer,
+) -> Result<Vec<u32>> {
+    let mut pages = vec![];
+    let mut page = Page::<PAGE_SIZE>::new();
+    let mut page_num = ptr.first_page;
+    let mut len = 0;
+    while len < ptr.len as usize {
+        // A chain can't have more pages than the file, so this catches loops
+        if page_num == 0 || pages.len() as u64 >= paged_file.num_pages()? {
+            return Err(Error::corrupt("Invalid overflow chain"));
+        }
+        let hdr = read_overflow_page(paged_file, page_num, &mut page)?;
+        pages.push(page_num);
+        len += hdr.data_len as usize;
+        page_num = hdr.next_page;
+    }
+    Ok(pages)
+}
+
+/// Read the value stored in the chain of overflow pages starting at ptr.
+pub fn read_chain<const PAGE_SIZE: usize>(
+    paged_file: &mut PagedFile<PAGE_SIZE>,
+    ptr: &OverflowPointer,
+) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(ptr.len as usize);
+    let mut page = Page::<PAGE_SIZE>::new();
+    let mut page_num = ptr.first_page;
+
+    while data.len() < ptr.len as usize {
+        if page_num == 0 {
+            return Err(Error::corrupt("Overflow chain ended early"));
+        }
+        let hdr = read_overflow_page(paged_file, page_num, &mut page)?;
+        let start = OVERFLOW_HDR_SIZE;
+        data.extend_from_slice(&page.as_buf()[start..start + hdr.data_len as usize]);
+        page_num = hdr.next_page;
+    }
+
+    if data.len() != ptr.len as usize {
+        return Err(Error::corrupt("Overflow chain length mismatch"));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const PAGE_SIZE: usize = 64;
+    type Pf = PagedFile<PAGE_SIZE>;
+
+    #[test]
+    fn overflow_chain_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let mut handle = Pf::create(&file_path).unwrap();
+        // Page 0 is never part of a chain
+        handle.append_page(&Page::new()).unwrap();
+
+        let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        // 52 bytes per page, so 4 pages are needed
+        assert_eq!(num_chunks::<PAGE_SIZE>(data.len()), 4);
+        assert!(write_chain(&mut handle, &data, &[1, 2]).is_err());
+        let ptr = write_chain(&mut handle, &data, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(ptr.first_page, 1);
+        assert_eq!(ptr.len, 200);
+        assert_eq!(handle.num_pages().unwrap(), 5);
+
+        let ptr = OverflowPointer::from_bytes(&ptr.to_bytes()).unwrap();
+        assert_eq!(read_chain(&mut handle, &ptr).unwrap(), data);
+
+        // A pointer claiming more data than the chain holds is rejected
+        let bad_ptr = OverflowPointer { len: 300, ..ptr };
+        assert!(read_chain(&mut handle, &bad_ptr).is_err());
+
+        // Chains can reuse pages in any order
+        let data: Vec<u8> = (0..100).map(|i| 255 - i as u8).collect();
+        let ptr = write_chain(&mut handle, &data, &[3, 1]).unwrap();
+        assert_eq!(ptr.first_page, 3);
+        assert_eq!(chain_pages(&mut handle, &ptr).unwrap(), vec![3, 1]);
+        assert_eq!(read_chain(&mut handle, &ptr).unwrap(), data);
+        assert_eq!(handle.num_pages().unwrap(), 5);
+    }
+}
@@ -55,7 +55,7 @@ mod tests {
         let another_page = Page::<4096>::new_from_buf([1; 4096]);
         assert_eq!(*another_page.as_buf(), [1; 4096]);
         assert_eq!(*page.as_buf(), [0; 4096]);
-        assert_eq!(mem::size_of_val(&*page.as_buf()), 4096);
+        assert_eq!(mem::size_of_val(page.as_buf()), 4096);
     }
     #[test]
     fn into_test() {
@@ -1,10 +1,8 @@
+use crate::error::{Error, Result};
 use crate::page::*;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
-use std::io::Error;
-use std::io::ErrorKind;
-use std::io::Result;
 use std::io::SeekFrom;
 use std::path::Path;
 
@@ -24,13 +22,14 @@ impl<const PAGE_SIZE: usize> PagedFile<PAGE_SIZE> {
         file.read_exact(&mut hdr)?;
         // Extract the page_size from the header
         let hdr_page_size: u64 = u64::from_le_bytes(hdr);
-        // Confirm the hdr page size matches the expected page size
+        // Confirm the hdr page size matches the expected page size.
+        // Other page sizes are valid files, just not ones this type reads.
         if hdr_page_size != PAGE_SIZE as u64 {
             let err_str = format!(
                 "Page size mismatch. Header: {}, Expected: {}",
                 hdr_page_size, PAGE_SIZE as u64
             );
-            return Err(Error::new(ErrorKind::Other, err_str));
+            return Err(Error::UnsupportedFormat(err_str));
         }
         Ok(PagedFile::<PAGE_SIZE> { file })
     }
@@ -53,14 +52,16 @@ impl<const PAGE_SIZE: usize> PagedFile<PAGE_SIZE> {
         Ok((metadata.len() - HEADER_LEN as u64) / PAGE_SIZE as u64)
     }
 
-    fn seek(&mut self, pagenum: u64) -> Result<()> {
+    /// Seek to the start of page pagenum. With append, the page just past
+    /// the end of the file is allowed too.
+    fn seek(&mut self, pagenum: u64, append: bool) -> Result<()> {
         let num_pages = self.num_pages()?;
-        if num_pages < pagenum {
-            let err_str = format!(
-                "Page {} does not exist. Total pages: {}",
-                pagenum, num_pages
-            );
-            Err(Error::new(ErrorKind::NotFound, err_str))
+        let end = if append { num_pages + 1 } else { num_pages };
+        if pagenum >= end {
+            Err(Error::PageNotFound {
+                page: pagenum,
+                num_pages,
+            })
         } else {
             self.file.seek(SeekFrom::Start(
                 HEADER_LEN as u64 + pagenum * PAGE_SIZE as u64,
@@ -78,21 +79,22 @@ impl<const PAGE_SIZE: usize> PagedFile<PAGE_SIZE> {
 
     /// Read the given page from the file into the given Page buffer.
     pub fn read_page(&mut self, pagenum: u64, page: &mut Page<PAGE_SIZE>) -> Result<()> {
-        self.seek(pagenum)?;
+        self.seek(pagenum, false)?;
         self.file.read_exact(page.as_mut_buf())?;
         Ok(())
     }
 
     /// Write to the given page in the file.
+    /// Writing the page just past the end of the file appends it.
     pub fn write_page(&mut self, pagenum: u64, page: &Page<PAGE_SIZE>) -> Result<()> {
-        self.seek(pagenum)?;
-        self.file.write_all(page.as_buf())
+        self.seek(pagenum, true)?;
+        Ok(self.file.write_all(page.as_buf())?)
     }
 
     /// Appends a new page to the file.
     pub fn append_page(&mut self, page: &Page<PAGE_SIZE>) -> Result<()> {
         self.file.seek(SeekFrom::End(0))?;
-        self.file.write_all(page.as_buf())
+        Ok(self.file.write_all(page.as_buf())?)
     }
 }
 
@@ -112,11 +114,28 @@ mod tests {
         Pf::create(&file_path).unwrap();
 
         // Open the empty file
-        let mut handle = Pf::open(&file_path.as_path()).unwrap();
+        let mut handle = Pf::open(file_path.as_path()).unwrap();
         // Ensure no pages exist, and reading/writing non-existent pages fails
         assert_eq!(handle.num_pages().unwrap(), 0);
-        assert!(handle.read_page_alloc(0).is_err());
-        assert!(handle.write_page(10, &P::new()).is_err());
+        assert!(matches!(
+            handle.read_page_alloc(0).err().unwrap(),
+            Error::PageNotFound {
+                page: 0,
+                num_pages: 0
+            }
+        ));
+        assert!(matches!(
+            handle.write_page(10, &P::new()).unwrap_err(),
+            Error::PageNotFound { page: 10, .. }
+        ));
+        // The page after the last can be written, but not read
+        handle.write_page(0, &P::new()).unwrap();
+        assert_eq!(handle.num_pages().unwrap(), 1);
+        assert!(handle.read_page_alloc(0).is_ok());
+        assert!(matches!(
+            handle.read_page_alloc(1).err().unwrap(),
+            Error::PageNotFound { page: 1, .. }
+        ));
     }
 
     #[test]
@@ -125,7 +144,7 @@ mod tests {
         let file_path = dir.path().join("testfile");
         Pf::create(&file_path).unwrap();
 
-        let mut handle = Pf::open(&file_path.as_path()).unwrap();
+        let mut handle = Pf::open(file_path.as_path()).unwrap();
         let page = P::new();
 
         // Create 3 pages
@@ -200,7 +219,7 @@ mod tests {
 
         // Close the handle and open the same file again to verify contents were written to disk
         drop(handle);
-        let mut handle = Pf::open(&file_path.as_path()).unwrap();
+        let mut handle = Pf::open(file_path.as_path()).unwrap();
         assert_eq!(handle.num_pages().unwrap(), 4);
         assert_eq!(
             *handle.read_page_alloc(0).unwrap().as_buf(),
@@ -224,13 +243,14 @@ mod tests {
         Pf::create(&file_path).unwrap();
 
         // Verify we get a page size mismatch error
-        let err = PagedFile::<5000>::open(&file_path.as_path());
+        let err = PagedFile::<5000>::open(file_path.as_path());
         assert!(err.is_err());
         match err {
             Ok(_) => {}
             Err(e) => {
+                assert!(matches!(e, Error::UnsupportedFormat(_)));
                 let err_str = format!(
-                    "Page size mismatch. Header: {}, Expected: {}",
+                    "Unsupported file format: Page size mismatch. Header: {}, Expected: {}",
                     PAGE_SIZE, 5000
                 );
                 assert_eq!(e.to_string(), err_str);
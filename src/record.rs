@@ -0,0 +1,286 @@
+// Typed records
+//
+// Maps Rust structs to RBFM records, so callers don't have to build a
+// HashMap of AttributeValues for every insert. Usually implemented with
+// #[derive(Record)]:
+//
+//     #[derive(Record)]
+//     struct Player {
+//         #[record(len = 20)]
+//         name: String,
+//         level: i32,
+//         // Option fields are nullable, others are NOT NULL
+//         guild: Option<String>,
+//     }
+//
+// Field types map to attribute types through RecordField:
+// - i32: Int
+// - f64: Real
+// - String: Varchar, with a max length of #[record(len = N)] or DEFAULT_LEN
+// - Vec<u8>: Varbinary with #[record(len = N)], Blob without
+// - Uuid: Uuid
+// - Json: Json
+// - Option<T>: the same as T, but nullable
+
+use crate::attribute::*;
+use crate::error::{Error, Result};
+use crate::json::Json;
+use crate::uuid::Uuid;
+use std::collections::HashMap;
+
+pub use rust_rbf_derive::Record;
+
+/// Max length of String fields without a len option.
+pub const DEFAULT_LEN: u32 = 255;
+
+/// A type stored as a record, with one attribute per field.
+pub trait Record: Sized {
+    /// The schema for records of this type.
+    fn attributes() -> Vec<Attribute>;
+
+    /// The value of each attribute, keyed by name, for inserting.
+    fn to_values(&self) -> HashMap<String, AttributeValue>;
+
+    /// Build a value from the attribute values read from a record.
+    fn from_values(vals: HashMap<String, AttributeValue>) -> Result<Self>;
+}
+
+/// A type that can be stored in a single attribute.
+pub trait RecordField: Sized {
+    /// True if the attribute can be null.
+    const NULLABLE: bool = false;
+
+    /// The attribute type, given the field's len option.
+    fn attribute_type(len: Option<u32>) -> AttributeType;
+
+    /// The attribute value, given the field's len option.
+    fn to_value(&self, len: Option<u32>) -> AttributeValue;
+
+    /// Returns None if val has the wrong type.
+    fn from_value(val: AttributeValue) -> Option<Self>;
+}
+
+/// The attribute for a field of type T. Used by #[derive(Record)].
+pub fn field_attribute<T: RecordField>(name: &str, len: Option<u32>) -> Attribute {
+    let attr = Attribute::new(name, T::attribute_type(len));
+    if T::NULLABLE {
+        attr
+    } else {
+        attr.not_null()
+    }
+}
+
+/// Remove the value of attribute name from vals and convert it to T.
+/// Used by #[derive(Record)].
+pub fn take_value<T: RecordField>(
+    vals: &mut HashMap<String, AttributeValue>,
+    name: &str,
+) -> Result<T> {
+    let val = vals.remove(name).ok_or_else(|| Error::TypeMismatch {
+        attribute: name.to_string(),
+        message: "value is missing".to_string(),
+    })?;
+    let val_debug = format!("{:?}", val);
+    T::from_value(val).ok_or_else(|| Error::TypeMismatch {
+        attribute: name.to_string(),
+        message: format!("unexpected value {}", val_debug),
+    })
+}
+
+impl RecordField for i32 {
+    fn attribute_type(_len: Option<u32>) -> AttributeType {
+        AttributeType::Int
+    }
+
+    fn to_value(&self, _len: Option<u32>) -> AttributeValue {
+        AttributeValue::Int(*self)
+    }
+
+    fn from_value(val: AttributeValue) -> Option<Self> {
+        match val {
+            AttributeValue::Int(val) => Some(val),
+            _ => None,
+        }
+    }
+}
+
+impl RecordField for f64 {
+    fn attribute_type(_len: Option<u32>) -> AttributeType {
+        AttributeType::Real
+    }
+
+    fn to_value(&self, _len: Option<u32>) -> AttributeValue {
+        AttributeValue::Real(*self)
+    }
+
+    fn from_value(val: AttributeValue) -> Option<Self> {
+        match val {
+            AttributeValue::Real(val) => Some(val),
+            _ => None,
+        }
+    }
+}
+
+impl RecordField for String {
+    fn attribute_type(len: Option<u32>) -> AttributeType {
+        AttributeType::Varchar {
+            len: len.unwrap_or(DEFAULT_LEN),
+        }
+    }
+
+    fn to_value(&self, _len: Option<u32>) -> AttributeValue {
+        AttributeValue::Varchar(self.clone())
+    }
+
+    fn from_value(val: AttributeValue) -> Option<Self> {
+        match val {
+            AttributeValue::Varchar(val) => Some(val),
+            _ => None,
+        }
+    }
+}
+
+impl RecordField for Vec<u8> {
+    fn attribute_type(len: Option<u32>) -> AttributeType {
+        match len {
+            Some(len) => AttributeType::Varbinary { len },
+            None => AttributeType::Blob,
+        }
+    }
+
+    fn to_value(&self, len: Option<u32>) -> AttributeValue {
+        match len {
+            Some(_) => AttributeValue::Varbinary(self.clone()),
+            None => AttributeValue::Blob(self.clone()),
+        }
+    }
+
+    fn from_value(val: AttributeValue) -> Option<Self> {
+        match val {
+            AttributeValue::Varbinary(val) | AttributeValue::Blob(val) => Some(val),
+            _ => None,
+        }
+    }
+}
+
+impl RecordField for Uuid {
+    fn attribute_type(_len: Option<u32>) -> AttributeType {
+        AttributeType::Uuid
+    }
+
+    fn to_value(&self, _len: Option<u32>) -> AttributeValue {
+        AttributeValue::Uuid(*self)
+    }
+
+    fn from_value(val: AttributeValue) -> Option<Self> {
+        match val {
+            AttributeValue::Uuid(val) => Some(val),
+            _ => None,
+        }
+    }
+}
+
+impl RecordField for Json {
+    fn attribute_type(_len: Option<u32>) -> AttributeType {
+        AttributeType::Json
+    }
+
+    fn to_value(&self, _len: Option<u32>) -> AttributeValue {
+        AttributeValue::Json(self.clone())
+    }
+
+    fn from_value(val: AttributeValue) -> Option<Self> {
+        match val {
+            AttributeValue::Json(val) => Some(val),
+            _ => None,
+        }
+    }
+}
+
+impl<T: RecordField> RecordField for Option<T> {
+    const NULLABLE: bool = true;
+
+    fn attribute_type(len: Option<u32>) -> AttributeType {
+        T::attribute_type(len)
+    }
+
+    fn to_value(&self, len: Option<u32>) -> AttributeValue {
+        match self {
+            Some(val) => val.to_value(len),
+            None => AttributeValue::Null,
+        }
+    }
+
+    fn from_value(val: AttributeValue) -> Option<Self> {
+        match val {
+            AttributeValue::Null => Some(None),
+            val => T::from_value(val).map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record_based_file_mgr::*;
+    use tempfile::tempdir;
+
+    #[derive(Record, Debug, PartialEq)]
+    struct Player {
+        #[record(len = 20)]
+        name: String,
+        level: i32,
+        #[record(rename = "PowerLevel")]
+        power_level: f64,
+        guild: Option<String>,
+        #[record(len = 4)]
+        badge: Vec<u8>,
+        avatar: Option<Vec<u8>>,
+    }
+
+    #[test]
+    fn record_schema_test() {
+        let attrs = Player::attributes();
+        let names: Vec<&str> = attrs.iter().map(|attr| attr.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["name", "level", "PowerLevel", "guild", "badge", "avatar"]
+        );
+        assert_eq!(attrs[0].attribute_type, AttributeType::Varchar { len: 20 });
+        assert_eq!(
+            attrs[3].attribute_type,
+            AttributeType::Varchar { len: DEFAULT_LEN }
+        );
+        assert_eq!(attrs[4].attribute_type, AttributeType::Varbinary { len: 4 });
+        assert_eq!(attrs[5].attribute_type, AttributeType::Blob);
+        let nullable: Vec<bool> = attrs.iter().map(|attr| attr.nullable).collect();
+        assert_eq!(nullable, [false, false, false, true, false, true]);
+    }
+
+    #[test]
+    fn record_insert_read_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let mut file = RecordBasedFileMgr::create(&file_path, Player::attributes()).unwrap();
+
+        let player = Player {
+            name: "Cow".to_string(),
+            level: 28,
+            power_level: 8999.999,
+            guild: None,
+            badge: vec![1, 2],
+            avatar: Some(vec![3; 100]),
+        };
+        let rid = file.insert_record(&player).unwrap();
+        assert_eq!(file.read_record::<Player>(&rid).unwrap(), player);
+        assert_eq!(file.read(&rid).unwrap()["guild"], AttributeValue::Null);
+
+        // Records are checked against the file's schema
+        #[derive(Record, Debug)]
+        struct Other {
+            name: i32,
+        }
+        assert!(file.insert_record(&Other { name: 1 }).is_err());
+        assert!(file.read_record::<Other>(&rid).is_err());
+    }
+}
@@ -1,38 +1,214 @@
 use crate::attribute::*;
 use crate::bitmap::*;
+use crate::cast;
+use crate::compare::*;
+use crate::error::{Error, Result};
+use crate::field;
+use crate::free_space_map::*;
+use crate::fsck::FsckReport;
+use crate::json::{self, JsonPath};
+use crate::overflow::*;
 use crate::page::*;
 use crate::paged_file::*;
+use crate::record::Record;
+use crate::record_ref::{RecordRef, ValueRef};
+use crate::record_serde;
+use crate::row::Row;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::io::prelude::*;
-use std::io::Cursor;
-use std::io::Error;
-use std::io::ErrorKind;
-use std::io::Result;
-use std::io::SeekFrom;
+use std::ops::Range;
 use std::path::Path;
 
 // TODO - We should support configurable page size.
 // For now, it's easiest to keep it const.
 const PAGE_SIZE: usize = 8 * 1024;
-type Pf = PagedFile<PAGE_SIZE>;
+pub(crate) type Pf = PagedFile<PAGE_SIZE>;
 type P = Page<PAGE_SIZE>;
 
 // The following are the minimum sizes for storing the header
-const HDR_SIZE: usize = 12;
+const HDR_SIZE: usize = 16;
 const RECORD_ENTRY_SIZE: usize = 8;
 
+// Number of bytes at the start of a spilled value that stay in the record,
+// after its OverflowPointer. The overflow pages hold the rest.
+const SPILL_PREFIX_LEN: usize = 32;
+// Largest record that fits on an empty page
+const MAX_RECORD_SIZE: usize = PAGE_SIZE - HDR_SIZE - RECORD_ENTRY_SIZE;
+// Set on a field's offset header when the field holds an OverflowPointer
+// rather than the value itself. Offsets never exceed PAGE_SIZE, so the high
+// bit is free.
+const SPILLED_FLAG: u16 = 0x8000;
+// Page holding the file's schema
+const SCHEMA_PAGE_NUM: u64 = 0;
+// Stored on the schema page to identify record files
+const MAGIC: [u8; 4] = *b"RBF\0";
+// Version of the file layout, stored on the schema page. Files with another
+// version are rejected rather than misread. Bump it on any change to where
+// pages are or how they're encoded.
+// 1: schema page, then the free space map, then data pages
+// Files from before versioning, which have data from page 0 or page 1, can't
+// be upgraded in place: their pages would have to move, which would change
+// every RecordId. They fail to open with UnsupportedFormat, and have to be
+// reloaded into a new file.
+const FORMAT_VERSION: u32 = 1;
+// First page of the free space map
+const FSM_PAGE_NUM: u64 = 1;
+// Set on a slot's length when the record was relocated there by update.
+// The record is only reachable through the forwarding stub in its original
+// slot. Records never exceed PAGE_SIZE, so the high bit is free.
+const FORWARDED_FLAG: u32 = 0x8000_0000;
+
 pub struct RecordBasedFileMgr {
     paged_file: PagedFile<PAGE_SIZE>,
+    free_space_map: FreeSpaceMap<PAGE_SIZE>,
     attributes: Vec<Attribute>,
+    // Position of each attribute in attributes, by name
+    attribute_idxs: HashMap<String, usize>,
+    // Index of each attribute's field within a record. Dropped attributes
+    // leave their field unused, so records written before the drop still
+    // decode. Added attributes get a new field at the end.
+    field_idxs: Vec<usize>,
+    // Number of fields in newly written records, including unused ones
+    num_fields: usize,
+    // Cast inserted values that don't match their attribute's type
+    lenient: bool,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
 pub struct RecordId {
     pub page_num: u32,
     pub slot_num: u32,
 }
 
+/// A filter for scan, keeping records where `attribute op value` is true.
+/// Records where the comparison is Unknown, e.g. because the attribute is
+/// NULL, are skipped like in a WHERE clause.
+#[derive(Debug, Clone)]
+pub struct ScanCondition {
+    pub attribute: String,
+    /// For Json attributes, compare the value at this path instead of the
+    /// whole document. Records without a value there are skipped.
+    pub path: Option<JsonPath>,
+    pub op: CompOp,
+    pub value: AttributeValue,
+    pub collation: Collation,
+}
+
+impl ScanCondition {
+    /// A condition comparing Varchars byte-wise.
+    pub fn new(attribute: &str, op: CompOp, value: AttributeValue) -> Self {
+        Self {
+            attribute: attribute.to_string(),
+            path: None,
+            op,
+            value,
+            collation: Collation::Binary,
+        }
+    }
+
+    /// A condition on the value at path in a Json attribute, e.g. $.a.b[2].
+    /// The value is extracted as by Json::extract.
+    pub fn json_path(
+        attribute: &str,
+        path: &str,
+        op: CompOp,
+        value: AttributeValue,
+    ) -> Result<Self> {
+        Ok(Self {
+            path: Some(path.parse()?),
+            ..Self::new(attribute, op, value)
+        })
+    }
+
+    pub fn with_collation(mut self, collation: Collation) -> Self {
+        self.collation = collation;
+        self
+    }
+}
+
+/// Options for insert_batch.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchOptions {
+    /// Fraction of each new page to fill, greater than 0 and at most 1.
+    /// Leaving space lets records grow without moving to another page.
+    pub fill_factor: f64,
+    /// Only use new pages, without looking for room in existing ones.
+    /// The fastest way to load an empty or nearly full file.
+    pub append_only: bool,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            fill_factor: 1.0,
+            append_only: false,
+        }
+    }
+}
+
+// State of an insert_batch
+struct Batch {
+    fill_limit: usize,
+    // Whether to look for room in existing pages
+    search: bool,
+    rids: Vec<RecordId>,
+    // The new page being packed in memory
+    page: P,
+    hdr: SlotDirectoryHeader,
+    // Indexes in rids of the records on page
+    pending: Vec<usize>,
+}
+
+impl Batch {
+    /// The records on the packed page.
+    fn records(&self) -> Vec<&[u8]> {
+        self.hdr
+            .slots_vec
+            .iter()
+            .filter(|slot| slot.has_record())
+            .map(|slot| &self.page.as_buf()[slot.record_range()])
+            .collect()
+    }
+}
+
+/// A predicate on a view of a record, for Scan::with_filter.
+type RecordFilter<'a> = Box<dyn FnMut(&RecordRef) -> Result<bool> + 'a>;
+
+/// Iterator over the records in a file, returned by scan.
+/// Yields each record's RecordId and its projected attributes.
+pub struct Scan<'a> {
+    file: &'a mut RecordBasedFileMgr,
+    // Indexes of the attributes to return
+    projection: Vec<usize>,
+    // The condition, and the index of the attribute it tests
+    condition: Option<(usize, ScanCondition)>,
+    filter: Option<RecordFilter<'a>>,
+    // The page being scanned, and its slots
+    page_num: u64,
+    page: P,
+    slots: Vec<SlotDirectoryRecordEntry>,
+    // The next slot to visit
+    slot_num: usize,
+}
+
+/// Iterator over the records in a file as rows, returned by Scan::rows.
+/// Each row holds the projected attributes, in projection order.
+pub struct Rows<'a> {
+    scan: Scan<'a>,
+}
+
+/// Every page begins with its kind so pages can be told apart when walking
+/// the file.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub(crate) enum PageKind {
+    Data,
+    Overflow,
+    Schema,
+    FreeSpaceMap,
+}
+
 #[derive(Serialize, Deserialize)]
 struct SlotDirectoryRecordEntry {
     // Total length of the record
@@ -43,29 +219,61 @@ struct SlotDirectoryRecordEntry {
 
 enum SlotStatus {
     Valid,
+    // Valid, but reached through a forwarding stub rather than this slot
+    Forwarded,
     Dead,
     Moved(RecordId),
 }
 
 impl SlotDirectoryRecordEntry {
+    const DEAD: Self = Self {
+        length: 0,
+        offset: 0,
+    };
+
+    /// A forwarding stub for a record moved to rid.
+    /// The page number is stored in length, and the slot number in offset,
+    /// as -(slot_num + 1) so that slot 0 can't be mistaken for an offset.
+    fn moved(rid: &RecordId) -> Self {
+        Self {
+            length: rid.page_num,
+            offset: -(rid.slot_num as i32) - 1,
+        }
+    }
+
     fn status(&self) -> SlotStatus {
         if self.length == 0 && self.offset == 0 {
             SlotStatus::Dead
         } else if self.offset < 0 {
             SlotStatus::Moved(RecordId {
                 page_num: self.length,
-                slot_num: -self.offset as u32,
+                slot_num: (-(self.offset + 1)) as u32,
             })
+        } else if self.length & FORWARDED_FLAG != 0 {
+            SlotStatus::Forwarded
         } else {
             SlotStatus::Valid
         }
     }
+
+    /// True for slots holding a record on the page
+    fn has_record(&self) -> bool {
+        matches!(self.status(), SlotStatus::Valid | SlotStatus::Forwarded)
+    }
+
+    /// Range of the record's bytes in the page
+    fn record_range(&self) -> Range<usize> {
+        let offset = self.offset as usize;
+        offset..offset + (self.length & !FORWARDED_FLAG) as usize
+    }
 }
 
 // Header will begin at byte 0 of the page and grow forward
 // Records will begin at the end of the page and grow backward
 #[derive(Serialize, Deserialize)]
 struct SlotDirectoryHeader {
+    // Always PageKind::Data
+    page_kind: PageKind,
     // Points to the first used byte
     data_start_offset: u32,
     // Vec stored as a size + the entries in a compact form
@@ -74,478 +282,3972 @@ struct SlotDirectoryHeader {
     slots_vec: Vec<SlotDirectoryRecordEntry>,
 }
 
+// The schema page is the first page of the file
+#[derive(Serialize, Deserialize)]
+struct SchemaPageHeader {
+    // Always PageKind::Schema
+    page_kind: PageKind,
+    // Always MAGIC
+    magic: [u8; 4],
+    format_version: u32,
+    // One per record field. None for fields of dropped attributes.
+    attributes: Vec<Option<StoredAttribute>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredAttribute {
+    name: String,
+    attribute_type: AttributeType,
+    nullable: bool,
+    // Encoded as described in the field module.
+    // None if there's no default, or the default is Null.
+    default: Option<Vec<u8>>,
+}
+
 impl RecordBasedFileMgr {
     /// Create a new Record Based file at the given path.
+    /// The attributes are stored in the file.
     pub fn create(path: &Path, attributes: Vec<Attribute>) -> Result<Self> {
+        Self::check_attributes(&attributes)?;
+        let schema: Vec<Option<&Attribute>> = attributes.iter().map(Some).collect();
+        let schema_page = Self::schema_page(&schema)?;
         let mut paged_file = Pf::create(path)?;
-        let mut page = P::new();
-        Self::init_rb_page(&mut page);
-        paged_file.append_page(&page)?;
-        Ok(Self {
+        paged_file.append_page(&schema_page)?;
+        let free_space_map = FreeSpaceMap::create(&mut paged_file)?;
+        let mut file = Self::with_schema(
             paged_file,
-            attributes,
-        })
+            free_space_map,
+            attributes.into_iter().map(Some).collect(),
+        );
+        let mut page = P::new();
+        let hdr = Self::init_rb_page(&mut page);
+        file.write_data_page(FSM_PAGE_NUM + 1, &mut page, &hdr)?;
+        Ok(file)
     }
 
-    // Open a Record Based file at the given path.
+    /// Open a Record Based file at the given path.
+    /// Fails if attributes don't match the schema stored in the file, or
+    /// with UnsupportedFormat if the file was written in another format,
+    /// including by versions of this crate from before the free space map.
     pub fn open(path: &Path, attributes: Vec<Attribute>) -> Result<Self> {
-        let paged_file = Pf::open(path)?;
-        Ok(Self {
+        let file = Self::open_with_stored_schema(path)?;
+        file.check_schema(&attributes)?;
+        Ok(file)
+    }
+
+    /// Open a Record Based file at the given path, using the schema stored
+    /// in the file. Fails like open for files in another format.
+    pub fn open_with_stored_schema(path: &Path) -> Result<Self> {
+        let mut paged_file = Pf::open(path)?;
+        let mut page = P::new();
+        paged_file.read_page(SCHEMA_PAGE_NUM, &mut page)?;
+        let schema = Self::read_schema_page(&page).map_err(|e| e.at(SCHEMA_PAGE_NUM, None))?;
+        let free_space_map = FreeSpaceMap::open(&mut paged_file, FSM_PAGE_NUM)?;
+        Ok(Self::with_schema(paged_file, free_space_map, schema))
+    }
+
+    /// schema has an entry for each record field, which is None if the
+    /// field's attribute was dropped.
+    fn with_schema(
+        paged_file: Pf,
+        free_space_map: FreeSpaceMap<PAGE_SIZE>,
+        schema: Vec<Option<Attribute>>,
+    ) -> Self {
+        let num_fields = schema.len();
+        let (field_idxs, attributes) = schema
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, attr)| Some((i, attr?)))
+            .unzip();
+        let mut file = Self {
             paged_file,
+            free_space_map,
             attributes,
-        })
+            attribute_idxs: HashMap::new(),
+            field_idxs,
+            num_fields,
+            lenient: false,
+        };
+        file.index_attributes();
+        file
     }
 
-    /// Insert a new record to store the values from insert_vals
-    /// Returns the RecordId of the newly inserted record
-    pub fn insert(&mut self, insert_vals: &HashMap<String, AttributeValue>) -> Result<RecordId> {
-        let required_space = self.required_space(insert_vals);
-        let num_pages = self.paged_file.num_pages()?;
+    /// Rebuild attribute_idxs after the schema changes.
+    fn index_attributes(&mut self) {
+        self.attribute_idxs = self
+            .attributes
+            .iter()
+            .enumerate()
+            .map(|(i, attr)| (attr.name.clone(), i))
+            .collect();
+    }
 
-        let mut page = P::new();
-        let mut page_found = false;
-        let mut page_num = 0;
-        for i in 0..num_pages {
-            self.paged_file.read_page(i, &mut page)?;
-            let hdr = Self::get_slot_directory_hdr(&page);
-            if Self::free_space(&hdr) < required_space {
-                continue;
-            }
-            page_found = true;
-            page_num = i;
-        }
+    /// The file's schema.
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attributes
+    }
 
-        if !page_found {
-            page_num = num_pages;
-            Self::init_rb_page(&mut page);
+    /// Check that attributes match the file's schema: the same names and
+    /// types in the same order, with the same constraints.
+    pub fn check_schema(&self, attributes: &[Attribute]) -> Result<()> {
+        let mismatch = |msg: String| Err(Error::invalid_input(format!("Schema mismatch: {}", msg)));
+        if attributes.len() != self.attributes.len() {
+            return mismatch(format!(
+                "{} attributes given, but the file has {}",
+                attributes.len(),
+                self.attributes.len()
+            ));
+        }
+        // Null defaults behave the same as no default
+        let default = |attr: &Attribute| match &attr.default {
+            Some(AttributeValue::Null) | None => None,
+            Some(default) => Some(default.clone()),
+        };
+        for (i, (given, stored)) in attributes.iter().zip(self.attributes.iter()).enumerate() {
+            if given.name != stored.name || given.attribute_type != stored.attribute_type {
+                return mismatch(format!(
+                    "attribute {} is {} {}, but the file has {} {}",
+                    i, given.name, given.attribute_type, stored.name, stored.attribute_type
+                ));
+            }
+            if given.nullable != stored.nullable || default(given) != default(stored) {
+                return mismatch(format!(
+                    "attribute {} has different constraints in the file",
+                    given.name
+                ));
+            }
         }
+        Ok(())
+    }
 
-        let mut slot_dir_hdr = Self::get_slot_directory_hdr(&page);
-        let rid = RecordId {
-            page_num: page_num as u32,
-            slot_num: slot_dir_hdr.slots_vec.len() as u32,
+    /// Add an attribute to the end of the schema.
+    /// Existing records aren't rewritten. They read back with the
+    /// attribute's default until they're migrated.
+    pub fn add_attribute(&mut self, attribute: Attribute) -> Result<()> {
+        Self::check_attributes(std::slice::from_ref(&attribute))?;
+        let invalid = |msg: &str| {
+            Err(Error::invalid_input(format!(
+                "Attribute {} {}",
+                attribute.name, msg
+            )))
         };
+        if self.attribute_idxs.contains_key(&attribute.name) {
+            return invalid("already exists");
+        }
+        match &attribute.default {
+            None | Some(AttributeValue::Null) if !attribute.nullable => {
+                return invalid("needs a default to be NOT NULL");
+            }
+            // Migrating a record writes the default inline
+            Some(default) if Self::is_spilled(&attribute.attribute_type, default)? => {
+                return invalid("has a default too large to store inline");
+            }
+            _ => {}
+        }
 
-        let starting_offset = slot_dir_hdr.data_start_offset - required_space as u32;
-        slot_dir_hdr.data_start_offset = starting_offset;
-        slot_dir_hdr.slots_vec.push(SlotDirectoryRecordEntry {
-            length: required_space as u32,
-            offset: starting_offset as i32,
-        });
+        self.attributes.push(attribute);
+        self.field_idxs.push(self.num_fields);
+        self.num_fields += 1;
+        if let Err(e) = self.write_schema() {
+            self.attributes.pop();
+            self.field_idxs.pop();
+            self.num_fields -= 1;
+            return Err(e);
+        }
+        self.index_attributes();
+        Ok(())
+    }
 
-        Self::write_slot_directory_hdr(&mut page, &slot_dir_hdr);
+    /// Remove an attribute from the schema.
+    /// Existing records aren't rewritten. The attribute's data is skipped
+    /// when reading, and removed when the record is migrated.
+    pub fn drop_attribute(&mut self, name: &str) -> Result<()> {
+        let attr_idx = self.attribute_index(name)?;
+        let attr = self.attributes.remove(attr_idx);
+        let field_idx = self.field_idxs.remove(attr_idx);
+        if let Err(e) = self.write_schema() {
+            self.attributes.insert(attr_idx, attr);
+            self.field_idxs.insert(attr_idx, field_idx);
+            return Err(e);
+        }
+        self.index_attributes();
+        Ok(())
+    }
 
-        // Struct fields are written to disk and should be fixed sizes
-        // But it's easier to work with usizes for slicing
-        let starting_offset = starting_offset as usize;
+    fn write_schema(&mut self) -> Result<()> {
+        let mut schema = vec![None; self.num_fields];
+        for (attr, field_idx) in self.attributes.iter().zip(self.field_idxs.iter()) {
+            schema[*field_idx] = Some(attr);
+        }
+        let page = Self::schema_page(&schema)?;
+        self.paged_file.write_page(SCHEMA_PAGE_NUM, &page)
+    }
 
-        let bytes_written = self
-            .write_record_into_buf(
-                &mut page.as_mut_buf()[starting_offset..starting_offset + required_space],
-                insert_vals,
-            )
-            .unwrap();
+    fn schema_page(schema: &[Option<&Attribute>]) -> Result<P> {
+        let hdr = SchemaPageHeader {
+            page_kind: PageKind::Schema,
+            magic: MAGIC,
+            format_version: FORMAT_VERSION,
+            attributes: schema
+                .iter()
+                .map(|attr| {
+                    attr.map(|attr| {
+                        Ok(StoredAttribute {
+                            name: attr.name.clone(),
+                            attribute_type: attr.attribute_type.clone(),
+                            nullable: attr.nullable,
+                            default: match &attr.default {
+                                Some(AttributeValue::Null) | None => None,
+                                Some(default) => {
+                                    Some(field::encode(&attr.attribute_type, default)?)
+                                }
+                            },
+                        })
+                    })
+                    .transpose()
+                })
+                .collect::<Result<_>>()?,
+        };
+        if bincode::serialized_size(&hdr).unwrap() as usize > PAGE_SIZE {
+            return Err(Error::invalid_input(
+                "Schema is too large to store in a page",
+            ));
+        }
+        let mut page = P::new();
+        bincode::serialize_into(&mut page.as_mut_buf()[..], &hdr).unwrap();
+        Ok(page)
+    }
 
-        debug_assert_eq!(bytes_written, self.record_size(insert_vals).unwrap());
+    /// Fails with UnsupportedFormat if the page isn't a schema page of the
+    /// current format version, e.g. because the file was written before
+    /// versions were stored.
+    fn read_schema_page(page: &P) -> Result<Vec<Option<Attribute>>> {
+        let (page_kind, magic, format_version): (PageKind, [u8; 4], u32) =
+            bincode::deserialize(page.as_buf()).map_err(|_| Self::unversioned())?;
+        if page_kind != PageKind::Schema || magic != MAGIC {
+            return Err(Self::unversioned());
+        }
+        if format_version != FORMAT_VERSION {
+            return Err(Error::UnsupportedFormat(format!(
+                "Format version {}, expected {}",
+                format_version, FORMAT_VERSION
+            )));
+        }
+        let hdr: SchemaPageHeader = bincode::deserialize(page.as_buf())
+            .map_err(|_| Error::corrupt("Invalid schema page"))?;
+        hdr.attributes
+            .into_iter()
+            .map(|stored| {
+                let stored = match stored {
+                    Some(stored) => stored,
+                    None => return Ok(None),
+                };
+                let default = match &stored.default {
+                    Some(bytes) => Some(field::decode(&stored.attribute_type, bytes)?),
+                    None => None,
+                };
+                Ok(Some(Attribute {
+                    name: stored.name,
+                    attribute_type: stored.attribute_type,
+                    nullable: stored.nullable,
+                    default,
+                }))
+            })
+            .collect()
+    }
 
-        if page_found {
-            self.paged_file.write_page(page_num, &page)?;
-        } else {
-            self.paged_file.append_page(&page)?;
+    /// Check that each attribute's default is valid for the attribute.
+    fn check_attributes(attributes: &[Attribute]) -> Result<()> {
+        for attr in attributes.iter() {
+            let valid = match &attr.default {
+                None => true,
+                Some(AttributeValue::Null) => attr.nullable,
+                Some(default) => field::type_matches_value(&attr.attribute_type, default),
+            };
+            if !valid {
+                return Err(Error::invalid_input(format!(
+                    "Invalid default for attribute {}",
+                    attr.name
+                )));
+            }
         }
+        Ok(())
+    }
 
-        Ok(rid)
+    /// In lenient mode, insert casts values that don't match their
+    /// attribute's type using the explicit rules from the cast module,
+    /// instead of rejecting them. Off by default.
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
     }
 
-    /// Reads the record with RecordId rid and returns a HashMap mapping
-    /// attribute name to value.
-    pub fn read(&mut self, rid: &RecordId) -> Result<HashMap<String, AttributeValue>> {
-        let mut page = P::new();
-        self.paged_file.read_page(rid.page_num as u64, &mut page)?;
+    /// Insert a new record to store the values from insert_vals
+    /// Attributes missing from insert_vals get their default, or NULL if
+    /// they have none.
+    /// Returns the RecordId of the newly inserted record
+    pub fn insert(&mut self, insert_vals: &HashMap<String, AttributeValue>) -> Result<RecordId> {
+        let insert_vals = Self::prepare_values(&self.attributes, insert_vals, self.lenient)?;
+        self.insert_prepared(&insert_vals)
+    }
 
-        let hdr = Self::get_slot_directory_hdr(&page);
-        if hdr.slots_vec.len() <= rid.slot_num as usize {
-            return Err(Error::new(ErrorKind::InvalidInput, "Slot does not exist"));
+    /// Insert values already resolved by prepare_values.
+    fn insert_prepared(&mut self, insert_vals: &[AttributeValue]) -> Result<RecordId> {
+        let record = self.build_record(insert_vals)?;
+        let result = self.insert_bytes(&record, false);
+        if result.is_err() {
+            self.discard_overflow(&[&record]);
         }
-
-        let slot = hdr.slots_vec.get(rid.slot_num as usize).unwrap();
-        return match slot.status() {
-            SlotStatus::Dead => Err(Error::new(ErrorKind::InvalidData, "Record deleted")),
-            SlotStatus::Moved(rid) => self.read(&rid),
-            SlotStatus::Valid => self.read_record_from_buf(
-                &page.as_buf()[slot.offset as usize..slot.offset as usize + slot.length as usize],
-            ),
-        };
+        result
     }
 
-    /// Initialize a new Page for use by RBFM
-    fn init_rb_page(page: &mut P) {
-        // First we 0 out the buffer
-        page.as_mut_buf().iter_mut().for_each(|i| *i = 0);
-        // Then we write the header
-        let hdr = SlotDirectoryHeader {
-            data_start_offset: PAGE_SIZE as u32,
-            slots_vec: vec![],
-        };
-        bincode::serialize_into(&mut page.as_mut_buf()[0..HDR_SIZE], &hdr).unwrap();
+    /// Encode values resolved by prepare_values as a record, writing the
+    /// values that don't fit on a page to overflow pages.
+    /// If the record isn't stored, its overflow pages must be freed with
+    /// discard_overflow.
+    fn build_record(&mut self, vals: &[AttributeValue]) -> Result<Vec<u8>> {
+        let (spilled, _) = self.field_layout(vals)?;
+        let mut fields = vec![None; self.num_fields];
+        // Chains written so far, freed if a later one fails
+        let mut chains = vec![];
+        for (i, attr_val) in vals.iter().enumerate() {
+            if let AttributeValue::Null = attr_val {
+                continue;
+            }
+            let bytes = match field::encode(&self.attributes[i].attribute_type, attr_val) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    for ptr in chains.iter() {
+                        let _ = self.free_chain(ptr);
+                    }
+                    return Err(e);
+                }
+            };
+            fields[self.field_idxs[i]] = Some(if spilled[i] {
+                let ptr = match self.write_overflow(&bytes[SPILL_PREFIX_LEN..]) {
+                    Ok(ptr) => ptr,
+                    Err(e) => {
+                        for ptr in chains.iter() {
+                            let _ = self.free_chain(ptr);
+                        }
+                        return Err(e);
+                    }
+                };
+                chains.push(ptr);
+                let mut field = ptr.to_bytes().to_vec();
+                field.extend_from_slice(&bytes[..SPILL_PREFIX_LEN]);
+                (field, true)
+            } else {
+                (bytes, false)
+            });
+        }
+        Ok(Self::assemble_record(&fields))
     }
 
-    fn get_slot_directory_hdr(page: &P) -> SlotDirectoryHeader {
-        bincode::deserialize(page.as_buf()).unwrap()
+    /// Free the overflow pages of records built by build_record that couldn't
+    /// be stored. Errors are ignored, since the caller is already failing
+    /// with one, and at worst the pages stay unused.
+    fn discard_overflow(&mut self, records: &[&[u8]]) {
+        for record in records.iter() {
+            let _ = self.free_overflow(record);
+        }
     }
 
-    fn write_slot_directory_hdr(page: &mut P, hdr: &SlotDirectoryHeader) {
-        bincode::serialize_into(&mut page.as_mut_buf()[..], &hdr).unwrap();
+    /// Encode values resolved by prepare_values as a record in this file's
+    /// field layout, without overflow pages.
+    /// Fails if the record doesn't fit on a page.
+    pub(crate) fn encode_inline(&self, vals: &[AttributeValue]) -> Result<Vec<u8>> {
+        let (spilled, _) = self.field_layout(vals)?;
+        if let Some(i) = spilled.iter().position(|spilled| *spilled) {
+            return Err(Error::invalid_input(format!(
+                "Attribute {} is too large to store inline",
+                self.attributes[i].name
+            )));
+        }
+        let mut fields = vec![None; self.num_fields];
+        for (i, attr_val) in vals.iter().enumerate() {
+            if !matches!(attr_val, AttributeValue::Null) {
+                let bytes = field::encode(&self.attributes[i].attribute_type, attr_val)?;
+                fields[self.field_idxs[i]] = Some((bytes, false));
+            }
+        }
+        Ok(Self::assemble_record(&fields))
     }
 
-    fn free_space(hdr: &SlotDirectoryHeader) -> usize {
-        let hdr_size = bincode::serialized_size(hdr).unwrap() as usize;
-        hdr.data_start_offset as usize - hdr_size
+    /// Decode a record in this file's field layout, such as one from
+    /// encode_inline. Fails if a value is stored in overflow pages.
+    pub(crate) fn decode_inline(&self, buf: &[u8]) -> Result<HashMap<String, AttributeValue>> {
+        let hdr = RecordHeader::parse(buf)?;
+        let mut vals = HashMap::new();
+        for (attr, field_idx) in self.attributes.iter().zip(self.field_idxs.iter()) {
+            let attr_val = if *field_idx >= hdr.num_attributes {
+                attr.default.clone().unwrap_or(AttributeValue::Null)
+            } else {
+                match hdr.field_range(*field_idx, buf.len())? {
+                    None => AttributeValue::Null,
+                    Some((range, false)) => field::decode(&attr.attribute_type, &buf[range])?,
+                    Some((_, true)) => {
+                        return Err(Error::invalid_input(format!(
+                            "Attribute {} is stored in overflow pages",
+                            attr.name
+                        )))
+                    }
+                }
+            };
+            vals.insert(attr.name.clone(), attr_val);
+        }
+        Ok(vals)
     }
 
-    fn required_space(&self, insert_vals: &HashMap<String, AttributeValue>) -> usize {
-        // Overhead:
-        // SlotDirectoryRecordEntry: 8 bytes
-        // Total
-        // Overhead + Record size
-        RECORD_ENTRY_SIZE + self.record_size(insert_vals).unwrap()
+    /// Write data to a chain of overflow pages, reusing empty data pages, e.g.
+    /// those of freed chains, before extending the file.
+    fn write_overflow(&mut self, data: &[u8]) -> Result<OverflowPointer> {
+        let count = num_chunks::<PAGE_SIZE>(data.len());
+        let mut pages = Vec::with_capacity(count);
+        let mut page = P::new();
+        let mut start = FSM_PAGE_NUM + 1;
+        while pages.len() < count {
+            let page_num =
+                match self
+                    .free_space_map
+                    .find(&mut self.paged_file, MAX_RECORD_SIZE, start)?
+                {
+                    Some(page_num) if page_num < self.paged_file.num_pages()? => page_num,
+                    _ => break,
+                };
+            start = page_num + 1;
+            self.paged_file.read_page(page_num, &mut page)?;
+            // Pages with dead slots keep them, so their RecordIds stay deleted
+            if Self::get_page_kind(&page, page_num)? == PageKind::Data
+                && Self::get_slot_directory_hdr(&page, page_num)?
+                    .slots_vec
+                    .is_empty()
+            {
+                pages.push(page_num as u32);
+            }
+        }
+        let reused = pages.len();
+        let num_pages = self.paged_file.num_pages()?;
+        pages.extend((0..(count - reused) as u64).map(|i| (num_pages + i) as u32));
+
+        let ptr = write_chain(&mut self.paged_file, data, &pages)?;
+        // Overflow pages have no room for records
+        for page_num in pages[..reused].iter() {
+            self.free_space_map
+                .set(&mut self.paged_file, *page_num as u64, 0)?;
+        }
+        Ok(ptr)
     }
 
-    /// Calculate the length of the null bitmap in bytes
-    fn null_bitmap_len(attrs_len: usize) -> usize {
-        Bitmap::bmp_size_in_bytes(attrs_len as usize)
+    /// Store an encoded record on the first page with room, appending a page
+    /// if none has. The record gets the page's first dead slot, or a new one.
+    /// Forwarded records are marked as only reachable through a stub.
+    fn insert_bytes(&mut self, record: &[u8], forwarded: bool) -> Result<RecordId> {
+        let mut page = P::new();
+        let (page_num, mut slot_dir_hdr) = match self.find_page(record.len(), &mut page)? {
+            Some(found) => found,
+            None => (self.paged_file.num_pages()?, Self::init_rb_page(&mut page)),
+        };
+        let slot_num = Self::add_record(&mut page, &mut slot_dir_hdr, record, forwarded);
+        self.write_data_page(page_num, &mut page, &slot_dir_hdr)?;
+        Ok(RecordId {
+            page_num: page_num as u32,
+            slot_num: slot_num as u32,
+        })
     }
 
-    fn record_size(&self, insert_vals: &HashMap<String, AttributeValue>) -> Result<usize> {
-        // Record Format:
-        // Num_Attributes: 2 byte unsigned int
-        // Null_Bitmap: Variable length bitmap, byte length is ceil(num_attributes / 8)
-        // Offset_Headers: 2 bytes for each non-null attribute
-        // Data: 4 bytes for each int, 8 bytes for each real, variable length varchar
-        let bmp_len = Self::null_bitmap_len(self.attributes.len());
+    /// Find the first data page with room for a record of record_len bytes
+    /// using the free space map, and read it into page.
+    /// Returns the page number and slot directory, or None if no page has
+    /// room.
+    fn find_page(
+        &mut self,
+        record_len: usize,
+        page: &mut P,
+    ) -> Result<Option<(u64, SlotDirectoryHeader)>> {
+        let mut start = 0;
+        while let Some(page_num) =
+            self.free_space_map
+                .find(&mut self.paged_file, record_len, start)?
+        {
+            // The map only gives candidates, so check the page has room
+            self.paged_file.read_page(page_num, page)?;
+            let insert_space = match Self::get_page_kind(page, page_num)? {
+                PageKind::Data => {
+                    let hdr = Self::get_slot_directory_hdr(page, page_num)?;
+                    let insert_space = Self::insert_space(&hdr);
+                    if insert_space >= record_len {
+                        return Ok(Some((page_num, hdr)));
+                    }
+                    insert_space
+                }
+                _ => 0,
+            };
+            self.free_space_map
+                .set(&mut self.paged_file, page_num, insert_space)?;
+            start = page_num + 1;
+        }
+        Ok(None)
+    }
 
-        let num_attributes_len: usize = 2;
+    /// Store a record on a page known to have room for it, in the page's
+    /// first dead slot or a new one. Returns the slot number.
+    fn add_record(
+        page: &mut P,
+        hdr: &mut SlotDirectoryHeader,
+        record: &[u8],
+        forwarded: bool,
+    ) -> usize {
+        let slot_num = match Self::dead_slot(hdr) {
+            Some(slot_num) => slot_num,
+            None => {
+                hdr.slots_vec.push(SlotDirectoryRecordEntry::DEAD);
+                hdr.slots_vec.len() - 1
+            }
+        };
+        let placed = Self::place_record(page, hdr, slot_num, record, forwarded);
+        debug_assert!(placed);
+        slot_num
+    }
 
-        let mut offset_headers_len: usize = 0;
-        let mut data_len: usize = 0;
+    /// Insert many records, returning their RecordIds in order.
+    /// Records go into existing pages with room, like insert, until one
+    /// doesn't fit in any. The rest are packed into new pages in memory,
+    /// and each page is appended once full, so it's only written once.
+    /// Every record is checked before any is written.
+    pub fn insert_batch(
+        &mut self,
+        records: &[HashMap<String, AttributeValue>],
+        options: BatchOptions,
+    ) -> Result<Vec<RecordId>> {
+        if !(options.fill_factor > 0.0 && options.fill_factor <= 1.0) {
+            return Err(Error::invalid_input(
+                "Fill factor must be greater than 0 and at most 1",
+            ));
+        }
+        let records = records
+            .iter()
+            .map(|vals| Self::prepare_values(&self.attributes, vals, self.lenient))
+            .collect::<Result<Vec<_>>>()?;
+        for vals in records.iter() {
+            self.field_layout(vals)?;
+        }
 
-        for attr in self.attributes.iter() {
-            // No change in size for null values
-            if !insert_vals.contains_key(&attr.name) {
-                continue;
+        let mut page = P::new();
+        let hdr = Self::init_rb_page(&mut page);
+        let mut batch = Batch {
+            // Bytes of each new page to use, including the slot directory
+            fill_limit: (PAGE_SIZE as f64 * options.fill_factor) as usize,
+            search: !options.append_only,
+            rids: Vec::with_capacity(records.len()),
+            page,
+            hdr,
+            pending: vec![],
+        };
+        for vals in records.iter() {
+            let record = self.build_record(vals)?;
+            if let Err(e) = self.add_to_batch(&mut batch, &record) {
+                // Neither this record nor those on the packed page were
+                // written, so their overflow pages are unused
+                let mut unwritten = batch.records();
+                unwritten.push(&record);
+                self.discard_overflow(&unwritten);
+                return Err(e);
             }
-            // All non-null fields have a 2 byte offset header
-            offset_headers_len += 2;
-
-            let attr_val = insert_vals.get(&attr.name).unwrap();
-            if !Self::attribute_type_matches_value(&attr.attribute_type, attr_val) {
-                return Err(Error::new(
-                    ErrorKind::InvalidInput,
-                    "Attribute type mismatch",
-                ));
+        }
+        if !batch.pending.is_empty() {
+            if let Err(e) = self.append_batch_page(&mut batch) {
+                self.discard_overflow(&batch.records());
+                return Err(e);
             }
-            match attr_val {
-                // All ints are 4 bytes
-                AttributeValue::Int(_) => {
-                    data_len += 4;
-                }
-                // All reals are 8 bytes
-                AttributeValue::Real(_) => {
-                    data_len += 8;
-                }
-                // Get the length of the actual string value
-                AttributeValue::Varchar(val) => {
-                    data_len += val.as_bytes().len();
+        }
+        Ok(batch.rids)
+    }
+
+    /// Store a record for insert_batch, in an existing page while the batch
+    /// is still searching them, otherwise on the packed page.
+    fn add_to_batch(&mut self, batch: &mut Batch, record: &[u8]) -> Result<()> {
+        if batch.search {
+            let mut existing = P::new();
+            match self.find_page(record.len(), &mut existing)? {
+                Some((page_num, mut existing_hdr)) => {
+                    let slot_num =
+                        Self::add_record(&mut existing, &mut existing_hdr, record, false);
+                    self.write_data_page(page_num, &mut existing, &existing_hdr)?;
+                    batch.rids.push(RecordId {
+                        page_num: page_num as u32,
+                        slot_num: slot_num as u32,
+                    });
+                    return Ok(());
                 }
+                // The rest of the batch is unlikely to find room either
+                None => batch.search = false,
             }
         }
 
-        Ok(bmp_len + num_attributes_len + offset_headers_len + data_len)
+        let required_space = record.len() + RECORD_ENTRY_SIZE;
+        let used_space = PAGE_SIZE - Self::free_space(&batch.hdr);
+        if !batch.pending.is_empty()
+            && (used_space + required_space > batch.fill_limit
+                || Self::free_space(&batch.hdr) < required_space)
+        {
+            self.append_batch_page(batch)?;
+            batch.hdr = Self::init_rb_page(&mut batch.page);
+            batch.pending.clear();
+        }
+        let slot_num = Self::add_record(&mut batch.page, &mut batch.hdr, record, false);
+        batch.pending.push(batch.rids.len());
+        // The page number is known once the page is appended
+        batch.rids.push(RecordId {
+            page_num: 0,
+            slot_num: slot_num as u32,
+        });
+        Ok(())
     }
 
-    /// True if AttributeValue matches the expected type based on AttributeType
-    fn attribute_type_matches_value(attr_type: &AttributeType, attr_val: &AttributeValue) -> bool {
-        match attr_type {
-            AttributeType::Int => {
-                matches!(attr_val, AttributeValue::Int(_))
-            }
-            AttributeType::Real => {
-                matches!(attr_val, AttributeValue::Real(_))
-            }
-            AttributeType::Varchar { len: max } => match attr_val {
-                AttributeValue::Varchar(s) => s.len() <= *max as usize,
-                _ => false,
-            },
+    /// Append the page packed by insert_batch, and fill in the page number of
+    /// the RecordIds of the records on it.
+    fn append_batch_page(&mut self, batch: &mut Batch) -> Result<()> {
+        let page_num = self.paged_file.num_pages()?;
+        self.write_data_page(page_num, &mut batch.page, &batch.hdr)?;
+        for i in batch.pending.iter() {
+            batch.rids[*i].page_num = page_num as u32;
         }
+        Ok(())
     }
 
-    fn write_record_into_buf(
-        &self,
-        buf: &mut [u8],
+    /// Resolve the value to store for every attribute: fill in defaults,
+    /// cast values in lenient mode, and enforce NOT NULL.
+    /// Returns the values in attribute order.
+    pub(crate) fn prepare_values(
+        attributes: &[Attribute],
         insert_vals: &HashMap<String, AttributeValue>,
-    ) -> Result<usize> {
-        // Record Format:
-        // Num_Attributes: 2 byte unsigned int
-        // Null_Bitmap: Variable length bitmap, byte length is ceil(num_attributes / 8)
-        // Offset_Headers: 2 bytes for each non-null attribute
-        // Data: 4 bytes for each int, 8 bytes for each real, variable length varchar
-        let num_attributes = self.attributes.len() as u16;
-        let bmp_len = Self::null_bitmap_len(self.attributes.len());
-        let mut bmp = Bitmap::new(num_attributes as usize);
-        let mut valid_cnt: usize = 0;
-
-        let mut cursor = Cursor::new(buf);
-        let mut bytes_written = 0;
-
-        // First iteration, determine what is null/valid
-        for (i, attr) in self.attributes.iter().enumerate() {
-            if !insert_vals.contains_key(&attr.name) {
-                continue;
+        lenient: bool,
+    ) -> Result<Vec<AttributeValue>> {
+        if let Some(name) = insert_vals
+            .keys()
+            .find(|name| !attributes.iter().any(|attr| &attr.name == *name))
+        {
+            return Err(Error::UnknownAttribute {
+                attribute: name.clone(),
+            });
+        }
+
+        let mut vals = Vec::with_capacity(attributes.len());
+        for attr in attributes.iter() {
+            let attr_val = match insert_vals.get(&attr.name).or(attr.default.as_ref()) {
+                Some(attr_val) => attr_val,
+                None => &AttributeValue::Null,
+            };
+            vals.push(Self::prepare_value(attr, attr_val, lenient)?);
+        }
+        Ok(vals)
+    }
+
+    /// Resolve the value to store for a single attribute: cast it in lenient
+    /// mode, and enforce NOT NULL.
+    fn prepare_value(
+        attr: &Attribute,
+        attr_val: &AttributeValue,
+        lenient: bool,
+    ) -> Result<AttributeValue> {
+        match attr_val {
+            AttributeValue::Null if !attr.nullable => Err(Error::NotNullViolation {
+                attribute: attr.name.clone(),
+            }),
+            // Lenient mode doesn't truncate Varchars that are too long
+            AttributeValue::Varchar(_)
+                if matches!(attr.attribute_type, AttributeType::Varchar { .. })
+                    && !field::type_matches_value(&attr.attribute_type, attr_val) =>
+            {
+                Err(Self::type_error(attr, attr_val))
             }
-            bmp.set(i);
-            valid_cnt += 1;
-        }
-        // At this point, our null bmp is ready
-        // and we know the number of offset headers
-        let mut offset_hdrs = vec![0_u16; valid_cnt as usize];
-
-        // num_attributes = 2 bytes
-        // + bmp_len in bytes
-        // + 2 bytes for each valid entry
-        let mut data_offset = 2 + bmp_len + valid_cnt * 2;
-        cursor.seek(SeekFrom::Start(data_offset as u64))?;
-
-        // idx is the non-null index
-        let mut idx = 0;
-        // i includes nulls
-        for (i, attr) in self.attributes.iter().enumerate() {
-            // Skip nulls
-            if !bmp.get(i) {
-                continue;
+            attr_val if lenient && !field::type_matches_value(&attr.attribute_type, attr_val) => {
+                cast::cast(attr_val, &attr.attribute_type).map_err(|e| Error::TypeMismatch {
+                    attribute: attr.name.clone(),
+                    message: e.to_string(),
+                })
             }
-            // Guaranteed to be valid because field is non-null
-            let attr_val = insert_vals.get(&attr.name).unwrap();
-            if !Self::attribute_type_matches_value(&attr.attribute_type, attr_val) {
-                return Err(Error::new(
-                    ErrorKind::InvalidInput,
-                    "Attribute type mismatch",
-                ));
+            attr_val => Ok(attr_val.clone()),
+        }
+    }
+
+    /// The error for a value that doesn't fit attr's type.
+    fn type_error(attr: &Attribute, attr_val: &AttributeValue) -> Error {
+        match (&attr.attribute_type, attr_val) {
+            (AttributeType::Varchar { len: max }, AttributeValue::Varchar(s)) => {
+                Error::VarcharOverflow {
+                    attribute: attr.name.clone(),
+                    len: s.len(),
+                    max: *max,
+                }
             }
-            // Convert the attribute value to bytes,
-            // then write to the buffer
-            let bytes = match attr_val {
-                AttributeValue::Int(val) => val.to_le_bytes().to_vec(),
-                AttributeValue::Real(val) => val.to_le_bytes().to_vec(),
-                // Inverse is from_utf8 for reading
-                AttributeValue::Varchar(val) => val.as_bytes().to_vec(),
-            };
-            cursor.write_all(&bytes[..]).unwrap();
-            // Offset headers point to END of value
-            // so we update the offset first, then the header
-            data_offset += bytes.len();
-            bytes_written += bytes.len();
-            offset_hdrs[idx] = data_offset as u16;
-            idx += 1;
+            (attr_type, attr_val) => Error::TypeMismatch {
+                attribute: attr.name.clone(),
+                message: format!(
+                    "expected {}, found {}",
+                    attr_type,
+                    cast::type_name(attr_val)
+                ),
+            },
         }
+    }
 
-        // Write headers
-        cursor.seek(SeekFrom::Start(0))?;
+    /// Insert a row with a value for every attribute, in schema order.
+    /// Defaults aren't applied: a None value is NULL.
+    pub fn insert_row(&mut self, row: &Row) -> Result<RecordId> {
+        if row.len() != self.attributes.len() {
+            return Err(Error::invalid_input(format!(
+                "Row has {} values, but the file has {} attributes",
+                row.len(),
+                self.attributes.len()
+            )));
+        }
+        let insert_vals = self
+            .attributes
+            .iter()
+            .zip(row.values().iter())
+            .map(|(attr, attr_val)| {
+                let attr_val = attr_val.as_ref().unwrap_or(&AttributeValue::Null);
+                Self::prepare_value(attr, attr_val, self.lenient)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.insert_prepared(&insert_vals)
+    }
 
-        let bytes = num_attributes.to_le_bytes();
-        cursor.write_all(&bytes)?;
-        bytes_written += bytes.len();
+    /// A row holding each attribute's default, to fill in before insert_row.
+    pub fn default_row(&self) -> Row {
+        Row::new(
+            self.attributes
+                .iter()
+                .map(|attr| match &attr.default {
+                    Some(AttributeValue::Null) | None => None,
+                    Some(default) => Some(default.clone()),
+                })
+                .collect(),
+        )
+    }
 
-        let bytes = bmp.into_bytes();
-        cursor.write_all(&bytes)?;
-        bytes_written += bytes.len();
+    /// Insert a typed record. See the record module.
+    pub fn insert_record<R: Record>(&mut self, record: &R) -> Result<RecordId> {
+        self.insert(&record.to_values())
+    }
 
-        for offset_hdr in offset_hdrs.iter() {
-            let bytes = offset_hdr.to_le_bytes();
-            cursor.write_all(&bytes)?;
-            bytes_written += bytes.len();
-        }
+    /// Insert any serializable struct or map, converting each field to its
+    /// attribute's type with the explicit casting rules. See record_serde.
+    pub fn insert_serialized<T: Serialize>(&mut self, value: &T) -> Result<RecordId> {
+        let insert_vals = record_serde::to_attribute_values(value, &self.attributes)?;
+        self.insert_prepared(&insert_vals)
+    }
 
-        Ok(bytes_written)
+    /// Read the record with RecordId rid into any deserializable type.
+    pub fn read_deserialized<T: DeserializeOwned>(&mut self, rid: &RecordId) -> Result<T> {
+        record_serde::from_values(self.read(rid)?)
     }
 
-    fn read_record_from_buf(&self, buf: &[u8]) -> Result<HashMap<String, AttributeValue>> {
-        // Record Format:
-        // Num_Attributes: 2 byte unsigned int
-        // Null_Bitmap: Variable length bitmap, byte length is ceil(num_attributes / 8)
-        // Offset_Headers: 2 bytes for each non-null attribute
-        // Data: 4 bytes for each int, 8 bytes for each real, variable length varchar
-        let mut cursor = Cursor::new(buf);
-        let mut num_attributes_bytes = [0; 2];
-        cursor.read_exact(&mut num_attributes_bytes)?;
-        let num_attributes = u16::from_le_bytes(num_attributes_bytes);
+    /// Read the record with RecordId rid as a typed record.
+    pub fn read_record<R: Record>(&mut self, rid: &RecordId) -> Result<R> {
+        R::from_values(self.read(rid)?)
+    }
+
+    /// Reads the record with RecordId rid and returns a HashMap mapping
+    /// attribute name to value. Null attributes map to AttributeValue::Null.
+    pub fn read(&mut self, rid: &RecordId) -> Result<HashMap<String, AttributeValue>> {
+        let (loc, page, slot) = self.find_record(rid)?;
+        self.read_record_from_buf(&page.as_buf()[slot])
+            .map_err(|e| e.at_record(&loc))
+    }
 
-        let bmp_len = Bitmap::bmp_size_in_bytes(num_attributes as usize);
-        let mut bmp_vec = vec![0; bmp_len];
-        cursor.read_exact(&mut bmp_vec)?;
-        let bmp = Bitmap::new_with_vec(num_attributes as usize, bmp_vec);
+    /// Reads the record with RecordId rid as a row, in schema order.
+    pub fn read_row(&mut self, rid: &RecordId) -> Result<Row> {
+        let (loc, page, slot) = self.find_record(rid)?;
+        let all: Vec<usize> = (0..self.attributes.len()).collect();
+        self.read_fields(&page.as_buf()[slot], &all)
+            .map_err(|e| e.at_record(&loc))
+    }
 
-        let mut offset_hdrs = Vec::<u16>::new();
+    /// Reads the value of attribute name from the record with RecordId rid.
+    /// Only that field is decoded, located through the record's null bitmap
+    /// and offset headers.
+    pub fn read_attribute(&mut self, rid: &RecordId, name: &str) -> Result<AttributeValue> {
+        let attr_idx = self.attribute_index(name)?;
+        let (loc, page, slot) = self.find_record(rid)?;
+        let buf = &page.as_buf()[slot];
+        RecordHeader::parse(buf)
+            .and_then(|hdr| self.read_field(buf, &hdr, attr_idx))
+            .map_err(|e| e.at_record(&loc))
+    }
 
-        for i in 0..num_attributes {
-            if bmp.get(i as usize) {
-                let mut hdr_bytes = [0; 2];
-                cursor.read_exact(&mut hdr_bytes)?;
-                offset_hdrs.push(u16::from_le_bytes(hdr_bytes));
-            }
-        }
+    /// Reads the values of the named attributes from the record with
+    /// RecordId rid, decoding only those fields.
+    /// Returns a HashMap mapping attribute name to value, like read.
+    pub fn read_attributes(
+        &mut self,
+        rid: &RecordId,
+        names: &[&str],
+    ) -> Result<HashMap<String, AttributeValue>> {
+        let attr_idxs = names
+            .iter()
+            .map(|name| self.attribute_index(name))
+            .collect::<Result<Vec<_>>>()?;
+        let (loc, page, slot) = self.find_record(rid)?;
+        let row = self
+            .read_fields(&page.as_buf()[slot], &attr_idxs)
+            .map_err(|e| e.at_record(&loc))?;
+        Ok(attr_idxs
+            .iter()
+            .zip(row.into_values())
+            .map(|(attr_idx, attr_val)| {
+                (
+                    self.attributes[*attr_idx].name.clone(),
+                    attr_val.unwrap_or(AttributeValue::Null),
+                )
+            })
+            .collect())
+    }
 
-        let mut results = HashMap::new();
+    /// Decode the attributes at attr_idxs of the record in buf, in order.
+    fn read_fields(&mut self, buf: &[u8], attr_idxs: &[usize]) -> Result<Row> {
+        let hdr = RecordHeader::parse(buf)?;
+        let values = attr_idxs
+            .iter()
+            .map(|attr_idx| {
+                Ok(match self.read_field(buf, &hdr, *attr_idx)? {
+                    AttributeValue::Null => None,
+                    attr_val => Some(attr_val),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Row::new(values))
+    }
 
-        // offset_idx skips nulls
-        let mut offset_idx = 0;
-        for (i, attr) in self.attributes.iter().enumerate() {
-            // Skip nulls
-            if !bmp.get(i) {
-                continue;
-            }
-            let attr_val = match attr.attribute_type {
-                AttributeType::Int => {
-                    let mut int_bytes = [0; 4];
-                    cursor.read_exact(&mut int_bytes)?;
-                    AttributeValue::Int(i32::from_le_bytes(int_bytes))
-                }
-                AttributeType::Real => {
-                    let mut real_bytes = [0; 8];
-                    cursor.read_exact(&mut real_bytes)?;
-                    AttributeValue::Real(f64::from_le_bytes(real_bytes))
-                }
-                AttributeType::Varchar { len: max } => {
-                    let curr = cursor.position();
-                    let end = *offset_hdrs.get(offset_idx).unwrap() as u64;
-                    let str_len = (end - curr) as usize;
-                    if str_len > max as usize {
-                        return Err(Error::new(
-                            ErrorKind::InvalidData,
-                            "Varchar larger than max len",
-                        ));
-                    }
-                    let mut str_bytes = vec![0; str_len];
-                    cursor.read_exact(&mut str_bytes)?;
-                    AttributeValue::Varchar(String::from_utf8(str_bytes).unwrap())
+    /// Finds the record with RecordId rid, following a forwarding address if
+    /// the record has moved.
+    /// Returns the record's location, the page holding it and the record's
+    /// range in the page.
+    fn find_record(&mut self, rid: &RecordId) -> Result<(RecordId, P, Range<usize>)> {
+        let (loc, page, hdr) = self.locate_record(rid)?;
+        let range = hdr.slots_vec[loc.slot_num as usize].record_range();
+        Ok((loc, page, range))
+    }
+
+    /// Finds where the record with RecordId rid is stored, following a
+    /// forwarding address if the record has moved.
+    /// Returns the record's location and the page and slot directory there.
+    fn locate_record(&mut self, rid: &RecordId) -> Result<(RecordId, P, SlotDirectoryHeader)> {
+        let (page, hdr) = self.read_slot_page(rid)?;
+        match hdr.slots_vec[rid.slot_num as usize].status() {
+            SlotStatus::Dead => Err(Error::RecordDeleted(rid.clone())),
+            // Relocated records keep the RecordId of their original slot
+            SlotStatus::Forwarded => Err(Error::RecordNotFound(rid.clone())),
+            SlotStatus::Valid => Ok((rid.clone(), page, hdr)),
+            SlotStatus::Moved(target) => {
+                // Updates keep forwarding one hop deep, so the target must
+                // hold the record
+                let invalid = || Error::Corruption {
+                    page: Some(rid.page_num as u64),
+                    slot: Some(rid.slot_num),
+                    message: "Invalid forwarding address".to_string(),
+                };
+                let (page, hdr) = match self.read_slot_page(&target) {
+                    Ok(found) => found,
+                    Err(Error::RecordNotFound(_)) => return Err(invalid()),
+                    Err(e) => return Err(e),
+                };
+                match hdr.slots_vec[target.slot_num as usize].status() {
+                    SlotStatus::Forwarded => Ok((target, page, hdr)),
+                    _ => Err(invalid()),
                 }
-            };
-            results.insert(attr.name.clone(), attr_val);
-            offset_idx += 1;
+            }
         }
-        Ok(results)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::tempdir;
+    /// Replaces the values of the record with RecordId rid. Like insert,
+    /// attributes missing from update_vals get their default, or NULL.
+    /// The record is rewritten in place if it fits on its page. Otherwise it
+    /// moves to another page, and its slot keeps a forwarding address so rid
+    /// stays valid. A moved record returns to its original page when it
+    /// fits there again.
+    pub fn update(
+        &mut self,
+        rid: &RecordId,
+        update_vals: &HashMap<String, AttributeValue>,
+    ) -> Result<()> {
+        let update_vals = Self::prepare_values(&self.attributes, update_vals, self.lenient)?;
+        let (loc, mut page, mut hdr) = self.locate_record(rid)?;
+        let record = self.build_record(&update_vals)?;
 
-    #[test]
-    fn rbfm_create_test() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.path().join("testfile");
-        // File should be created with no errors
-        RecordBasedFileMgr::create(&file_path, vec![]).unwrap();
+        let loc_slot = loc.slot_num as usize;
+        let old_record = page.as_buf()[hdr.slots_vec[loc_slot].record_range()].to_vec();
+
+        // The new copy is written before the old one is removed, so a failed
+        // write never loses the record. The old overflow pages are freed last.
+        if let Err(e) = self.place_update(rid, &loc, &mut page, &mut hdr, &record, &old_record) {
+            self.discard_overflow(&[&record]);
+            return Err(e);
+        }
+        self.free_overflow(&old_record)
+            .map_err(|e| e.at_record(&loc))
+    }
+
+    /// Write the updated record for rid, currently stored at loc, and remove
+    /// the old one from loc's page.
+    fn place_update(
+        &mut self,
+        rid: &RecordId,
+        loc: &RecordId,
+        page: &mut P,
+        hdr: &mut SlotDirectoryHeader,
+        record: &[u8],
+        old_record: &[u8],
+    ) -> Result<()> {
+        let loc_slot = loc.slot_num as usize;
+        let moved = loc != rid;
+        if !moved && record.len() <= old_record.len() {
+            // Records that don't grow are rewritten where they are. The
+            // bytes they no longer use are reclaimed by reorganizing the page.
+            let slot = &mut hdr.slots_vec[loc_slot];
+            let offset = slot.offset as usize;
+            page.as_mut_buf()[offset..offset + record.len()].copy_from_slice(record);
+            slot.length = record.len() as u32;
+            return self.write_data_page(loc.page_num as u64, page, hdr);
+        }
+        Self::remove_record_bytes(page, hdr, loc_slot);
+        hdr.slots_vec[loc_slot] = SlotDirectoryRecordEntry::DEAD;
+
+        if moved {
+            // Move the record back if its original page has room now
+            let (mut home_page, mut home_hdr) = self.read_slot_page(rid)?;
+            let home_slot = rid.slot_num as usize;
+            if Self::place_record(&mut home_page, &mut home_hdr, home_slot, record, false) {
+                self.write_data_page(rid.page_num as u64, &mut home_page, &home_hdr)?;
+                return self.write_data_page(loc.page_num as u64, page, hdr);
+            }
+        }
+        if Self::place_record(page, hdr, loc_slot, record, moved) {
+            return self.write_data_page(loc.page_num as u64, page, hdr);
+        }
+
+        // The record goes to a new slot on another page. The stub always
+        // points straight at it, so reads never follow more than one hop.
+        // Neither loc's page nor the home page has room for it, so
+        // insert_bytes won't pick them while they still hold the old copy.
+        let target = self.insert_bytes(record, true)?;
+        if moved {
+            let (mut home_page, mut home_hdr) = self.read_slot_page(rid)?;
+            home_hdr.slots_vec[rid.slot_num as usize] = SlotDirectoryRecordEntry::moved(&target);
+            self.write_data_page(rid.page_num as u64, &mut home_page, &home_hdr)?;
+        } else {
+            hdr.slots_vec[loc_slot] = SlotDirectoryRecordEntry::moved(&target);
+        }
+        self.write_data_page(loc.page_num as u64, page, hdr)
+    }
+
+    /// Deletes the record with RecordId rid. Its bytes are reclaimed by
+    /// compacting the page, and its overflow pages are freed for reuse.
+    /// If the record has moved, the forwarding stub is deleted too.
+    pub fn delete(&mut self, rid: &RecordId) -> Result<()> {
+        let (_, hdr) = self.read_slot_page(rid)?;
+        if let SlotStatus::Dead = hdr.slots_vec[rid.slot_num as usize].status() {
+            return Err(Error::RecordDeleted(rid.clone()));
+        }
+
+        let (loc, mut page, mut hdr) = self.locate_record(rid)?;
+        let slot_num = loc.slot_num as usize;
+        let record = page.as_buf()[hdr.slots_vec[slot_num].record_range()].to_vec();
+        self.free_overflow(&record).map_err(|e| e.at_record(&loc))?;
+        Self::remove_record_bytes(&mut page, &mut hdr, slot_num);
+        hdr.slots_vec[slot_num] = SlotDirectoryRecordEntry::DEAD;
+        self.write_data_page(loc.page_num as u64, &mut page, &hdr)?;
+
+        if loc != *rid {
+            // Stubs hold no bytes on the page
+            let (mut page, mut hdr) = self.read_slot_page(rid)?;
+            hdr.slots_vec[rid.slot_num as usize] = SlotDirectoryRecordEntry::DEAD;
+            self.write_data_page(rid.page_num as u64, &mut page, &hdr)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the data page holding rid's slot.
+    /// Fails if the page or slot doesn't exist.
+    fn read_slot_page(&mut self, rid: &RecordId) -> Result<(P, SlotDirectoryHeader)> {
+        let no_slot = || Error::RecordNotFound(rid.clone());
+        let page_num = rid.page_num as u64;
+        if page_num >= self.paged_file.num_pages()? {
+            return Err(no_slot());
+        }
+        let mut page = P::new();
+        self.paged_file.read_page(page_num, &mut page)?;
+        if Self::get_page_kind(&page, page_num)? != PageKind::Data {
+            return Err(no_slot());
+        }
+        let hdr = Self::get_slot_directory_hdr(&page, page_num)?;
+        if hdr.slots_vec.len() <= rid.slot_num as usize {
+            return Err(no_slot());
+        }
+        Ok((page, hdr))
+    }
+
+    /// Remove the bytes of the record in slot slot_num from the page, moving
+    /// the records stored before it up to close the gap.
+    /// The slot itself is left for the caller to update.
+    fn remove_record_bytes(page: &mut P, hdr: &mut SlotDirectoryHeader, slot_num: usize) {
+        let range = hdr.slots_vec[slot_num].record_range();
+        let length = range.len();
+        let data_start = hdr.data_start_offset as usize;
+        page.as_mut_buf()
+            .copy_within(data_start..range.start, data_start + length);
+        // Records grow backward, so only records before this one moved
+        for slot in hdr.slots_vec.iter_mut() {
+            if slot.has_record() && (slot.offset as usize) < range.start {
+                slot.offset += length as i32;
+            }
+        }
+        hdr.data_start_offset += length as u32;
+    }
+
+    /// Free the overflow pages of a record's spilled fields. Freed pages
+    /// become empty data pages, so inserts and later chains can reuse them.
+    fn free_overflow(&mut self, record: &[u8]) -> Result<()> {
+        let hdr = RecordHeader::parse(record)?;
+        for field_idx in 0..hdr.num_attributes {
+            if let Some((range, true)) = hdr.field_range(field_idx, record.len())? {
+                let (ptr, _) = Self::spilled_field(&record[range])?;
+                self.free_chain(&ptr)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Free the overflow pages of a single spilled value.
+    fn free_chain(&mut self, ptr: &OverflowPointer) -> Result<()> {
+        let mut page = P::new();
+        let page_hdr = Self::init_rb_page(&mut page);
+        for page_num in chain_pages(&mut self.paged_file, ptr)? {
+            self.write_data_page(page_num as u64, &mut page, &page_hdr)?;
+        }
+        Ok(())
+    }
+
+    /// Returns an iterator over every record in the file, in page order.
+    /// projection lists the attributes to return, or None for all of them.
+    /// If a condition is given, only records satisfying it are returned. It
+    /// is tested before the other attributes are decoded, so records that
+    /// don't match cost little more than decoding one field.
+    /// Moved records are returned once, under their original RecordId.
+    pub fn scan(
+        &mut self,
+        projection: Option<&[&str]>,
+        condition: Option<ScanCondition>,
+    ) -> Result<Scan<'_>> {
+        let projection = match projection {
+            Some(names) => names
+                .iter()
+                .map(|name| self.attribute_index(name))
+                .collect::<Result<_>>()?,
+            None => (0..self.attributes.len()).collect(),
+        };
+        let condition = match condition {
+            Some(condition) => {
+                let attr_idx = self.attribute_index(&condition.attribute)?;
+                let attr_type = &self.attributes[attr_idx].attribute_type;
+                if condition.path.is_some() && *attr_type != AttributeType::Json {
+                    return Err(Error::invalid_input(format!(
+                        "Attribute {} isn't Json, so it has no paths",
+                        condition.attribute
+                    )));
+                }
+                Some((attr_idx, condition))
+            }
+            None => None,
+        };
+        Ok(Scan {
+            file: self,
+            projection,
+            condition,
+            filter: None,
+            // The schema page has no records, so scanning starts after it
+            page_num: SCHEMA_PAGE_NUM,
+            page: P::new(),
+            slots: vec![],
+            slot_num: 0,
+        })
+    }
+
+    /// Position of the attribute called name in the schema, and in rows.
+    pub fn attribute_index(&self, name: &str) -> Result<usize> {
+        self.attribute_idxs
+            .get(name)
+            .copied()
+            .ok_or_else(|| Error::UnknownAttribute {
+                attribute: name.to_string(),
+            })
+    }
+
+    /// Calls f with a view of the record with RecordId rid, which decodes
+    /// fields on demand without copying them. See the record_ref module.
+    pub fn read_ref<T, F>(&mut self, rid: &RecordId, f: F) -> Result<T>
+    where
+        F: FnOnce(&RecordRef) -> Result<T>,
+    {
+        let (loc, page, slot) = self.find_record(rid)?;
+        let buf = &page.as_buf()[slot];
+        RecordRef::new(
+            buf,
+            &self.attributes,
+            &self.field_idxs,
+            &mut self.paged_file,
+        )
+        .and_then(|record| f(&record))
+        .map_err(|e| e.at_record(&loc))
+    }
+
+    /// Decode the projected attributes of a record for scan.
+    /// Returns None without decoding them if the record fails the condition
+    /// or filter, which test a view of the record.
+    fn scan_record(
+        &mut self,
+        buf: &[u8],
+        projection: &[usize],
+        condition: Option<&(usize, ScanCondition)>,
+        filter: Option<&mut RecordFilter>,
+    ) -> Result<Option<Row>> {
+        let hdr = RecordHeader::parse(buf)?;
+        if let Some((attr_idx, condition)) = condition {
+            let record = RecordRef::new(
+                buf,
+                &self.attributes,
+                &self.field_idxs,
+                &mut self.paged_file,
+            )?;
+            let truth = match (&condition.path, record.get(*attr_idx)?) {
+                (Some(path), attr_val) => {
+                    let extracted = match attr_val {
+                        ValueRef::Null => None,
+                        // Only the path is decoded
+                        ValueRef::Json(bytes) => json::extract_from_bytes(bytes, path)?,
+                        // Spilled documents and defaults
+                        _ => match record.get_value(*attr_idx)? {
+                            AttributeValue::Json(doc) => doc.extract(path),
+                            _ => None,
+                        },
+                    };
+                    condition.op.eval(
+                        extracted.as_ref(),
+                        Some(&condition.value),
+                        condition.collation,
+                    )
+                }
+                // Spilled values have to be read from their overflow pages
+                (None, ValueRef::Spilled) => {
+                    let attr_val = record.get_value(*attr_idx)?;
+                    condition
+                        .op
+                        .eval(Some(&attr_val), Some(&condition.value), condition.collation)
+                }
+                (None, attr_val) => {
+                    condition
+                        .op
+                        .eval_ref(&attr_val, Some(&condition.value), condition.collation)?
+                }
+            };
+            if !truth.is_true() {
+                return Ok(None);
+            }
+        }
+        if let Some(filter) = filter {
+            let record = RecordRef::new(
+                buf,
+                &self.attributes,
+                &self.field_idxs,
+                &mut self.paged_file,
+            )?;
+            if !filter(&record)? {
+                return Ok(None);
+            }
+        }
+        let mut values = Vec::with_capacity(projection.len());
+        for attr_idx in projection.iter() {
+            values.push(match self.read_field(buf, &hdr, *attr_idx)? {
+                AttributeValue::Null => None,
+                attr_val => Some(attr_val),
+            });
+        }
+        Ok(Some(Row::new(values)))
+    }
+
+    /// Reads a single element of the array attribute attr_name from the record
+    /// with RecordId rid, without decoding the rest of the array.
+    /// Returns None if the element or the whole array is null.
+    pub fn read_array_element(
+        &mut self,
+        rid: &RecordId,
+        attr_name: &str,
+        idx: usize,
+    ) -> Result<Option<AttributeValue>> {
+        let attr_idx = self
+            .attributes
+            .iter()
+            .position(|attr| attr.name == attr_name)
+            .ok_or_else(|| Error::UnknownAttribute {
+                attribute: attr_name.to_string(),
+            })?;
+        let attr = &self.attributes[attr_idx];
+        let elem_type = match &attr.attribute_type {
+            AttributeType::Array(elem_type) => elem_type.clone(),
+            _ => return Err(Error::invalid_input("Attribute is not an array")),
+        };
+        let (loc, page, slot) = self.find_record(rid)?;
+        self.read_element(&page.as_buf()[slot], attr_idx, &elem_type, idx)
+            .map_err(|e| e.at_record(&loc))
+    }
+
+    /// Decode element idx of the array attribute at attr_idx of the record in buf.
+    fn read_element(
+        &mut self,
+        buf: &[u8],
+        attr_idx: usize,
+        elem_type: &AttributeType,
+        idx: usize,
+    ) -> Result<Option<AttributeValue>> {
+        let attr = &self.attributes[attr_idx];
+        let hdr = RecordHeader::parse(buf)?;
+        let field_idx = self.field_idxs[attr_idx];
+        // Records written before the attribute was added use the default
+        if field_idx >= hdr.num_attributes {
+            return match &attr.default {
+                None | Some(AttributeValue::Null) => Ok(None),
+                Some(default) => field::array_element(
+                    elem_type,
+                    &field::encode(&attr.attribute_type, default)?,
+                    idx,
+                ),
+            };
+        }
+        match Self::read_field_bytes(&mut self.paged_file, buf, &hdr, field_idx)? {
+            Some(bytes) => field::array_element(elem_type, &bytes, idx),
+            None => Ok(None),
+        }
+    }
+
+    /// Initialize a new Page for use by RBFM
+    /// Returns its empty slot directory.
+    fn init_rb_page(page: &mut P) -> SlotDirectoryHeader {
+        // First we 0 out the buffer
+        page.as_mut_buf().iter_mut().for_each(|i| *i = 0);
+        // The header is written with the page
+        SlotDirectoryHeader {
+            page_kind: PageKind::Data,
+            data_start_offset: PAGE_SIZE as u32,
+            slots_vec: vec![],
+        }
+    }
+
+    fn unversioned() -> Error {
+        Error::UnsupportedFormat(
+            "Not a record file, or written before file formats were versioned".to_string(),
+        )
+    }
+
+    fn get_page_kind(page: &P, page_num: u64) -> Result<PageKind> {
+        bincode::deserialize(page.as_buf())
+            .map_err(|_| Error::corrupt_page(page_num, "Invalid page kind"))
+    }
+
+    /// Read the slot directory of data page page_num, checking that every
+    /// record it points to is within the page, and that no records overlap.
+    /// Together these bound the space used by records, so computing the free
+    /// space can't underflow.
+    fn get_slot_directory_hdr(page: &P, page_num: u64) -> Result<SlotDirectoryHeader> {
+        let invalid = || Error::corrupt_page(page_num, "Invalid slot directory");
+        let hdr: SlotDirectoryHeader =
+            bincode::deserialize(page.as_buf()).map_err(|_| invalid())?;
+        let hdr_size = bincode::serialized_size(&hdr).map_err(|_| invalid())? as usize;
+        let data_start = hdr.data_start_offset as usize;
+        if hdr.page_kind != PageKind::Data || data_start < hdr_size || data_start > PAGE_SIZE {
+            return Err(invalid());
+        }
+        for (slot_num, slot) in hdr.slots_vec.iter().enumerate() {
+            if slot.has_record()
+                && (slot.record_range().start < data_start || slot.record_range().end > PAGE_SIZE)
+            {
+                return Err(Error::Corruption {
+                    page: Some(page_num),
+                    slot: Some(slot_num as u32),
+                    message: "Record is outside the page".to_string(),
+                });
+            }
+        }
+        let mut slot_nums: Vec<usize> = (0..hdr.slots_vec.len())
+            .filter(|slot_num| hdr.slots_vec[*slot_num].has_record())
+            .collect();
+        slot_nums.sort_by_key(|slot_num| hdr.slots_vec[*slot_num].offset);
+        for pair in slot_nums.windows(2) {
+            let prev = hdr.slots_vec[pair[0]].record_range();
+            if hdr.slots_vec[pair[1]].record_range().start < prev.end {
+                return Err(Error::Corruption {
+                    page: Some(page_num),
+                    slot: Some(pair[1] as u32),
+                    message: "Record overlaps another record".to_string(),
+                });
+            }
+        }
+        Ok(hdr)
+    }
+
+    fn write_slot_directory_hdr(page: &mut P, hdr: &SlotDirectoryHeader) -> Result<()> {
+        bincode::serialize_into(&mut page.as_mut_buf()[..], &hdr)
+            .map_err(|_| Error::invalid_input("Slot directory is too large for the page"))
+    }
+
+    /// Write a data page with slot directory hdr, appending it if page_num
+    /// is the end of the file, and record its free space in the free space
+    /// map.
+    fn write_data_page(
+        &mut self,
+        page_num: u64,
+        page: &mut P,
+        hdr: &SlotDirectoryHeader,
+    ) -> Result<()> {
+        Self::write_slot_directory_hdr(page, hdr)?;
+        if page_num == self.paged_file.num_pages()? {
+            self.paged_file.append_page(page)?;
+        } else {
+            self.paged_file.write_page(page_num, page)?;
+        }
+        self.free_space_map
+            .set(&mut self.paged_file, page_num, Self::insert_space(hdr))
+    }
+
+    /// Contiguous free space between the slot directory and the records
+    fn free_space(hdr: &SlotDirectoryHeader) -> usize {
+        let hdr_size = bincode::serialized_size(hdr).unwrap() as usize;
+        hdr.data_start_offset as usize - hdr_size
+    }
+
+    /// Free space on the page, including holes between records left by
+    /// records that shrank. compact_page makes it all contiguous.
+    fn total_free_space(hdr: &SlotDirectoryHeader) -> usize {
+        let hdr_size = bincode::serialized_size(hdr).unwrap() as usize;
+        let used: usize = hdr
+            .slots_vec
+            .iter()
+            .filter(|slot| slot.has_record())
+            .map(|slot| slot.record_range().len())
+            .sum();
+        PAGE_SIZE - hdr_size - used
+    }
+
+    /// Slide the records on the page together at the end of the page,
+    /// removing the holes between them.
+    fn compact_page(page: &mut P, hdr: &mut SlotDirectoryHeader) {
+        let mut slot_nums: Vec<usize> = (0..hdr.slots_vec.len())
+            .filter(|slot_num| hdr.slots_vec[*slot_num].has_record())
+            .collect();
+        // Moving the last record first means no record is overwritten before
+        // it's moved
+        slot_nums.sort_by_key(|slot_num| std::cmp::Reverse(hdr.slots_vec[*slot_num].offset));
+        let mut offset = PAGE_SIZE;
+        for slot_num in slot_nums {
+            let slot = &mut hdr.slots_vec[slot_num];
+            let range = slot.record_range();
+            offset -= range.len();
+            page.as_mut_buf().copy_within(range, offset);
+            slot.offset = offset as i32;
+        }
+        hdr.data_start_offset = offset as u32;
+    }
+
+    /// Space for a new record on the page. A new slot costs
+    /// RECORD_ENTRY_SIZE bytes, unless a dead slot can be reused.
+    /// This is what the free space map records for each data page.
+    fn insert_space(hdr: &SlotDirectoryHeader) -> usize {
+        match Self::dead_slot(hdr) {
+            Some(_) => Self::total_free_space(hdr),
+            None => Self::total_free_space(hdr).saturating_sub(RECORD_ENTRY_SIZE),
+        }
+    }
+
+    /// The first dead slot on the page, which insert can reuse.
+    /// Slots of moved records hold a forwarding address, so they're never
+    /// dead while the record exists.
+    fn dead_slot(hdr: &SlotDirectoryHeader) -> Option<usize> {
+        hdr.slots_vec
+            .iter()
+            .position(|slot| matches!(slot.status(), SlotStatus::Dead))
+    }
+
+    /// Write record at the start of the page's data region and point slot
+    /// slot_num at it. The slot must exist and hold no bytes on the page.
+    /// Returns false, leaving the page unchanged, if there isn't room.
+    fn place_record(
+        page: &mut P,
+        hdr: &mut SlotDirectoryHeader,
+        slot_num: usize,
+        record: &[u8],
+        forwarded: bool,
+    ) -> bool {
+        if Self::free_space(hdr) < record.len() {
+            if Self::total_free_space(hdr) < record.len() {
+                return false;
+            }
+            Self::compact_page(page, hdr);
+        }
+        let offset = hdr.data_start_offset as usize - record.len();
+        page.as_mut_buf()[offset..offset + record.len()].copy_from_slice(record);
+        hdr.data_start_offset = offset as u32;
+        let mut length = record.len() as u32;
+        if forwarded {
+            length |= FORWARDED_FLAG;
+        }
+        hdr.slots_vec[slot_num] = SlotDirectoryRecordEntry {
+            length,
+            offset: offset as i32,
+        };
+        true
+    }
+
+    /// Calculate the length of the null bitmap in bytes
+    fn null_bitmap_len(attrs_len: usize) -> usize {
+        Bitmap::bmp_size_in_bytes(attrs_len)
+    }
+
+    /// True if the value is too large to be stored inline in any record
+    fn is_spilled(attr_type: &AttributeType, attr_val: &AttributeValue) -> Result<bool> {
+        Ok(field::encoded_len(attr_type, attr_val)? > MAX_RECORD_SIZE)
+    }
+
+    /// Decide which values to store in overflow pages: none if the record
+    /// fits on a page, otherwise the largest values until it does.
+    /// Returns whether each value is spilled, and the size of the record.
+    fn field_layout(&self, insert_vals: &[AttributeValue]) -> Result<(Vec<bool>, usize)> {
+        // Record Format:
+        // Num_Attributes: 2 byte unsigned int
+        // Null_Bitmap: Variable length bitmap, byte length is ceil(num_attributes / 8)
+        // Offset_Headers: 2 bytes for each non-null attribute
+        // Data: Each non-null field, encoded as described in the field module.
+        //       Spilled fields hold an 8 byte OverflowPointer, then the first
+        //       SPILL_PREFIX_LEN bytes of the encoded value.
+        let spilled_len = OVERFLOW_POINTER_SIZE + SPILL_PREFIX_LEN;
+        let bmp_len = Self::null_bitmap_len(self.num_fields);
+
+        let num_attributes_len: usize = 2;
+
+        let mut offset_headers_len: usize = 0;
+        // Encoded length of each value, None for nulls
+        let mut lens = Vec::with_capacity(insert_vals.len());
+
+        for (attr, attr_val) in self.attributes.iter().zip(insert_vals.iter()) {
+            // No change in size for null values
+            if let AttributeValue::Null = attr_val {
+                lens.push(None);
+                continue;
+            }
+            // All non-null fields have a 2 byte offset header
+            offset_headers_len += 2;
+
+            if !field::type_matches_value(&attr.attribute_type, attr_val) {
+                return Err(Self::type_error(attr, attr_val));
+            }
+            lens.push(Some(field::encoded_len(&attr.attribute_type, attr_val)?));
+        }
+
+        let mut spilled = vec![false; lens.len()];
+        loop {
+            let data_len: usize = lens
+                .iter()
+                .zip(spilled.iter())
+                .map(|(len, spilled)| match (len, spilled) {
+                    (Some(_), true) => spilled_len,
+                    (Some(len), false) => *len,
+                    (None, _) => 0,
+                })
+                .sum();
+            let size = bmp_len + num_attributes_len + offset_headers_len + data_len;
+            if size <= MAX_RECORD_SIZE {
+                return Ok((spilled, size));
+            }
+            // Spill the largest value that's still inline, if that saves space
+            let largest = lens
+                .iter()
+                .enumerate()
+                .filter(|(i, len)| !spilled[*i] && matches!(len, Some(len) if *len > spilled_len))
+                .max_by_key(|(_, len)| len.unwrap());
+            match largest {
+                Some((i, _)) => spilled[i] = true,
+                None => return Err(Error::invalid_input("Record too large")),
+            }
+        }
+    }
+
+    /// Build a record from each field's encoded bytes, and whether the field
+    /// is spilled. None fields are null.
+    fn assemble_record(fields: &[Option<(Vec<u8>, bool)>]) -> Vec<u8> {
+        // Record Format:
+        // Num_Attributes: 2 byte unsigned int
+        // Null_Bitmap: Variable length bitmap, byte length is ceil(num_attributes / 8)
+        // Offset_Headers: 2 bytes for each non-null attribute
+        // Data: Each non-null field
+        let mut bmp = Bitmap::new(fields.len());
+        let valid_cnt = fields.iter().flatten().count();
+        let data_start = 2 + Self::null_bitmap_len(fields.len()) + valid_cnt * 2;
+
+        let mut offset_hdrs = Vec::with_capacity(valid_cnt);
+        let mut data = vec![];
+        for (i, (bytes, spilled)) in fields
+            .iter()
+            .enumerate()
+            .filter_map(|(i, field)| Some((i, field.as_ref()?)))
+        {
+            bmp.set(i);
+            data.extend_from_slice(bytes);
+            // Offset headers point to END of value
+            let mut offset_hdr = (data_start + data.len()) as u16;
+            if *spilled {
+                offset_hdr |= SPILLED_FLAG;
+            }
+            offset_hdrs.push(offset_hdr);
+        }
+
+        let mut record = Vec::with_capacity(data_start + data.len());
+        record.extend_from_slice(&(fields.len() as u16).to_le_bytes());
+        record.extend_from_slice(&bmp.into_bytes());
+        for offset_hdr in offset_hdrs.iter() {
+            record.extend_from_slice(&offset_hdr.to_le_bytes());
+        }
+        record.extend_from_slice(&data);
+        record
+    }
+
+    fn read_record_from_buf(&mut self, buf: &[u8]) -> Result<HashMap<String, AttributeValue>> {
+        let hdr = RecordHeader::parse(buf)?;
+        let mut results = HashMap::new();
+        for i in 0..self.attributes.len() {
+            let attr_val = self.read_field(buf, &hdr, i)?;
+            results.insert(self.attributes[i].name.clone(), attr_val);
+        }
+        Ok(results)
+    }
+
+    /// Decode the field for attribute attr_idx from a record, following
+    /// overflow pointers.
+    fn read_field(
+        &mut self,
+        buf: &[u8],
+        hdr: &RecordHeader,
+        attr_idx: usize,
+    ) -> Result<AttributeValue> {
+        let attr = &self.attributes[attr_idx];
+        let field_idx = self.field_idxs[attr_idx];
+        // Records written before the attribute was added don't have the field
+        if field_idx >= hdr.num_attributes {
+            return Ok(attr.default.clone().unwrap_or(AttributeValue::Null));
+        }
+        match Self::read_field_bytes(&mut self.paged_file, buf, hdr, field_idx)? {
+            Some(bytes) => field::decode(&attr.attribute_type, &bytes),
+            None => Ok(AttributeValue::Null),
+        }
+    }
+
+    /// The encoded value of the field at field_idx in a record, joining the
+    /// prefix and overflow pages of spilled values. None if the field is null.
+    pub(crate) fn read_field_bytes<'b>(
+        paged_file: &mut Pf,
+        buf: &'b [u8],
+        hdr: &RecordHeader,
+        field_idx: usize,
+    ) -> Result<Option<Cow<'b, [u8]>>> {
+        match hdr.field_range(field_idx, buf.len())? {
+            None => Ok(None),
+            Some((range, false)) => Ok(Some(Cow::Borrowed(&buf[range]))),
+            Some((range, true)) => {
+                let (ptr, prefix) = Self::spilled_field(&buf[range])?;
+                let mut bytes = prefix.to_vec();
+                bytes.extend_from_slice(&read_chain(paged_file, &ptr)?);
+                Ok(Some(Cow::Owned(bytes)))
+            }
+        }
+    }
+
+    /// Split a spilled field into its OverflowPointer and inline prefix.
+    /// Records written before prefixes were kept have no prefix.
+    fn spilled_field(field: &[u8]) -> Result<(OverflowPointer, &[u8])> {
+        if field.len() < OVERFLOW_POINTER_SIZE {
+            return Err(Error::corrupt("Invalid overflow pointer"));
+        }
+        let (ptr, prefix) = field.split_at(OVERFLOW_POINTER_SIZE);
+        Ok((OverflowPointer::from_bytes(ptr)?, prefix))
+    }
+
+    /// Rewrite the records on page page_num that were written under an older
+    /// schema, so they have a field for each attribute and no data for
+    /// dropped attributes. Records that would no longer fit on the page are
+    /// left as they are; reads handle both layouts. The overflow pages of
+    /// dropped values are freed.
+    /// Pages can be migrated one at a time, e.g. from a background task.
+    /// Returns the number of records migrated.
+    pub fn migrate_page(&mut self, page_num: u64) -> Result<usize> {
+        let mut page = P::new();
+        self.paged_file.read_page(page_num, &mut page)?;
+        if Self::get_page_kind(&page, page_num)? != PageKind::Data {
+            return Ok(0);
+        }
+        let mut hdr = Self::get_slot_directory_hdr(&page, page_num)?;
+
+        // Every record on the page, as (slot number, record)
+        let mut records = vec![];
+        let mut used_space = 0;
+        for (slot_num, slot) in hdr.slots_vec.iter().enumerate() {
+            if slot.has_record() {
+                let record = page.as_buf()[slot.record_range()].to_vec();
+                used_space += record.len();
+                records.push((slot_num, record));
+            }
+        }
+
+        // The slot directory doesn't grow, so this is the space for records
+        let available_space = Self::free_space(&hdr) + (PAGE_SIZE - hdr.data_start_offset as usize);
+        let mut migrated = 0;
+        // Overflow chains of dropped fields, freed once the page is written
+        let mut dropped_chains = vec![];
+        for (slot_num, record) in records.iter_mut() {
+            let (upgraded, dropped) = match self
+                .upgrade_record(record)
+                .map_err(|e| e.at(page_num, Some(*slot_num as u32)))?
+            {
+                Some(upgraded) => upgraded,
+                None => continue,
+            };
+            if used_space - record.len() + upgraded.len() > available_space {
+                continue;
+            }
+            used_space = used_space - record.len() + upgraded.len();
+            *record = upgraded;
+            dropped_chains.extend(dropped);
+            migrated += 1;
+        }
+        if migrated == 0 {
+            return Ok(0);
+        }
+
+        // Repack the records from the end of the page
+        let mut offset = PAGE_SIZE;
+        for (slot_num, record) in records.iter() {
+            offset -= record.len();
+            page.as_mut_buf()[offset..offset + record.len()].copy_from_slice(record);
+            let slot = &mut hdr.slots_vec[*slot_num];
+            slot.offset = offset as i32;
+            slot.length = record.len() as u32 | (slot.length & FORWARDED_FLAG);
+        }
+        hdr.data_start_offset = offset as u32;
+        self.write_data_page(page_num, &mut page, &hdr)?;
+        for ptr in dropped_chains.iter() {
+            self.free_chain(ptr)?;
+        }
+        Ok(migrated)
+    }
+
+    /// Remove the holes between the records on page page_num, so all its
+    /// free space is contiguous. Insert and update do this when a record
+    /// only fits after reorganizing, so it's rarely needed directly.
+    pub fn reorganize_page(&mut self, page_num: u64) -> Result<()> {
+        let mut page = P::new();
+        self.paged_file.read_page(page_num, &mut page)?;
+        if Self::get_page_kind(&page, page_num)? != PageKind::Data {
+            return Ok(());
+        }
+        let mut hdr = Self::get_slot_directory_hdr(&page, page_num)?;
+        Self::compact_page(&mut page, &mut hdr);
+        self.write_data_page(page_num, &mut page, &hdr)
+    }
+
+    /// Migrate every page in the file. See migrate_page.
+    pub fn migrate(&mut self) -> Result<usize> {
+        let mut migrated = 0;
+        for page_num in 0..self.paged_file.num_pages()? {
+            migrated += self.migrate_page(page_num)?;
+        }
+        Ok(migrated)
+    }
+
+    /// Check every data page of the file for corruption, and with repair,
+    /// fix what can be fixed without losing data. See the fsck module.
+    pub fn check(&mut self, repair: bool) -> Result<FsckReport> {
+        let mut report = FsckReport {
+            pages: self.paged_file.num_pages()?,
+            ..FsckReport::default()
+        };
+        // Forwarding stubs by the location they point at, and moved records,
+        // which must match up once every page has been read
+        let mut stubs: HashMap<RecordId, RecordId> = HashMap::new();
+        let mut forwarded = vec![];
+
+        let mut page = P::new();
+        // The schema and free space map pages were checked by open
+        for page_num in FSM_PAGE_NUM + 1..report.pages {
+            self.paged_file.read_page(page_num, &mut page)?;
+            match Self::get_page_kind(&page, page_num) {
+                Ok(PageKind::Data) => (),
+                // Overflow pages are checked through the records using them
+                Ok(_) => continue,
+                Err(_) => {
+                    report.add(page_num, None, "Invalid page kind");
+                    continue;
+                }
+            }
+            let mut hdr: SlotDirectoryHeader = match bincode::deserialize(page.as_buf()) {
+                Ok(hdr) => hdr,
+                Err(_) => {
+                    report.add(page_num, None, "Invalid slot directory");
+                    continue;
+                }
+            };
+            let hdr_size = bincode::serialized_size(&hdr).unwrap() as usize;
+            let first_problem = report.problems.len();
+
+            // Byte range and slot number of each record on the page
+            let mut ranges = vec![];
+            for (slot_num, slot) in hdr.slots_vec.iter().enumerate() {
+                let rid = RecordId {
+                    page_num: page_num as u32,
+                    slot_num: slot_num as u32,
+                };
+                match slot.status() {
+                    SlotStatus::Dead => continue,
+                    SlotStatus::Moved(target) => {
+                        if let Some(other) = stubs.insert(target.clone(), rid.clone()) {
+                            report.add(
+                                page_num,
+                                Some(rid.slot_num),
+                                format!(
+                                    "Forwarding address {}:{} is also used by {}:{}",
+                                    target.page_num,
+                                    target.slot_num,
+                                    other.page_num,
+                                    other.slot_num
+                                ),
+                            );
+                        }
+                        continue;
+                    }
+                    SlotStatus::Forwarded => forwarded.push(rid.clone()),
+                    SlotStatus::Valid => (),
+                }
+                let range = slot.record_range();
+                if range.start < hdr_size || range.end > PAGE_SIZE {
+                    report.add(page_num, Some(rid.slot_num), "Record is outside the page");
+                    continue;
+                }
+                report.records += 1;
+                if let Err(e) = self.check_record(&page.as_buf()[range.clone()]) {
+                    let message = match e {
+                        Error::Corruption { message, .. } => message,
+                        e => e.to_string(),
+                    };
+                    report.add(page_num, Some(rid.slot_num), message);
+                }
+                ranges.push((range, rid.slot_num));
+            }
+
+            ranges.sort_by_key(|(range, _)| range.start);
+            for pair in ranges.windows(2) {
+                let ((prev, prev_slot), (next, next_slot)) = (&pair[0], &pair[1]);
+                if next.start < prev.end {
+                    report.add(
+                        page_num,
+                        Some(*next_slot),
+                        format!("Record overlaps the record in slot {}", prev_slot),
+                    );
+                }
+            }
+
+            // Inserts write below the data start offset, so it can't be past
+            // any record
+            let data_start = ranges.first().map_or(PAGE_SIZE, |(range, _)| range.start);
+            if (hdr.data_start_offset as usize) < hdr_size
+                || hdr.data_start_offset as usize > data_start
+            {
+                report.add(
+                    page_num,
+                    None,
+                    format!(
+                        "Data start offset {} should be {}",
+                        hdr.data_start_offset, data_start
+                    ),
+                );
+                // Writing the page computes its free space from the slot
+                // directory, so only pages that are otherwise valid are fixed
+                if repair && report.problems.len() == first_problem + 1 {
+                    hdr.data_start_offset = data_start as u32;
+                    self.write_data_page(page_num, &mut page, &hdr)?;
+                    report.repaired();
+                }
+            }
+        }
+
+        for (target, stub) in stubs.iter() {
+            let target_forwarded = match self.read_slot_page(target) {
+                Ok((_, hdr)) => matches!(
+                    hdr.slots_vec[target.slot_num as usize].status(),
+                    SlotStatus::Forwarded
+                ),
+                Err(_) => false,
+            };
+            if !target_forwarded {
+                report.add(
+                    stub.page_num as u64,
+                    Some(stub.slot_num),
+                    format!(
+                        "Forwarding address {}:{} is not a moved record",
+                        target.page_num, target.slot_num
+                    ),
+                );
+            }
+        }
+        for rid in forwarded.iter().filter(|rid| !stubs.contains_key(rid)) {
+            report.add(
+                rid.page_num as u64,
+                Some(rid.slot_num),
+                "Moved record has no forwarding stub",
+            );
+            // The record becomes reachable through its own RecordId. Pages
+            // whose slot directory is invalid are left alone.
+            if repair {
+                if let Ok((mut page, mut hdr)) = self.read_slot_page(rid) {
+                    hdr.slots_vec[rid.slot_num as usize].length &= !FORWARDED_FLAG;
+                    self.write_data_page(rid.page_num as u64, &mut page, &hdr)?;
+                    report.repaired();
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Check a record's header against the schema, and that every field
+    /// decodes, including spilled ones.
+    fn check_record(&mut self, buf: &[u8]) -> Result<()> {
+        let hdr = RecordHeader::parse(buf)?;
+        if hdr.num_attributes > self.num_fields {
+            return Err(Error::corrupt("Record has more fields than the schema"));
+        }
+        // Bits past the last field are unused
+        let last_bmp_byte = buf[1 + Bitmap::bmp_size_in_bytes(hdr.num_attributes)];
+        if hdr.num_attributes % 8 != 0 && last_bmp_byte >> (hdr.num_attributes % 8) != 0 {
+            return Err(Error::corrupt(
+                "Null bitmap has bits set past the last field",
+            ));
+        }
+
+        let mut end = hdr.data_start;
+        for valid_idx in 0..hdr.offset_hdrs.len() / 2 {
+            let offset = (hdr.offset_hdr(valid_idx) & !SPILLED_FLAG) as usize;
+            if offset < end {
+                return Err(Error::corrupt("Offset headers are not increasing"));
+            }
+            end = offset;
+        }
+        if end > buf.len() {
+            return Err(Error::corrupt(
+                "Offset header is past the end of the record",
+            ));
+        }
+
+        for attr_idx in 0..self.attributes.len() {
+            let attr = &self.attributes[attr_idx];
+            let field_idx = self.field_idxs[attr_idx];
+            if field_idx < hdr.num_attributes && !attr.nullable && !hdr.bmp.get(field_idx) {
+                return Err(Error::NotNullViolation {
+                    attribute: attr.name.clone(),
+                });
+            }
+            self.read_field(buf, &hdr, attr_idx)?;
+        }
+        Ok(())
+    }
+
+    /// Re-encode a record in the current layout, along with the overflow
+    /// pointers of the dropped fields it no longer stores.
+    /// Returns None if it's already in the current layout.
+    fn upgrade_record(&self, buf: &[u8]) -> Result<Option<(Vec<u8>, Vec<OverflowPointer>)>> {
+        let hdr = RecordHeader::parse(buf)?;
+        if hdr.num_attributes > self.num_fields {
+            return Err(Error::corrupt("Record has more fields than the schema"));
+        }
+
+        let mut outdated = hdr.num_attributes != self.num_fields;
+        let mut fields = vec![None; self.num_fields];
+        let mut dropped = vec![];
+        for (field_idx, field) in fields.iter_mut().enumerate().take(hdr.num_attributes) {
+            let (range, spilled) = match hdr.field_range(field_idx, buf.len())? {
+                Some(field) => field,
+                None => continue,
+            };
+            if self.field_idxs.binary_search(&field_idx).is_ok() {
+                *field = Some((buf[range].to_vec(), spilled));
+            } else {
+                // The attribute was dropped, so its data goes
+                if spilled {
+                    dropped.push(Self::spilled_field(&buf[range])?.0);
+                }
+                outdated = true;
+            }
+        }
+        if !outdated {
+            return Ok(None);
+        }
+
+        // Fill in fields for attributes added since the record was written
+        for (attr, field_idx) in self.attributes.iter().zip(self.field_idxs.iter()) {
+            if *field_idx < hdr.num_attributes {
+                continue;
+            }
+            // add_attribute makes sure defaults aren't spilled
+            if let Some(default) = &attr.default {
+                if !matches!(default, AttributeValue::Null) {
+                    fields[*field_idx] =
+                        Some((field::encode(&attr.attribute_type, default)?, false));
+                }
+            }
+        }
+        Ok(Some((Self::assemble_record(&fields), dropped)))
+    }
+}
+
+impl<'a> Scan<'a> {
+    /// Yield rows instead of HashMaps.
+    pub fn rows(self) -> Rows<'a> {
+        Rows { scan: self }
+    }
+
+    /// Only return records for which filter returns true. filter gets a view
+    /// of each record, so records it rejects are never copied out of their
+    /// page. Spilled values appear as ValueRef::Spilled, and are read with
+    /// RecordRef::get_value.
+    pub fn with_filter<F>(mut self, filter: F) -> Self
+    where
+        F: FnMut(&RecordRef) -> Result<bool> + 'a,
+    {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    fn next_row(&mut self) -> Option<Result<(RecordId, Row)>> {
+        loop {
+            if self.slot_num >= self.slots.len() {
+                self.page_num += 1;
+                match self.file.paged_file.num_pages() {
+                    Ok(num_pages) if self.page_num >= num_pages => return None,
+                    Ok(_) => (),
+                    Err(e) => return Some(Err(e)),
+                }
+                if let Err(e) = self
+                    .file
+                    .paged_file
+                    .read_page(self.page_num, &mut self.page)
+                {
+                    return Some(Err(e));
+                }
+                self.slot_num = 0;
+                self.slots = vec![];
+                let page_num = self.page_num;
+                match RecordBasedFileMgr::get_page_kind(&self.page, page_num) {
+                    Ok(PageKind::Data) => {
+                        match RecordBasedFileMgr::get_slot_directory_hdr(&self.page, page_num) {
+                            Ok(hdr) => self.slots = hdr.slots_vec,
+                            Err(e) => return Some(Err(e)),
+                        }
+                    }
+                    Ok(_) => (),
+                    Err(e) => return Some(Err(e)),
+                }
+                continue;
+            }
+
+            let rid = RecordId {
+                page_num: self.page_num as u32,
+                slot_num: self.slot_num as u32,
+            };
+            let slot = &self.slots[self.slot_num];
+            self.slot_num += 1;
+            let result = match slot.status() {
+                // Forwarded records are visited through their stub
+                SlotStatus::Dead | SlotStatus::Forwarded => continue,
+                SlotStatus::Valid => self
+                    .file
+                    .scan_record(
+                        &self.page.as_buf()[slot.record_range()],
+                        &self.projection,
+                        self.condition.as_ref(),
+                        self.filter.as_mut(),
+                    )
+                    .map_err(|e| e.at_record(&rid)),
+                SlotStatus::Moved(_) => match self.file.find_record(&rid) {
+                    Ok((loc, page, range)) => self
+                        .file
+                        .scan_record(
+                            &page.as_buf()[range],
+                            &self.projection,
+                            self.condition.as_ref(),
+                            self.filter.as_mut(),
+                        )
+                        .map_err(|e| e.at_record(&loc)),
+                    Err(e) => Err(e),
+                },
+            };
+            match result {
+                Ok(Some(row)) => return Some(Ok((rid, row))),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Scan<'a> {
+    type Item = Result<(RecordId, HashMap<String, AttributeValue>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (rid, row) = match self.next_row()? {
+            Ok(next) => next,
+            Err(e) => return Some(Err(e)),
+        };
+        let vals = self
+            .projection
+            .iter()
+            .zip(row.into_values())
+            .map(|(attr_idx, attr_val)| {
+                (
+                    self.file.attributes[*attr_idx].name.clone(),
+                    attr_val.unwrap_or(AttributeValue::Null),
+                )
+            })
+            .collect();
+        Some(Ok((rid, vals)))
+    }
+}
+
+impl<'a> Iterator for Rows<'a> {
+    type Item = Result<(RecordId, Row)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.scan.next_row()
+    }
+}
+
+/// The headers at the start of a record, which locate each field without
+/// decoding the others. They're borrowed from the record, so parsing doesn't
+/// allocate.
+pub(crate) struct RecordHeader<'a> {
+    pub(crate) num_attributes: usize,
+    bmp: BitmapRef<'a>,
+    offset_hdrs: &'a [u8],
+    // Offset of the first field
+    data_start: usize,
+}
+
+impl<'a> RecordHeader<'a> {
+    pub(crate) fn parse(buf: &'a [u8]) -> Result<Self> {
+        // Record Format:
+        // Num_Attributes: 2 byte unsigned int
+        // Null_Bitmap: Variable length bitmap, byte length is ceil(num_attributes / 8)
+        // Offset_Headers: 2 bytes for each non-null attribute
+        let invalid = || Error::corrupt("Invalid record header");
+        let num_attributes_bytes = buf.get(..2).ok_or_else(invalid)?;
+        let num_attributes =
+            u16::from_le_bytes([num_attributes_bytes[0], num_attributes_bytes[1]]) as usize;
+
+        let bmp_end = 2 + Bitmap::bmp_size_in_bytes(num_attributes);
+        let bmp = BitmapRef::new(num_attributes, buf.get(2..bmp_end).ok_or_else(invalid)?)?;
+
+        let data_start = bmp_end + bmp.count_ones_before(num_attributes)? * 2;
+        let offset_hdrs = buf.get(bmp_end..data_start).ok_or_else(invalid)?;
+
+        Ok(Self {
+            num_attributes,
+            bmp,
+            offset_hdrs,
+            data_start,
+        })
+    }
+
+    fn offset_hdr(&self, valid_idx: usize) -> u16 {
+        let bytes = &self.offset_hdrs[valid_idx * 2..valid_idx * 2 + 2];
+        u16::from_le_bytes([bytes[0], bytes[1]])
+    }
+
+    /// Byte range of the field at attr_idx within a record of record_len
+    /// bytes, and whether it is spilled. None if the field is null.
+    pub(crate) fn field_range(
+        &self,
+        attr_idx: usize,
+        record_len: usize,
+    ) -> Result<Option<(Range<usize>, bool)>> {
+        if attr_idx >= self.num_attributes || !self.bmp.get(attr_idx) {
+            return Ok(None);
+        }
+        // Offset headers point to the END of each field, so a field starts
+        // where the previous non-null field ends
+        let valid_idx = self.bmp.count_ones_before(attr_idx)?;
+        let start = match valid_idx {
+            0 => self.data_start,
+            _ => (self.offset_hdr(valid_idx - 1) & !SPILLED_FLAG) as usize,
+        };
+        let offset_hdr = self.offset_hdr(valid_idx);
+        let end = (offset_hdr & !SPILLED_FLAG) as usize;
+        if start > end || end > record_len {
+            return Err(Error::corrupt("Invalid offset header"));
+        }
+        Ok(Some((start..end, offset_hdr & SPILLED_FLAG != 0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::*;
+    use crate::uuid::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn rbfm_create_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        // File should be created with no errors
+        RecordBasedFileMgr::create(&file_path, vec![]).unwrap();
 
         // Opening the newly created file should work
         RecordBasedFileMgr::open(&file_path, vec![]).unwrap();
 
-        // Creating the same file twice should cause an error
-        assert!(RecordBasedFileMgr::create(&file_path, vec!()).is_err());
+        // Creating the same file twice should cause an error
+        assert!(RecordBasedFileMgr::create(&file_path, vec!()).is_err());
+    }
+
+    #[test]
+    fn rbfm_serialize_test() {
+        // We check that the serialized header matches the minimum size we'd expect
+        let mut hdr = SlotDirectoryHeader {
+            page_kind: PageKind::Data,
+            data_start_offset: PAGE_SIZE as u32,
+            slots_vec: vec![],
+        };
+        let hdr_size = bincode::serialized_size(&hdr).unwrap() as usize;
+        assert_eq!(hdr_size, HDR_SIZE);
+
+        hdr.slots_vec.push(SlotDirectoryRecordEntry {
+            length: 0,
+            offset: 0,
+        });
+        let hdr_size = bincode::serialized_size(&hdr).unwrap() as usize;
+        assert_eq!(hdr_size, HDR_SIZE + RECORD_ENTRY_SIZE);
+
+        hdr.slots_vec.push(SlotDirectoryRecordEntry {
+            length: 0,
+            offset: 0,
+        });
+        let hdr_size = bincode::serialized_size(&hdr).unwrap() as usize;
+        assert_eq!(hdr_size, HDR_SIZE + RECORD_ENTRY_SIZE * 2);
+    }
+
+    #[test]
+    fn rbfm_insert_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new("FirstName", AttributeType::Varchar { len: 20 }),
+            Attribute::new("Age", AttributeType::Int),
+            Attribute::new("PowerLevel", AttributeType::Real),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+
+        let mut attr_vals = HashMap::new();
+        attr_vals.insert(
+            "FirstName".to_string(),
+            AttributeValue::Varchar("Cow".to_string()),
+        );
+        attr_vals.insert("Age".to_string(), AttributeValue::Int(28));
+        attr_vals.insert("PowerLevel".to_string(), AttributeValue::Real(8999.999));
+        let rid = file.insert(&attr_vals).unwrap();
+        assert_eq!(
+            rid,
+            RecordId {
+                page_num: 2,
+                slot_num: 0,
+            }
+        );
+
+        let read_result = file.read(&rid).unwrap();
+        assert_eq!(read_result, attr_vals);
+
+        // Try with one null
+        attr_vals.insert("Age".to_string(), AttributeValue::Null);
+        let rid = file.insert(&attr_vals).unwrap();
+        assert_eq!(
+            rid,
+            RecordId {
+                page_num: 2,
+                slot_num: 1,
+            }
+        );
+
+        let read_result = file.read(&rid).unwrap();
+        assert_eq!(read_result, attr_vals);
+
+        // Try with all null values
+        let null_attr_vals: HashMap<String, AttributeValue> = HashMap::new();
+        let rid = file.insert(&null_attr_vals).unwrap();
+        assert_eq!(
+            rid,
+            RecordId {
+                page_num: 2,
+                slot_num: 2,
+            }
+        );
+
+        // Omitted attributes read back as Null
+        let read_result = file.read(&rid).unwrap();
+        let all_null: HashMap<String, AttributeValue> = ["FirstName", "Age", "PowerLevel"]
+            .iter()
+            .map(|name| (name.to_string(), AttributeValue::Null))
+            .collect();
+        assert_eq!(read_result, all_null);
+        assert_ne!(read_result, attr_vals);
+    }
+
+    #[test]
+    fn rbfm_binary_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new("Hash", AttributeType::Varbinary { len: 4 }),
+            Attribute::new("Thumbnail", AttributeType::Blob),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+
+        // Bytes that aren't valid UTF-8 round trip
+        let mut attr_vals = HashMap::new();
+        attr_vals.insert(
+            "Hash".to_string(),
+            AttributeValue::Varbinary(vec![0xff, 0x00, 0xfe]),
+        );
+        attr_vals.insert("Thumbnail".to_string(), AttributeValue::Blob(vec![0xc3]));
+        let rid = file.insert(&attr_vals).unwrap();
+        assert_eq!(file.read(&rid).unwrap(), attr_vals);
+
+        // Varbinary values are limited to their max len
+        attr_vals.insert("Hash".to_string(), AttributeValue::Varbinary(vec![0; 5]));
+        assert!(file.insert(&attr_vals).is_err());
+
+        // A blob larger than a page spills to overflow pages
+        let big_blob: Vec<u8> = (0..3 * PAGE_SIZE).map(|i| (i % 251) as u8).collect();
+        attr_vals.insert("Hash".to_string(), AttributeValue::Varbinary(vec![1]));
+        attr_vals.insert("Thumbnail".to_string(), AttributeValue::Blob(big_blob));
+        let rid = file.insert(&attr_vals).unwrap();
+        assert_eq!(
+            rid,
+            RecordId {
+                page_num: 2,
+                slot_num: 1,
+            }
+        );
+        assert_eq!(file.paged_file.num_pages().unwrap(), 7);
+        assert_eq!(file.read(&rid).unwrap(), attr_vals);
+
+        // Overflow pages aren't used for records, and don't hold slots
+        attr_vals.remove("Thumbnail");
+        let rid = file.insert(&attr_vals).unwrap();
+        assert_eq!(rid.page_num, 2);
+        assert!(file
+            .read(&RecordId {
+                page_num: 3,
+                slot_num: 0
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn rbfm_char_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new("Country", AttributeType::Char { len: 3 }),
+            Attribute::new("Id", AttributeType::Int),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+
+        let mut attr_vals = HashMap::new();
+        attr_vals.insert(
+            "Country".to_string(),
+            AttributeValue::Char("US".to_string()),
+        );
+        attr_vals.insert("Id".to_string(), AttributeValue::Int(7));
+        let rid = file.insert(&attr_vals).unwrap();
+
+        // Pad bytes are trimmed on read
+        let read_result = file.read(&rid).unwrap();
+        match read_result.get("Country").unwrap() {
+            AttributeValue::Char(val) => assert_eq!(val, "US"),
+            _ => panic!(),
+        }
+        assert_eq!(read_result, attr_vals);
+
+        // Values longer than the fixed length are rejected,
+        // unless only trailing blanks would be cut off
+        attr_vals.insert(
+            "Country".to_string(),
+            AttributeValue::Char("USAB".to_string()),
+        );
+        assert!(file.insert(&attr_vals).is_err());
+        attr_vals.insert(
+            "Country".to_string(),
+            AttributeValue::Char("USA  ".to_string()),
+        );
+        let rid = file.insert(&attr_vals).unwrap();
+        assert_eq!(
+            file.read(&rid).unwrap().get("Country"),
+            Some(&AttributeValue::Char("USA".to_string()))
+        );
+    }
+
+    #[test]
+    fn rbfm_uuid_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![Attribute::new("Key", AttributeType::Uuid)];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+
+        let key: Uuid = "67e55044-10b1-426f-9247-bb680e5fe0c8".parse().unwrap();
+        let mut attr_vals = HashMap::new();
+        attr_vals.insert("Key".to_string(), AttributeValue::Uuid(key));
+        // Num_Attributes + null bitmap + 1 offset header + 16 bytes
+        assert_eq!(
+            file.field_layout(&[AttributeValue::Uuid(key)]).unwrap().1,
+            2 + 1 + 2 + 16
+        );
+
+        let rid = file.insert(&attr_vals).unwrap();
+        assert_eq!(file.read(&rid).unwrap(), attr_vals);
+    }
+
+    #[test]
+    fn rbfm_json_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![Attribute::new("Payload", AttributeType::Json)];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+
+        let doc: Json = r#"{"user": {"tags": ["a", "b", "c"]}}"#.parse().unwrap();
+        let mut attr_vals = HashMap::new();
+        attr_vals.insert("Payload".to_string(), AttributeValue::Json(doc));
+        let rid = file.insert(&attr_vals).unwrap();
+
+        let read_result = file.read(&rid).unwrap();
+        assert_eq!(read_result, attr_vals);
+        let path: JsonPath = "$.user.tags[2]".parse().unwrap();
+        match read_result.get("Payload").unwrap() {
+            AttributeValue::Json(doc) => assert_eq!(
+                doc.extract(&path),
+                Some(AttributeValue::Varchar("c".to_string()))
+            ),
+            _ => panic!(),
+        }
+
+        // Scans can filter on a path, in inline and spilled documents
+        let mut rids = vec![rid];
+        for (tag, len) in [("d", 0), ("c", 0), ("c", PAGE_SIZE)].iter() {
+            let doc = Json::Object(vec![
+                (
+                    "user".to_string(),
+                    Json::Object(vec![(
+                        "tags".to_string(),
+                        Json::Array(vec![
+                            Json::String("a".to_string()),
+                            Json::String("b".to_string()),
+                            Json::String(tag.to_string()),
+                        ]),
+                    )]),
+                ),
+                ("pad".to_string(), Json::String("x".repeat(*len))),
+            ]);
+            attr_vals.insert("Payload".to_string(), AttributeValue::Json(doc));
+            rids.push(file.insert(&attr_vals).unwrap());
+        }
+        attr_vals.insert("Payload".to_string(), AttributeValue::Json(Json::Int(1)));
+        rids.push(file.insert(&attr_vals).unwrap());
+        let condition = ScanCondition::json_path(
+            "Payload",
+            "$.user.tags[2]",
+            CompOp::Eq,
+            AttributeValue::Varchar("c".to_string()),
+        )
+        .unwrap();
+        let scanned: Vec<_> = file
+            .scan(Some(&[]), Some(condition))
+            .unwrap()
+            .map(|result| result.unwrap().0)
+            .collect();
+        assert_eq!(scanned, [rids[0].clone(), rids[2].clone(), rids[3].clone()]);
+        assert!(
+            ScanCondition::json_path("Payload", "user", CompOp::Eq, AttributeValue::Null).is_err()
+        );
+
+        // Documents with numbers JSON can't represent are rejected
+        let doc = Json::Object(vec![("a".to_string(), Json::Real(f64::NAN))]);
+        attr_vals.insert("Payload".to_string(), AttributeValue::Json(doc));
+        assert!(matches!(
+            file.insert(&attr_vals).unwrap_err(),
+            Error::TypeMismatch { .. }
+        ));
+    }
+
+    #[test]
+    fn rbfm_array_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new(
+                "Tags",
+                AttributeType::Array(Box::new(AttributeType::Varchar { len: 8 })),
+            ),
+            Attribute::new(
+                "Samples",
+                AttributeType::Array(Box::new(AttributeType::Real)),
+            ),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+
+        let mut attr_vals = HashMap::new();
+        attr_vals.insert(
+            "Tags".to_string(),
+            AttributeValue::Array(vec![
+                Some(AttributeValue::Varchar("new".to_string())),
+                None,
+                Some(AttributeValue::Varchar("sale".to_string())),
+            ]),
+        );
+        attr_vals.insert("Samples".to_string(), AttributeValue::Array(vec![]));
+        let rid = file.insert(&attr_vals).unwrap();
+        assert_eq!(file.read(&rid).unwrap(), attr_vals);
+
+        assert_eq!(
+            file.read_array_element(&rid, "Tags", 2).unwrap(),
+            Some(AttributeValue::Varchar("sale".to_string()))
+        );
+        assert_eq!(file.read_array_element(&rid, "Tags", 1).unwrap(), None);
+        assert!(file.read_array_element(&rid, "Tags", 3).is_err());
+        assert!(file.read_array_element(&rid, "Samples", 0).is_err());
+        assert!(file.read_array_element(&rid, "Missing", 0).is_err());
+
+        // Elements must match the element type
+        attr_vals.insert(
+            "Samples".to_string(),
+            AttributeValue::Array(vec![Some(AttributeValue::Int(1))]),
+        );
+        assert!(file.insert(&attr_vals).is_err());
+
+        // A null array reads as None
+        attr_vals.remove("Tags");
+        attr_vals.insert(
+            "Samples".to_string(),
+            AttributeValue::Array(vec![Some(AttributeValue::Real(0.5))]),
+        );
+        let rid = file.insert(&attr_vals).unwrap();
+        assert_eq!(file.read_array_element(&rid, "Tags", 0).unwrap(), None);
+        assert_eq!(
+            file.read_array_element(&rid, "Samples", 0).unwrap(),
+            Some(AttributeValue::Real(0.5))
+        );
+    }
+
+    #[test]
+    fn rbfm_lenient_insert_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new("Age", AttributeType::Int),
+            Attribute::new("PowerLevel", AttributeType::Real),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+
+        let mut attr_vals = HashMap::new();
+        attr_vals.insert("Age".to_string(), AttributeValue::Varchar("28".to_string()));
+        attr_vals.insert("PowerLevel".to_string(), AttributeValue::Int(9000));
+        // Rejected unless lenient
+        assert!(file.insert(&attr_vals).is_err());
+
+        file.set_lenient(true);
+        let rid = file.insert(&attr_vals).unwrap();
+        let read_result = file.read(&rid).unwrap();
+        assert!(matches!(
+            read_result.get("Age"),
+            Some(AttributeValue::Int(28))
+        ));
+        assert!(matches!(
+            read_result.get("PowerLevel"),
+            Some(AttributeValue::Real(r)) if *r == 9000.0
+        ));
+
+        // Bad values still fail, naming the attribute
+        attr_vals.insert(
+            "Age".to_string(),
+            AttributeValue::Varchar("old".to_string()),
+        );
+        assert_eq!(
+            file.insert(&attr_vals).unwrap_err().to_string(),
+            "Attribute Age: Cannot cast Varchar to Int: \"old\" is not an integer"
+        );
+    }
+
+    #[test]
+    fn rbfm_constraints_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new("Name", AttributeType::Varchar { len: 20 }).not_null(),
+            Attribute::new("Age", AttributeType::Int).with_default(AttributeValue::Int(18)),
+            Attribute::new("Level", AttributeType::Int)
+                .not_null()
+                .with_default(AttributeValue::Int(1)),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+
+        // Omitted attributes get their defaults
+        let mut attr_vals = HashMap::new();
+        attr_vals.insert(
+            "Name".to_string(),
+            AttributeValue::Varchar("Cow".to_string()),
+        );
+        let rid = file.insert(&attr_vals).unwrap();
+        let read_result = file.read(&rid).unwrap();
+        assert_eq!(read_result["Age"], AttributeValue::Int(18));
+        assert_eq!(read_result["Level"], AttributeValue::Int(1));
+
+        // An explicit Null overrides a default
+        attr_vals.insert("Age".to_string(), AttributeValue::Null);
+        let rid = file.insert(&attr_vals).unwrap();
+        assert_eq!(file.read(&rid).unwrap()["Age"], AttributeValue::Null);
+
+        // NOT NULL violations name the attribute
+        let not_null_err = |err: Error| match err {
+            Error::NotNullViolation { attribute } => attribute,
+            err => panic!("unexpected error {}", err),
+        };
+        attr_vals.insert("Level".to_string(), AttributeValue::Null);
+        assert_eq!(not_null_err(file.insert(&attr_vals).unwrap_err()), "Level");
+        attr_vals.remove("Level");
+        attr_vals.remove("Name");
+        assert_eq!(not_null_err(file.insert(&attr_vals).unwrap_err()), "Name");
+
+        // Misspelled attributes are rejected rather than treated as null
+        attr_vals.insert(
+            "name".to_string(),
+            AttributeValue::Varchar("Cow".to_string()),
+        );
+        let err = file.insert(&attr_vals).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown attribute name");
+
+        // Defaults must be valid for their attribute
+        let attrs = vec![Attribute::new("Age", AttributeType::Int)
+            .with_default(AttributeValue::Varchar("18".to_string()))];
+        assert!(RecordBasedFileMgr::open(&file_path, attrs).is_err());
+    }
+
+    #[test]
+    fn rbfm_schema_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = || {
+            vec![
+                Attribute::new("Name", AttributeType::Varchar { len: 20 }).not_null(),
+                Attribute::new("Age", AttributeType::Int).with_default(AttributeValue::Int(18)),
+                Attribute::new(
+                    "Scores",
+                    AttributeType::Array(Box::new(AttributeType::Real)),
+                ),
+            ]
+        };
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs()).unwrap();
+        let mut attr_vals = HashMap::new();
+        attr_vals.insert(
+            "Name".to_string(),
+            AttributeValue::Varchar("Cow".to_string()),
+        );
+        let rid = file.insert(&attr_vals).unwrap();
+        drop(file);
+
+        // The schema is read back from the file
+        let mut file = RecordBasedFileMgr::open_with_stored_schema(&file_path).unwrap();
+        assert!(file.check_schema(&attrs()).is_ok());
+        assert_eq!(file.attributes()[1].default, Some(AttributeValue::Int(18)));
+        assert_eq!(file.read(&rid).unwrap()["Age"], AttributeValue::Int(18));
+        // Defaults still apply to new records
+        let rid = file.insert(&attr_vals).unwrap();
+        assert_eq!(file.read(&rid).unwrap()["Age"], AttributeValue::Int(18));
+
+        RecordBasedFileMgr::open(&file_path, attrs()).unwrap();
+
+        // Mismatched schemas are rejected
+        let mut wrong_type = attrs();
+        wrong_type[1].attribute_type = AttributeType::Real;
+        let err = RecordBasedFileMgr::open(&file_path, wrong_type)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Schema mismatch: attribute 1 is Age Real, but the file has Age Int"
+        );
+        let mut reordered = attrs();
+        reordered.swap(0, 1);
+        assert!(RecordBasedFileMgr::open(&file_path, reordered).is_err());
+        let mut missing = attrs();
+        missing.pop();
+        assert!(RecordBasedFileMgr::open(&file_path, missing).is_err());
+        let mut nullable = attrs();
+        nullable[0].nullable = true;
+        assert!(RecordBasedFileMgr::open(&file_path, nullable).is_err());
+
+        // Files in other formats are rejected. The first page of files written
+        // before formats were versioned is a slot directory, or a schema page
+        // without the magic number.
+        let write_first_page = |bytes: Vec<u8>| {
+            let mut page = P::new();
+            page.as_mut_buf()[..bytes.len()].copy_from_slice(&bytes);
+            let mut paged_file = Pf::open(&file_path).unwrap();
+            paged_file.write_page(SCHEMA_PAGE_NUM, &page).unwrap();
+        };
+        let unsupported = || match RecordBasedFileMgr::open_with_stored_schema(&file_path)
+            .err()
+            .unwrap()
+        {
+            Error::UnsupportedFormat(message) => message,
+            err => panic!("unexpected error {}", err),
+        };
+        write_first_page(bincode::serialize(&(PAGE_SIZE as u32, 0u64)).unwrap());
+        assert!(unsupported().starts_with("Not a record file"));
+        let no_fields: Vec<Option<StoredAttribute>> = vec![];
+        write_first_page(bincode::serialize(&(PageKind::Schema, no_fields)).unwrap());
+        assert!(unsupported().starts_with("Not a record file"));
+        let newer = SchemaPageHeader {
+            page_kind: PageKind::Schema,
+            magic: MAGIC,
+            format_version: FORMAT_VERSION + 1,
+            attributes: vec![],
+        };
+        write_first_page(bincode::serialize(&newer).unwrap());
+        assert_eq!(
+            unsupported(),
+            format!(
+                "Format version {}, expected {}",
+                FORMAT_VERSION + 1,
+                FORMAT_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn rbfm_schema_evolution_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new("Name", AttributeType::Varchar { len: 20 }),
+            Attribute::new("Age", AttributeType::Int),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+        let mut attr_vals = HashMap::new();
+        attr_vals.insert(
+            "Name".to_string(),
+            AttributeValue::Varchar("Cow".to_string()),
+        );
+        attr_vals.insert("Age".to_string(), AttributeValue::Int(28));
+        let old_rid = file.insert(&attr_vals).unwrap();
+
+        file.drop_attribute("Age").unwrap();
+        file.add_attribute(
+            Attribute::new("Level", AttributeType::Int)
+                .not_null()
+                .with_default(AttributeValue::Int(1)),
+        )
+        .unwrap();
+        // A new attribute doesn't see the old data of a dropped one
+        file.add_attribute(Attribute::new("Age", AttributeType::Int))
+            .unwrap();
+
+        // Old records are read with defaults for the new attributes
+        let expected: HashMap<String, AttributeValue> = [
+            ("Name", AttributeValue::Varchar("Cow".to_string())),
+            ("Level", AttributeValue::Int(1)),
+            ("Age", AttributeValue::Null),
+        ]
+        .iter()
+        .map(|(name, val)| (name.to_string(), val.clone()))
+        .collect();
+        assert_eq!(file.read(&old_rid).unwrap(), expected);
+
+        let mut new_vals = expected.clone();
+        new_vals.insert("Level".to_string(), AttributeValue::Int(5));
+        let new_rid = file.insert(&new_vals).unwrap();
+        assert_eq!(file.read(&new_rid).unwrap(), new_vals);
+
+        // Changes are stored in the file
+        drop(file);
+        let mut file = RecordBasedFileMgr::open_with_stored_schema(&file_path).unwrap();
+        let names: Vec<&str> = file
+            .attributes()
+            .iter()
+            .map(|attr| attr.name.as_str())
+            .collect();
+        assert_eq!(names, ["Name", "Level", "Age"]);
+        assert_eq!(file.read(&old_rid).unwrap(), expected);
+
+        // Only the old record needs migrating, and reads don't change
+        assert_eq!(file.migrate().unwrap(), 1);
+        assert_eq!(file.migrate().unwrap(), 0);
+        assert_eq!(file.read(&old_rid).unwrap(), expected);
+        assert_eq!(file.read(&new_rid).unwrap(), new_vals);
+
+        // Invalid changes are rejected
+        assert!(file
+            .add_attribute(Attribute::new("Name", AttributeType::Int))
+            .is_err());
+        assert!(file
+            .add_attribute(Attribute::new("Rank", AttributeType::Int).not_null())
+            .is_err());
+        assert!(file.drop_attribute("Rank").is_err());
+    }
+
+    #[test]
+    fn rbfm_delete_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new("Name", AttributeType::Varchar { len: 20 }),
+            Attribute::new("Photo", AttributeType::Blob),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+
+        let mut rids = vec![];
+        let mut vals = vec![];
+        for i in 0..5 {
+            let mut insert_vals = HashMap::new();
+            insert_vals.insert(
+                "Name".to_string(),
+                AttributeValue::Varchar(format!("Name {}", i)),
+            );
+            // One large photo, stored in overflow pages
+            let photo_len = if i == 2 { 4 * PAGE_SIZE } else { 10 };
+            insert_vals.insert(
+                "Photo".to_string(),
+                AttributeValue::Blob(vec![i as u8; photo_len]),
+            );
+            rids.push(file.insert(&insert_vals).unwrap());
+            vals.push(insert_vals);
+        }
+        let (_, hdr) = file.read_slot_page(&rids[0]).unwrap();
+        let data_start = hdr.data_start_offset;
+        let num_pages = file.paged_file.num_pages().unwrap();
+
+        // Deleting from the middle of the page keeps the other records intact
+        file.delete(&rids[1]).unwrap();
+        file.delete(&rids[2]).unwrap();
+        for i in [0, 3, 4].iter() {
+            assert_eq!(file.read(&rids[*i]).unwrap(), vals[*i]);
+        }
+        let err = file.read(&rids[1]).unwrap_err();
+        assert!(matches!(err, Error::RecordDeleted(rid) if rid == rids[1]));
+
+        // The bytes are reclaimed, and the slots are left dead
+        let (_, hdr) = file.read_slot_page(&rids[0]).unwrap();
+        assert!(hdr.data_start_offset > data_start);
+        assert_eq!(hdr.slots_vec.len(), 5);
+
+        // Deleting twice, or deleting a slot that doesn't exist, fails
+        let err = file.delete(&rids[1]).unwrap_err();
+        assert!(matches!(err, Error::RecordDeleted(_)));
+        let missing = [
+            RecordId {
+                page_num: 2,
+                slot_num: 5,
+            },
+            RecordId {
+                page_num: 0,
+                slot_num: 0,
+            },
+            RecordId {
+                page_num: 100,
+                slot_num: 0,
+            },
+        ];
+        for rid in missing.iter() {
+            let err = file.delete(rid).unwrap_err();
+            assert!(matches!(err, Error::RecordNotFound(ref r) if r == rid));
+        }
+
+        // The freed overflow pages are reused by later inserts
+        let mut insert_vals = HashMap::new();
+        insert_vals.insert("Photo".to_string(), AttributeValue::Blob(vec![0; 100]));
+        let rid = file.insert(&insert_vals).unwrap();
+        assert_eq!(rid.page_num, 2);
+        for _ in 0..100 {
+            file.insert(&insert_vals).unwrap();
+        }
+        assert_eq!(file.paged_file.num_pages().unwrap(), num_pages);
+    }
+
+    #[test]
+    fn rbfm_delete_moved_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![Attribute::new("Level", AttributeType::Int)];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+
+        let mut insert_vals = HashMap::new();
+        insert_vals.insert("Level".to_string(), AttributeValue::Int(1));
+        let rid = file.insert(&insert_vals).unwrap();
+        let target = file.insert(&insert_vals).unwrap();
+
+        // Turn the first slot into a forwarding stub for the second record
+        let (mut page, mut hdr) = file.read_slot_page(&rid).unwrap();
+        RecordBasedFileMgr::remove_record_bytes(&mut page, &mut hdr, 0);
+        hdr.slots_vec[0] = SlotDirectoryRecordEntry::moved(&target);
+        hdr.slots_vec[target.slot_num as usize].length |= FORWARDED_FLAG;
+        file.write_data_page(2, &mut page, &hdr).unwrap();
+        assert_eq!(file.read(&rid).unwrap(), insert_vals);
+
+        // Deleting through the stub deletes both the stub and the record
+        file.delete(&rid).unwrap();
+        assert!(file.read(&rid).is_err());
+        assert!(file.read(&target).is_err());
+        let (_, hdr) = file.read_slot_page(&rid).unwrap();
+        assert_eq!(hdr.data_start_offset as usize, PAGE_SIZE);
+    }
+
+    #[test]
+    fn rbfm_update_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new("Name", AttributeType::Varchar { len: 4000 }),
+            Attribute::new("Level", AttributeType::Int),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+        let vals = |name: &str, level: i32| {
+            let mut vals = HashMap::new();
+            vals.insert(
+                "Name".to_string(),
+                AttributeValue::Varchar(name.to_string()),
+            );
+            vals.insert("Level".to_string(), AttributeValue::Int(level));
+            vals
+        };
+
+        // Fill the first data page
+        let mut rids = vec![file.insert(&vals("Cow", 1)).unwrap()];
+        let long_name = "x".repeat(1000);
+        loop {
+            let rid = file.insert(&vals(&long_name, 2)).unwrap();
+            if rid.page_num != 2 {
+                break;
+            }
+            rids.push(rid);
+        }
+
+        // Records that still fit are rewritten in place
+        file.update(&rids[0], &vals("Moo", 3)).unwrap();
+        assert_eq!(file.read(&rids[0]).unwrap(), vals("Moo", 3));
+        assert_eq!(file.locate_record(&rids[0]).unwrap().0, rids[0]);
+        file.update(&rids[1], &vals("Short", 4)).unwrap();
+        assert_eq!(file.read(&rids[1]).unwrap(), vals("Short", 4));
+        assert_eq!(file.locate_record(&rids[1]).unwrap().0, rids[1]);
+        for rid in rids[2..].iter() {
+            assert_eq!(file.read(rid).unwrap(), vals(&long_name, 2));
+        }
+
+        // Records that don't fit move, but keep their RecordId
+        let longer_name = "y".repeat(2000);
+        file.update(&rids[0], &vals(&longer_name, 5)).unwrap();
+        assert_eq!(file.read(&rids[0]).unwrap(), vals(&longer_name, 5));
+        let (target, _, _) = file.locate_record(&rids[0]).unwrap();
+        assert_ne!(target.page_num, 2);
+        // The new location isn't a RecordId of its own
+        let err = file.read(&target).unwrap_err();
+        assert!(matches!(err, Error::RecordNotFound(_)));
+
+        // Moving again updates the forwarding address rather than adding a hop
+        while file.insert(&vals(&long_name, 2)).unwrap().page_num == target.page_num {}
+        let longest_name = "z".repeat(4000);
+        file.update(&rids[0], &vals(&longest_name, 6)).unwrap();
+        assert_eq!(file.read(&rids[0]).unwrap(), vals(&longest_name, 6));
+        let (new_target, _, _) = file.locate_record(&rids[0]).unwrap();
+        assert_ne!(new_target, target);
+        let (_, hdr) = file.read_slot_page(&rids[0]).unwrap();
+        match hdr.slots_vec[0].status() {
+            SlotStatus::Moved(rid) => assert_eq!(rid, new_target),
+            _ => panic!("Expected a forwarding address"),
+        }
+        let (_, hdr) = file.read_slot_page(&target).unwrap();
+        assert!(matches!(
+            hdr.slots_vec[target.slot_num as usize].status(),
+            SlotStatus::Dead
+        ));
+
+        // A moved record goes home once it fits again
+        file.update(&rids[0], &vals("Cow", 7)).unwrap();
+        assert_eq!(file.read(&rids[0]).unwrap(), vals("Cow", 7));
+        assert_eq!(file.locate_record(&rids[0]).unwrap().0, rids[0]);
+        let (_, hdr) = file.read_slot_page(&new_target).unwrap();
+        assert!(matches!(
+            hdr.slots_vec[new_target.slot_num as usize].status(),
+            SlotStatus::Dead
+        ));
+
+        // Invalid updates are rejected and leave the record as it was
+        let mut bad_vals = vals("Cow", 8);
+        bad_vals.insert("Rank".to_string(), AttributeValue::Int(1));
+        assert!(file.update(&rids[0], &bad_vals).is_err());
+        assert_eq!(file.read(&rids[0]).unwrap(), vals("Cow", 7));
+        file.delete(&rids[1]).unwrap();
+        assert!(file.update(&rids[1], &vals("Cow", 8)).is_err());
+    }
+
+    #[test]
+    fn rbfm_update_failure_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![Attribute::new("Name", AttributeType::Varchar { len: 4000 })];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+        let row = |name: &str| Row::new(vec![Some(AttributeValue::Varchar(name.to_string()))]);
+
+        // Fill the first data page, so the last record goes to the second
+        let rid = file.insert_row(&row("Cow")).unwrap();
+        let long_name = "x".repeat(3000);
+        let other = loop {
+            let other = file.insert_row(&row(&long_name)).unwrap();
+            if other.page_num != rid.page_num {
+                break other;
+            }
+        };
+
+        // Growing the record means moving it to the second page. If that
+        // fails, the record is still where it was.
+        let mut vals = HashMap::new();
+        vals.insert("Name".to_string(), AttributeValue::Varchar(long_name));
+        let mut page = P::new();
+        page.as_mut_buf()
+            .iter_mut()
+            .take(16)
+            .for_each(|b| *b = 0xff);
+        file.paged_file
+            .write_page(other.page_num as u64, &page)
+            .unwrap();
+        assert!(matches!(
+            file.update(&rid, &vals).unwrap_err(),
+            Error::Corruption { .. }
+        ));
+        assert_eq!(file.read_row(&rid).unwrap(), row("Cow"));
+        assert_eq!(file.locate_record(&rid).unwrap().0, rid);
+    }
+
+    #[test]
+    fn rbfm_scan_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new("Name", AttributeType::Varchar { len: 2000 }),
+            Attribute::new("Level", AttributeType::Int),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+        let vals = |name: &str, level: Option<i32>| {
+            let mut vals = HashMap::new();
+            vals.insert(
+                "Name".to_string(),
+                AttributeValue::Varchar(name.to_string()),
+            );
+            let level = level.map_or(AttributeValue::Null, AttributeValue::Int);
+            vals.insert("Level".to_string(), level);
+            vals
+        };
+
+        // Enough records to fill a few pages
+        let mut expected = vec![];
+        for i in 0..30 {
+            let level = if i % 10 == 0 { None } else { Some(i) };
+            let insert_vals = vals(&"x".repeat(500), level);
+            let rid = file.insert(&insert_vals).unwrap();
+            expected.push((rid, insert_vals));
+        }
+        // Dead slots are skipped, and moved records appear once, under their
+        // original RecordId
+        file.delete(&expected[3].0).unwrap();
+        expected.remove(3);
+        let moved_vals = vals(&"y".repeat(2000), Some(100));
+        file.update(&expected[0].0, &moved_vals).unwrap();
+        expected[0].1 = moved_vals;
+        assert_ne!(file.locate_record(&expected[0].0).unwrap().0, expected[0].0);
+
+        let scanned: Vec<_> = file.scan(None, None).unwrap().map(Result::unwrap).collect();
+        assert_eq!(scanned, expected);
+
+        // Projection
+        let scanned: Vec<_> = file
+            .scan(Some(&["Level"]), None)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(scanned.len(), expected.len());
+        for ((rid, vals), (expected_rid, expected_vals)) in scanned.iter().zip(expected.iter()) {
+            assert_eq!(rid, expected_rid);
+            assert_eq!(vals.len(), 1);
+            assert_eq!(vals["Level"], expected_vals["Level"]);
+        }
+
+        // The condition doesn't need to be projected, and NULLs never match
+        let condition = ScanCondition::new("Level", CompOp::Lt, AttributeValue::Int(5));
+        let rids: Vec<RecordId> = file
+            .scan(Some(&["Name"]), Some(condition))
+            .unwrap()
+            .map(|result| result.unwrap().0)
+            .collect();
+        let expected_rids: Vec<RecordId> =
+            [1, 2, 3].iter().map(|i| expected[*i].0.clone()).collect();
+        assert_eq!(rids, expected_rids);
+        let condition = ScanCondition::new("Level", CompOp::Ge, AttributeValue::Int(100));
+        let rids: Vec<RecordId> = file
+            .scan(None, Some(condition))
+            .unwrap()
+            .map(|result| result.unwrap().0)
+            .collect();
+        assert_eq!(rids, [expected[0].0.clone()]);
+
+        // Unknown attributes are rejected
+        assert!(file.scan(Some(&["Rank"]), None).is_err());
+        let condition = ScanCondition::new("Rank", CompOp::Eq, AttributeValue::Int(1));
+        assert!(file.scan(None, Some(condition)).is_err());
+    }
+
+    #[test]
+    fn rbfm_read_attribute_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new("Name", AttributeType::Varchar { len: 20 }),
+            Attribute::new("Photo", AttributeType::Blob),
+            Attribute::new("Guild", AttributeType::Varchar { len: 20 }),
+            Attribute::new("Level", AttributeType::Int),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+
+        let mut insert_vals = HashMap::new();
+        insert_vals.insert(
+            "Name".to_string(),
+            AttributeValue::Varchar("Cow".to_string()),
+        );
+        insert_vals.insert(
+            "Photo".to_string(),
+            AttributeValue::Blob(vec![7; 2 * PAGE_SIZE]),
+        );
+        insert_vals.insert("Level".to_string(), AttributeValue::Int(28));
+        let rid = file.insert(&insert_vals).unwrap();
+
+        assert_eq!(
+            file.read_attribute(&rid, "Level").unwrap(),
+            AttributeValue::Int(28)
+        );
+        assert_eq!(
+            file.read_attribute(&rid, "Guild").unwrap(),
+            AttributeValue::Null
+        );
+        assert_eq!(
+            file.read_attribute(&rid, "Photo").unwrap(),
+            insert_vals["Photo"]
+        );
+
+        let vals = file
+            .read_attributes(&rid, &["Level", "Name", "Guild"])
+            .unwrap();
+        assert_eq!(vals.len(), 3);
+        assert_eq!(vals["Name"], insert_vals["Name"]);
+        assert_eq!(vals["Level"], insert_vals["Level"]);
+        assert_eq!(vals["Guild"], AttributeValue::Null);
+        assert!(file.read_attributes(&rid, &[]).unwrap().is_empty());
+
+        // Added attributes read as their default in older records
+        file.add_attribute(
+            Attribute::new("Rank", AttributeType::Int).with_default(AttributeValue::Int(1)),
+        )
+        .unwrap();
+        assert_eq!(
+            file.read_attribute(&rid, "Rank").unwrap(),
+            AttributeValue::Int(1)
+        );
+
+        let err = file.read_attribute(&rid, "Age").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown attribute Age");
+        assert!(file.read_attributes(&rid, &["Name", "Age"]).is_err());
+        file.delete(&rid).unwrap();
+        assert!(file.read_attribute(&rid, "Name").is_err());
     }
 
     #[test]
-    fn rbfm_serialize_test() {
-        // We check that the serialized header matches the minimum size we'd expect
-        let mut hdr = SlotDirectoryHeader {
-            data_start_offset: PAGE_SIZE as u32,
-            slots_vec: vec![],
+    fn rbfm_free_space_map_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![Attribute::new("Name", AttributeType::Varchar { len: 1000 })];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+        let mut insert_vals = HashMap::new();
+        insert_vals.insert(
+            "Name".to_string(),
+            AttributeValue::Varchar("x".repeat(1000)),
+        );
+
+        // Pages fill up in order
+        let rids: Vec<RecordId> = (0..40)
+            .map(|_| file.insert(&insert_vals).unwrap())
+            .collect();
+        let last_page = rids.last().unwrap().page_num;
+        assert!(last_page > 4);
+        for pair in rids.windows(2) {
+            assert!(pair[0].page_num <= pair[1].page_num);
+        }
+
+        // Space freed by a delete is found through the map, including after
+        // reopening the file
+        let freed = rids.iter().find(|rid| rid.page_num == 3).unwrap();
+        file.delete(freed).unwrap();
+        drop(file);
+        let mut file = RecordBasedFileMgr::open_with_stored_schema(&file_path).unwrap();
+        assert_eq!(file.insert(&insert_vals).unwrap().page_num, 3);
+        assert!(file.insert(&insert_vals).unwrap().page_num >= last_page);
+    }
+
+    #[test]
+    fn rbfm_slot_reuse_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![Attribute::new("Name", AttributeType::Varchar { len: 4000 })];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+        let vals = |name: &str| {
+            let mut vals = HashMap::new();
+            vals.insert(
+                "Name".to_string(),
+                AttributeValue::Varchar(name.to_string()),
+            );
+            vals
         };
-        let hdr_size = bincode::serialized_size(&hdr).unwrap() as usize;
-        assert_eq!(hdr_size, HDR_SIZE);
 
-        hdr.slots_vec.push(SlotDirectoryRecordEntry {
-            length: 0,
-            offset: 0,
-        });
-        let hdr_size = bincode::serialized_size(&hdr).unwrap() as usize;
-        assert_eq!(hdr_size, HDR_SIZE + RECORD_ENTRY_SIZE);
+        let rids: Vec<RecordId> = (0..4)
+            .map(|i| file.insert(&vals(&i.to_string())).unwrap())
+            .collect();
+        file.delete(&rids[1]).unwrap();
+        file.delete(&rids[2]).unwrap();
 
-        hdr.slots_vec.push(SlotDirectoryRecordEntry {
-            length: 0,
-            offset: 0,
-        });
-        let hdr_size = bincode::serialized_size(&hdr).unwrap() as usize;
-        assert_eq!(hdr_size, HDR_SIZE + RECORD_ENTRY_SIZE * 2);
+        // Dead slots are reused first, lowest first
+        assert_eq!(file.insert(&vals("a")).unwrap(), rids[1]);
+        assert_eq!(file.insert(&vals("b")).unwrap(), rids[2]);
+        let rid = file.insert(&vals("c")).unwrap();
+        assert_eq!(rid.slot_num, 4);
+        assert_eq!(file.read(&rids[1]).unwrap(), vals("a"));
+
+        // The slot of a moved record keeps its forwarding address, so it
+        // isn't reused even though it holds no bytes on the page
+        let long_name = "x".repeat(3000);
+        for rid in [&rids[0], &rids[1], &rids[3]].iter() {
+            file.update(rid, &vals(&long_name)).unwrap();
+        }
+        assert_ne!(file.locate_record(&rids[3]).unwrap().0, rids[3]);
+        let rid = file.insert(&vals("d")).unwrap();
+        assert_eq!(
+            rid,
+            RecordId {
+                page_num: rids[0].page_num,
+                slot_num: 5
+            }
+        );
+        assert_eq!(file.read(&rids[3]).unwrap(), vals(&long_name));
+
+        // A dead slot means a record fits with fewer free bytes
+        let (_, hdr) = file.read_slot_page(&rids[0]).unwrap();
+        assert_eq!(
+            RecordBasedFileMgr::insert_space(&hdr),
+            RecordBasedFileMgr::free_space(&hdr) - RECORD_ENTRY_SIZE
+        );
+        file.delete(&rid).unwrap();
+        let (_, hdr) = file.read_slot_page(&rids[0]).unwrap();
+        assert_eq!(
+            RecordBasedFileMgr::insert_space(&hdr),
+            RecordBasedFileMgr::free_space(&hdr)
+        );
     }
 
     #[test]
-    fn rbfm_insert_test() {
+    fn rbfm_reorganize_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![Attribute::new("Name", AttributeType::Varchar { len: 4000 })];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+        let vals = |name: &str| {
+            let mut vals = HashMap::new();
+            vals.insert(
+                "Name".to_string(),
+                AttributeValue::Varchar(name.to_string()),
+            );
+            vals
+        };
+
+        // Fill the first data page
+        let name = "x".repeat(1000);
+        let mut rids = vec![];
+        loop {
+            let rid = file.insert(&vals(&name)).unwrap();
+            if rid.page_num != 2 {
+                file.delete(&rid).unwrap();
+                break;
+            }
+            rids.push(rid);
+        }
+
+        // Records that shrink leave holes
+        for rid in rids.iter().step_by(2) {
+            file.update(rid, &vals("y")).unwrap();
+        }
+        let (_, hdr) = file.read_slot_page(&rids[0]).unwrap();
+        let free_space = RecordBasedFileMgr::free_space(&hdr);
+        let total_free_space = RecordBasedFileMgr::total_free_space(&hdr);
+        assert!(free_space < 1000);
+        assert!(total_free_space > 3000);
+
+        // Inserting a record that only fits in the holes reorganizes the page
+        let rid = file.insert(&vals(&"z".repeat(2000))).unwrap();
+        assert_eq!(rid.page_num, 2);
+        let (_, hdr) = file.read_slot_page(&rids[0]).unwrap();
+        assert_eq!(
+            RecordBasedFileMgr::free_space(&hdr),
+            RecordBasedFileMgr::total_free_space(&hdr)
+        );
+
+        // So does growing a record
+        file.update(&rids[1], &vals("y")).unwrap();
+        file.update(&rids[3], &vals(&"w".repeat(1500))).unwrap();
+        assert_eq!(file.locate_record(&rids[3]).unwrap().0, rids[3]);
+
+        for (i, rid) in rids.iter().enumerate() {
+            let expected = match i {
+                1 => vals("y"),
+                3 => vals(&"w".repeat(1500)),
+                _ if i % 2 == 0 => vals("y"),
+                _ => vals(&name),
+            };
+            assert_eq!(file.read(rid).unwrap(), expected);
+        }
+        assert_eq!(file.read(&rid).unwrap(), vals(&"z".repeat(2000)));
+
+        // Reorganizing directly leaves the records readable
+        file.update(&rids[5], &vals("v")).unwrap();
+        file.reorganize_page(2).unwrap();
+        let (_, hdr) = file.read_slot_page(&rids[0]).unwrap();
+        assert_eq!(
+            RecordBasedFileMgr::free_space(&hdr),
+            RecordBasedFileMgr::total_free_space(&hdr)
+        );
+        assert_eq!(file.read(&rids[5]).unwrap(), vals("v"));
+        assert_eq!(file.read(&rids[4]).unwrap(), vals("y"));
+    }
+
+    #[test]
+    fn rbfm_large_value_test() {
         let dir = tempdir().unwrap();
         let file_path = dir.path().join("testfile");
         let attrs = vec![
-            Attribute {
-                name: "FirstName".to_string(),
-                attribute_type: AttributeType::Varchar { len: 20 },
-            },
-            Attribute {
-                name: "Age".to_string(),
-                attribute_type: AttributeType::Int,
-            },
-            Attribute {
-                name: "PowerLevel".to_string(),
-                attribute_type: AttributeType::Real,
-            },
+            Attribute::new("Bio", AttributeType::Varchar { len: 100_000 }),
+            Attribute::new(
+                "Samples",
+                AttributeType::Array(Box::new(AttributeType::Int)),
+            ),
+            Attribute::new("Level", AttributeType::Int),
         ];
         let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
 
+        // Values are stored inline while the record fits on a page
         let mut attr_vals = HashMap::new();
-        attr_vals.insert(
-            "FirstName".to_string(),
-            AttributeValue::Varchar("Cow".to_string()),
-        );
-        attr_vals.insert("Age".to_string(), AttributeValue::Int(28));
-        attr_vals.insert("PowerLevel".to_string(), AttributeValue::Real(8999.999));
+        attr_vals.insert("Bio".to_string(), AttributeValue::Varchar("x".repeat(1100)));
+        let (spilled, _) = file
+            .field_layout(&[
+                attr_vals["Bio"].clone(),
+                AttributeValue::Null,
+                AttributeValue::Null,
+            ])
+            .unwrap();
+        assert_eq!(spilled, vec![false; 3]);
+        let num_pages = file.paged_file.num_pages().unwrap();
+        let rid = file.insert(&attr_vals).unwrap();
+        assert_eq!(file.paged_file.num_pages().unwrap(), num_pages);
+        file.delete(&rid).unwrap();
+
+        // Values too large for a page are stored in overflow pages, with a
+        // prefix inline
+        let bio: String = (0..3 * PAGE_SIZE)
+            .map(|i| (b'a' + (i % 26) as u8) as char)
+            .collect();
+        let samples: Vec<Option<AttributeValue>> =
+            (0..3000).map(|i| Some(AttributeValue::Int(i))).collect();
+        let mut attr_vals = HashMap::new();
+        attr_vals.insert("Bio".to_string(), AttributeValue::Varchar(bio.clone()));
+        attr_vals.insert("Samples".to_string(), AttributeValue::Array(samples));
+        attr_vals.insert("Level".to_string(), AttributeValue::Int(28));
         let rid = file.insert(&attr_vals).unwrap();
+        assert_eq!(file.read(&rid).unwrap(), attr_vals);
         assert_eq!(
-            rid,
-            RecordId {
-                page_num: 0,
-                slot_num: 0,
-            }
+            file.read_array_element(&rid, "Samples", 2999).unwrap(),
+            Some(AttributeValue::Int(2999))
         );
 
-        let read_result = file.read(&rid).unwrap();
-        assert_eq!(read_result, attr_vals);
+        let (_, page, range) = file.find_record(&rid).unwrap();
+        let record = &page.as_buf()[range];
+        let hdr = RecordHeader::parse(record).unwrap();
+        let (field, spilled) = hdr.field_range(0, record.len()).unwrap().unwrap();
+        assert!(spilled);
+        assert_eq!(
+            &record[field][OVERFLOW_POINTER_SIZE..],
+            &bio.as_bytes()[..SPILL_PREFIX_LEN]
+        );
 
-        // Try with one null
-        attr_vals.remove("Age");
+        // Overflow pages are replaced on update and freed on delete. Freed
+        // pages are reused by later chains, so the file stops growing.
+        let bio = bio.to_uppercase();
+        attr_vals.insert("Bio".to_string(), AttributeValue::Varchar(bio));
+        file.update(&rid, &attr_vals).unwrap();
+        assert_eq!(file.read(&rid).unwrap(), attr_vals);
+        let num_pages = file.paged_file.num_pages().unwrap();
+        for level in 0..3 {
+            attr_vals.insert("Level".to_string(), AttributeValue::Int(level));
+            file.update(&rid, &attr_vals).unwrap();
+            assert_eq!(file.read(&rid).unwrap(), attr_vals);
+        }
+        assert_eq!(file.paged_file.num_pages().unwrap(), num_pages);
+        file.delete(&rid).unwrap();
+        assert!(file.read(&rid).is_err());
+        let rid = file.insert(&attr_vals).unwrap();
+        assert_eq!(file.read(&rid).unwrap(), attr_vals);
+        assert_eq!(file.paged_file.num_pages().unwrap(), num_pages);
+
+        // Records too large for a page spill their largest values, even if
+        // none is too large on its own
+        let attrs: Vec<Attribute> = (0..12)
+            .map(|i| {
+                Attribute::new(
+                    &format!("Part{}", i),
+                    AttributeType::Varbinary { len: 1000 },
+                )
+            })
+            .collect();
+        let file_path = dir.path().join("testfile2");
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+        let attr_vals: HashMap<String, AttributeValue> = (0..12)
+            .map(|i| {
+                (
+                    format!("Part{}", i),
+                    AttributeValue::Varbinary(vec![i as u8; 900 + i]),
+                )
+            })
+            .collect();
+        let vals: Vec<AttributeValue> = (0..12)
+            .map(|i| attr_vals[&format!("Part{}", i)].clone())
+            .collect();
+        let (spilled, size) = file.field_layout(&vals).unwrap();
+        assert!(size <= MAX_RECORD_SIZE);
+        assert_eq!(spilled.iter().filter(|spilled| **spilled).count(), 4);
+        // The largest values are the ones spilled
+        assert!(spilled[8..].iter().all(|spilled| *spilled));
         let rid = file.insert(&attr_vals).unwrap();
+        assert_eq!(file.read(&rid).unwrap(), attr_vals);
+
+        // Migrating a record frees the overflow pages of dropped values
+        let (_, page, range) = file.find_record(&rid).unwrap();
+        let record = &page.as_buf()[range];
+        let hdr = RecordHeader::parse(record).unwrap();
+        let (field, _) = hdr.field_range(11, record.len()).unwrap().unwrap();
+        let (ptr, _) = RecordBasedFileMgr::spilled_field(&record[field]).unwrap();
+        let pages = chain_pages(&mut file.paged_file, &ptr).unwrap();
+        file.drop_attribute("Part11").unwrap();
+        assert_eq!(file.migrate_page(rid.page_num as u64).unwrap(), 1);
+        for page_num in pages {
+            let page_num = page_num as u64;
+            let mut page = P::new();
+            file.paged_file.read_page(page_num, &mut page).unwrap();
+            let hdr = RecordBasedFileMgr::get_slot_directory_hdr(&page, page_num).unwrap();
+            assert!(hdr.slots_vec.is_empty());
+        }
+        let mut attr_vals = attr_vals;
+        attr_vals.remove("Part11");
+        assert_eq!(file.read(&rid).unwrap(), attr_vals);
+    }
+
+    #[test]
+    fn rbfm_overflow_failure_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![Attribute::new(
+            "Bio",
+            AttributeType::Varchar { len: 100_000 },
+        )];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+        let row = |bio: String| Row::new(vec![Some(AttributeValue::Varchar(bio))]);
+        let rid = file.insert_row(&row("x".repeat(1000))).unwrap();
+        let num_pages = file.paged_file.num_pages().unwrap();
+
+        // Overflow pages are written before the record's page is chosen. If
+        // storing the record fails, they're freed again.
+        let (_, mut page, _) = file.find_record(&rid).unwrap();
+        page.as_mut_buf()[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        file.paged_file
+            .write_page(rid.page_num as u64, &page)
+            .unwrap();
+        let bio = "y".repeat(3 * PAGE_SIZE);
+        assert!(file.insert_row(&row(bio.clone())).is_err());
+        let mut vals = HashMap::new();
+        vals.insert("Bio".to_string(), AttributeValue::Varchar(bio));
+        let options = BatchOptions::default();
+        assert!(file.insert_batch(&[vals.clone(), vals], options).is_err());
+        let end = file.paged_file.num_pages().unwrap();
+        assert!(end > num_pages);
+        for page_num in num_pages..end {
+            file.paged_file.read_page(page_num, &mut page).unwrap();
+            let hdr = RecordBasedFileMgr::get_slot_directory_hdr(&page, page_num).unwrap();
+            assert!(hdr.slots_vec.is_empty());
+        }
+    }
+
+    #[test]
+    fn rbfm_insert_batch_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new("Name", AttributeType::Varchar { len: 100 }),
+            Attribute::new("Level", AttributeType::Int).not_null(),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+        let vals = |i: i32| {
+            let mut vals = HashMap::new();
+            vals.insert("Name".to_string(), AttributeValue::Varchar("x".repeat(90)));
+            vals.insert("Level".to_string(), AttributeValue::Int(i));
+            vals
+        };
+
+        // New pages are filled up to the fill factor
+        let records: Vec<_> = (0..1000).map(vals).collect();
+        let options = BatchOptions {
+            fill_factor: 0.5,
+            append_only: true,
+        };
+        let rids = file.insert_batch(&records, options).unwrap();
+        assert_eq!(rids.len(), records.len());
+        for (rid, vals) in rids.iter().zip(records.iter()) {
+            assert_eq!(&file.read(rid).unwrap(), vals);
+        }
+        // The empty first data page is skipped when appending
+        assert_eq!(rids[0].page_num, 3);
+        for pair in rids.windows(2) {
+            assert!(pair[0].page_num <= pair[1].page_num);
+        }
+        let last_page = rids.last().unwrap().page_num;
+        for page_num in 3..last_page {
+            let (_, hdr) = file
+                .read_slot_page(&RecordId {
+                    page_num,
+                    slot_num: 0,
+                })
+                .unwrap();
+            let used_space = PAGE_SIZE - RecordBasedFileMgr::free_space(&hdr);
+            assert!(used_space <= PAGE_SIZE / 2);
+            assert!(used_space > PAGE_SIZE / 2 - 120);
+        }
+
+        // Without append_only, free space in existing pages is used first
+        file.delete(&rids[10]).unwrap();
+        let rids = file
+            .insert_batch(&[vals(1), vals(2), vals(3)], BatchOptions::default())
+            .unwrap();
+        assert_eq!(rids[0].page_num, 2);
+        assert_eq!(rids[1].page_num, 2);
+        assert_eq!(rids[2].page_num, 2);
+
+        // Nothing is written if any record is invalid
+        let num_pages = file.paged_file.num_pages().unwrap();
+        let mut bad_vals = vals(4);
+        bad_vals.insert("Level".to_string(), AttributeValue::Null);
+        let records = vec![vals(4); 200]
+            .into_iter()
+            .chain(Some(bad_vals))
+            .collect::<Vec<_>>();
+        assert!(file.insert_batch(&records, options).is_err());
+        assert_eq!(file.paged_file.num_pages().unwrap(), num_pages);
+
+        let options = BatchOptions {
+            fill_factor: 0.0,
+            append_only: false,
+        };
+        assert!(file.insert_batch(&[vals(5)], options).is_err());
+        assert!(file
+            .insert_batch(&[], BatchOptions::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn rbfm_row_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new("Id", AttributeType::Int).not_null(),
+            Attribute::new("Name", AttributeType::Varchar { len: 20 }),
+            Attribute::new("Level", AttributeType::Int).with_default(AttributeValue::Int(1)),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+        assert_eq!(file.attribute_index("Level").unwrap(), 2);
+        assert!(file.attribute_index("Missing").is_err());
+
+        // Defaults only apply through default_row
+        let mut row = file.default_row();
         assert_eq!(
-            rid,
-            RecordId {
-                page_num: 0,
-                slot_num: 1,
-            }
+            row,
+            Row::new(vec![None, None, Some(AttributeValue::Int(1))])
         );
+        row.set(0, Some(AttributeValue::Int(7)));
+        let rid = file.insert_row(&row).unwrap();
+        assert_eq!(file.read_row(&rid).unwrap(), row);
+        let row = Row::new(vec![Some(AttributeValue::Int(8)), None, None]);
+        let rid2 = file.insert_row(&row).unwrap();
+        assert_eq!(file.read_row(&rid2).unwrap(), row);
+        assert_eq!(file.read(&rid2).unwrap()["Level"], AttributeValue::Null);
 
-        let read_result = file.read(&rid).unwrap();
-        assert_eq!(read_result, attr_vals);
+        // Some(Null) is the same as None
+        assert_eq!(
+            Row::new(vec![Some(AttributeValue::Null), None]),
+            Row::new(vec![None, None])
+        );
+        let mut null_row = row.clone();
+        null_row.set(1, Some(AttributeValue::Null));
+        assert_eq!(null_row, row);
+        assert_eq!(null_row.get(1), None);
 
-        // Try with all null values
-        let null_attr_vals: HashMap<String, AttributeValue> = HashMap::new();
-        let rid = file.insert(&null_attr_vals).unwrap();
+        // Rows must have a value for every attribute, and respect NOT NULL
+        assert!(file.insert_row(&Row::new(vec![])).is_err());
+        let err = file
+            .insert_row(&Row::new(vec![Some(AttributeValue::Null), None, None]))
+            .unwrap_err();
+        assert!(matches!(err, Error::NotNullViolation { .. }));
+
+        // Scans yield rows in projection order
+        let rows: Vec<_> = file
+            .scan(Some(&["Level", "Id"]), None)
+            .unwrap()
+            .rows()
+            .map(Result::unwrap)
+            .collect();
         assert_eq!(
-            rid,
-            RecordId {
-                page_num: 0,
-                slot_num: 2,
-            }
+            rows,
+            vec![
+                (
+                    rid.clone(),
+                    Row::new(vec![
+                        Some(AttributeValue::Int(1)),
+                        Some(AttributeValue::Int(7))
+                    ])
+                ),
+                (rid2, Row::new(vec![None, Some(AttributeValue::Int(8))])),
+            ]
         );
 
-        let read_result = file.read(&rid).unwrap();
-        assert_eq!(read_result, null_attr_vals);
-        assert_ne!(read_result, attr_vals);
+        // The index follows schema changes
+        file.drop_attribute("Name").unwrap();
+        assert_eq!(file.attribute_index("Level").unwrap(), 1);
+        file.add_attribute(Attribute::new("Name", AttributeType::Varchar { len: 20 }))
+            .unwrap();
+        assert_eq!(file.attribute_index("Name").unwrap(), 2);
+        assert_eq!(
+            file.read_row(&rid).unwrap(),
+            Row::new(vec![
+                Some(AttributeValue::Int(7)),
+                Some(AttributeValue::Int(1)),
+                None
+            ])
+        );
+    }
+
+    #[test]
+    fn rbfm_record_ref_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new("Name", AttributeType::Varchar { len: 10_000 }),
+            Attribute::new("Level", AttributeType::Int),
+            Attribute::new("Tags", AttributeType::Array(Box::new(AttributeType::Int))),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+        let mut rids = vec![];
+        for i in 0..10 {
+            let name = if i == 9 {
+                // Spilled
+                "x".repeat(PAGE_SIZE)
+            } else {
+                format!("name{}", i)
+            };
+            let row = Row::new(vec![
+                Some(AttributeValue::Varchar(name)),
+                if i % 3 == 0 {
+                    None
+                } else {
+                    Some(AttributeValue::Int(i))
+                },
+                Some(AttributeValue::Array(vec![
+                    Some(AttributeValue::Int(i)),
+                    None,
+                ])),
+            ]);
+            rids.push(file.insert_row(&row).unwrap());
+        }
+
+        let name = file
+            .read_ref(&rids[1], |record| {
+                assert_eq!(record.get(1)?, ValueRef::Int(1));
+                assert_eq!(record.get_str(0)?, Some("name1"));
+                assert!(record.get_str(1).is_err());
+                assert_eq!(record.to_row()?, file_row(1));
+                Ok(record.get_str(0)?.unwrap().to_string())
+            })
+            .unwrap();
+        assert_eq!(name, "name1");
+        file.read_ref(&rids[3], |record| {
+            assert!(record.get(1)?.is_null());
+            assert_eq!(record.get_str(1)?, None);
+            assert!(record.get(3).is_err());
+            Ok(())
+        })
+        .unwrap();
+        // Spilled values can't be borrowed, but can be read
+        let spilled_name = AttributeValue::Varchar("x".repeat(PAGE_SIZE));
+        file.read_ref(&rids[9], |record| {
+            assert_eq!(record.get(0)?, ValueRef::Spilled);
+            assert!(record.get_str(0).is_err());
+            assert!(record.get(0)?.to_value().is_err());
+            assert_eq!(record.get_value(0)?, spilled_name);
+            assert_eq!(record.get_value(1)?, AttributeValue::Null);
+            assert_eq!(record.to_row()?.get(0), Some(&spilled_name));
+            Ok(())
+        })
+        .unwrap();
+
+        // Filters see views, and only records that pass are returned
+        let mut seen = 0;
+        let scanned: Vec<_> = file
+            .scan(Some(&["Level"]), None)
+            .unwrap()
+            .with_filter(|record| {
+                seen += 1;
+                Ok(match record.get(0)? {
+                    ValueRef::Varchar(name) => name.ends_with('2') || name.ends_with('4'),
+                    _ => false,
+                })
+            })
+            .rows()
+            .map(|result| result.unwrap().0)
+            .collect();
+        assert_eq!(seen, 10);
+        assert_eq!(scanned, vec![rids[2].clone(), rids[4].clone()]);
+        let scanned: Vec<_> = file
+            .scan(Some(&["Level"]), None)
+            .unwrap()
+            .with_filter(|record| match record.get(0)? {
+                ValueRef::Spilled => Ok(record.get_value(0)? == spilled_name),
+                _ => Ok(false),
+            })
+            .rows()
+            .map(|result| result.unwrap().0)
+            .collect();
+        assert_eq!(scanned, vec![rids[9].clone()]);
+
+        // Conditions on spilled values still work
+        let condition = ScanCondition::new(
+            "Name",
+            CompOp::Eq,
+            AttributeValue::Varchar("x".repeat(PAGE_SIZE)),
+        );
+        let scanned: Vec<_> = file
+            .scan(Some(&["Level"]), Some(condition))
+            .unwrap()
+            .map(|result| result.unwrap().0)
+            .collect();
+        assert_eq!(scanned, vec![rids[9].clone()]);
+
+        // Fields missing from older records read as the default
+        let tags = AttributeValue::Array(vec![Some(AttributeValue::Int(0))]);
+        file.add_attribute(
+            Attribute::new("More", AttributeType::Array(Box::new(AttributeType::Int)))
+                .with_default(tags.clone()),
+        )
+        .unwrap();
+        file.read_ref(&rids[0], |record| {
+            assert_eq!(record.get(3)?, ValueRef::Value(&tags));
+            assert_eq!(record.get(3)?.to_value()?, tags);
+            Ok(())
+        })
+        .unwrap();
+
+        fn file_row(i: i32) -> Row {
+            Row::new(vec![
+                Some(AttributeValue::Varchar(format!("name{}", i))),
+                Some(AttributeValue::Int(i)),
+                Some(AttributeValue::Array(vec![
+                    Some(AttributeValue::Int(i)),
+                    None,
+                ])),
+            ])
+        }
+    }
+
+    #[test]
+    fn rbfm_error_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new("Name", AttributeType::Varchar { len: 8 }),
+            Attribute::new("Level", AttributeType::Int),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+        let row = |name: &str, level| {
+            Row::new(vec![
+                Some(AttributeValue::Varchar(name.to_string())),
+                Some(AttributeValue::Int(level)),
+            ])
+        };
+        let rid = file.insert_row(&row("Cow", 1)).unwrap();
+        let rid2 = file.insert_row(&row("Pig", 2)).unwrap();
+
+        // Invalid values name their attribute
+        match file.insert_row(&row("Chickens!", 1)).unwrap_err() {
+            Error::VarcharOverflow {
+                attribute,
+                len,
+                max,
+            } => assert_eq!((attribute.as_str(), len, max), ("Name", 9, 8)),
+            err => panic!("unexpected error {}", err),
+        }
+        let mut bad_level = row("Cow", 1);
+        bad_level.set(1, Some(AttributeValue::Real(1.5)));
+        match file.insert_row(&bad_level).unwrap_err() {
+            Error::TypeMismatch { attribute, .. } => assert_eq!(attribute, "Level"),
+            err => panic!("unexpected error {}", err),
+        }
+
+        let missing = RecordId {
+            page_num: rid.page_num,
+            slot_num: 100,
+        };
+        assert!(matches!(file.read(&missing), Err(Error::RecordNotFound(r)) if r == missing));
+        file.delete(&rid2).unwrap();
+        assert!(matches!(file.read_row(&rid2), Err(Error::RecordDeleted(r)) if r == rid2));
+        let rid2 = file.insert_row(&row("Pig", 2)).unwrap();
+
+        // A record with an offset header past its end is reported where it
+        // is, and doesn't affect other records
+        let (_, mut page, range) = file.find_record(&rid).unwrap();
+        // After the 2 byte field count, 1 byte null bitmap and Name's header
+        let level_hdr = range.start + 5;
+        page.as_mut_buf()[level_hdr..level_hdr + 2].copy_from_slice(&0x7fffu16.to_le_bytes());
+        file.paged_file
+            .write_page(rid.page_num as u64, &page)
+            .unwrap();
+        let is_corrupt_record = |err: Error| {
+            matches!(err, Error::Corruption { page, slot, .. }
+                if page == Some(rid.page_num as u64) && slot == Some(rid.slot_num))
+        };
+        assert!(is_corrupt_record(file.read(&rid).unwrap_err()));
+        assert!(is_corrupt_record(
+            file.read_attribute(&rid, "Level").unwrap_err()
+        ));
+        assert!(is_corrupt_record(
+            file.read_ref(&rid, |record| record.to_row()).unwrap_err()
+        ));
+        assert_eq!(file.read_row(&rid2).unwrap(), row("Pig", 2));
+        let results: Vec<_> = file.scan(Some(&["Level"]), None).unwrap().collect();
+        assert_eq!(results.len(), 2);
+        assert!(results.into_iter().any(|result| match result {
+            Err(err) => is_corrupt_record(err),
+            Ok(_) => false,
+        }));
+
+        // An invalid slot directory is reported for its page
+        page.as_mut_buf()
+            .iter_mut()
+            .take(16)
+            .for_each(|b| *b = 0xff);
+        file.paged_file
+            .write_page(rid.page_num as u64, &page)
+            .unwrap();
+        assert!(matches!(
+            file.read(&rid2).unwrap_err(),
+            Error::Corruption { page: Some(page), slot: None, .. } if page == rid.page_num as u64
+        ));
+        let err: std::io::Error = file.read(&rid2).unwrap_err().into();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        // Overlapping records are rejected before their lengths are used
+        let mut page = P::new();
+        let mut hdr = RecordBasedFileMgr::init_rb_page(&mut page);
+        hdr.data_start_offset = 40;
+        hdr.slots_vec = vec![
+            SlotDirectoryRecordEntry {
+                length: PAGE_SIZE as u32 - 40,
+                offset: 40,
+            },
+            SlotDirectoryRecordEntry {
+                length: PAGE_SIZE as u32 - 40,
+                offset: 40,
+            },
+        ];
+        RecordBasedFileMgr::write_slot_directory_hdr(&mut page, &hdr).unwrap();
+        file.paged_file
+            .write_page(rid.page_num as u64, &page)
+            .unwrap();
+        assert!(matches!(
+            file.insert_row(&row("Hen", 3)).unwrap_err(),
+            Error::Corruption { slot: Some(1), .. }
+        ));
+        assert!(matches!(
+            file.reorganize_page(rid.page_num as u64).unwrap_err(),
+            Error::Corruption { slot: Some(1), .. }
+        ));
+    }
+
+    #[test]
+    fn rbfm_check_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let attrs = vec![
+            Attribute::new("Name", AttributeType::Varchar { len: 2000 }),
+            Attribute::new("Level", AttributeType::Int).not_null(),
+        ];
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs).unwrap();
+        let names = [
+            "Cow".to_string(),
+            "Pig".to_string(),
+            "x".repeat(1500),
+            "Hen".to_string(),
+        ];
+        let rids: Vec<RecordId> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let row = Row::new(vec![
+                    Some(AttributeValue::Varchar(name.clone())),
+                    Some(AttributeValue::Int(i as i32)),
+                ]);
+                file.insert_row(&row).unwrap()
+            })
+            .collect();
+        let page_num = rids[0].page_num as u64;
+        let report = file.check(false).unwrap();
+        assert!(report.problems.is_empty());
+        assert_eq!(report.records, 4);
+
+        // Rewrite page_num's records and slot directory
+        fn edit_page<T>(
+            file: &mut RecordBasedFileMgr,
+            page_num: u64,
+            f: impl FnOnce(&mut P, &mut SlotDirectoryHeader) -> T,
+        ) -> T {
+            let mut page = P::new();
+            file.paged_file.read_page(page_num, &mut page).unwrap();
+            let mut hdr: SlotDirectoryHeader = bincode::deserialize(page.as_buf()).unwrap();
+            let result = f(&mut page, &mut hdr);
+            RecordBasedFileMgr::write_slot_directory_hdr(&mut page, &hdr).unwrap();
+            file.paged_file.write_page(page_num, &page).unwrap();
+            result
+        }
+        let problems = |report: &FsckReport| -> Vec<(Option<u32>, bool)> {
+            report
+                .problems
+                .iter()
+                .map(|problem| (problem.slot, problem.repaired))
+                .collect()
+        };
+
+        // Records are checked field by field against the schema
+        edit_page(&mut file, page_num, |page, hdr| {
+            let buf = page.as_mut_buf();
+            // After the 2 byte field count, 1 byte null bitmap and 2 offset headers
+            buf[hdr.slots_vec[0].record_range().start + 7] = 0xff;
+            // Level is NOT NULL
+            buf[hdr.slots_vec[1].record_range().start + 2] &= !0b10;
+            // Only 2 fields
+            buf[hdr.slots_vec[3].record_range().start + 2] |= 0b100;
+        });
+        let report = file.check(true).unwrap();
+        assert_eq!(
+            problems(&report),
+            vec![(Some(0), false), (Some(1), false), (Some(3), false)]
+        );
+        assert!(report.problems[0].message.contains("UTF-8"));
+        assert!(report.problems[1].message.contains("Level"));
+        assert_eq!(
+            report.problems[2].to_string(),
+            format!(
+                "page {}, slot 3: Null bitmap has bits set past the last field",
+                page_num
+            )
+        );
+
+        // Overlapping records, and a data start offset past the records.
+        // The offset isn't repaired while the page has other problems.
+        let offset = edit_page(&mut file, page_num, |_, hdr| {
+            let offset = hdr.slots_vec[0].offset;
+            hdr.slots_vec[0].offset = hdr.slots_vec[2].offset;
+            hdr.data_start_offset = PAGE_SIZE as u32;
+            offset
+        });
+        let report = file.check(true).unwrap();
+        assert_eq!(
+            problems(&report),
+            vec![
+                (Some(0), false),
+                (Some(1), false),
+                (Some(3), false),
+                (Some(2), false),
+                (None, false)
+            ]
+        );
+        assert!(report.problems[3].message.contains("overlaps"));
+        assert_eq!(file.check(false).unwrap().problems.len(), 5);
+
+        // Once the page is otherwise valid, the offset is repaired
+        edit_page(&mut file, page_num, |page, hdr| {
+            hdr.slots_vec[0].offset = offset;
+            let start = hdr.slots_vec[0].record_range().start;
+            page.as_mut_buf()[start + 7] = b'C';
+            let start = hdr.slots_vec[1].record_range().start;
+            page.as_mut_buf()[start + 2] |= 0b10;
+            let start = hdr.slots_vec[3].record_range().start;
+            page.as_mut_buf()[start + 2] &= !0b100;
+        });
+        let report = file.check(true).unwrap();
+        assert_eq!(problems(&report), vec![(None, true)]);
+        assert!(file.check(false).unwrap().problems.is_empty());
+
+        // A forwarding stub must point at a moved record, and a moved
+        // record must have a stub
+        edit_page(&mut file, page_num, |_, hdr| {
+            hdr.slots_vec[0] = SlotDirectoryRecordEntry::moved(&rids[1]);
+            hdr.slots_vec[3].length |= FORWARDED_FLAG;
+        });
+        let report = file.check(false).unwrap();
+        assert_eq!(problems(&report), vec![(Some(0), false), (Some(3), false)]);
+        assert!(report.problems[0].message.contains("not a moved record"));
+        assert!(report.problems[1].message.contains("no forwarding stub"));
+
+        // Repair makes the moved record a record of its own
+        let report = file.check(true).unwrap();
+        assert_eq!(problems(&report), vec![(Some(0), false), (Some(3), true)]);
+        assert_eq!(
+            file.read_row(&rids[3]).unwrap().get(0),
+            Some(&AttributeValue::Varchar("Hen".to_string()))
+        );
     }
 }
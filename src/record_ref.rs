@@ -0,0 +1,228 @@
+// Record views
+//
+// A RecordRef borrows a record's bytes from a page and decodes fields on
+// demand, using the record's null bitmap and offset headers to find each
+// field. Nothing is copied: varchars are read as &str slices of the page, and
+// Json and array values stay encoded until they're converted to owned values.
+// Views let scans test records before paying to materialize them.
+//
+// Values spilled to overflow pages can't be borrowed from the page, so get
+// returns them as ValueRef::Spilled. get_value reads them from the file.
+
+use crate::attribute::*;
+use crate::error::{Error, Result};
+use crate::field;
+use crate::record_based_file_mgr::{Pf, RecordBasedFileMgr, RecordHeader};
+use crate::row::Row;
+use crate::uuid::Uuid;
+use std::cell::RefCell;
+
+/// A value borrowed from a record.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueRef<'a> {
+    Null,
+    Int(i32),
+    Real(f64),
+    Varchar(&'a str),
+    Char(&'a str),
+    Varbinary(&'a [u8]),
+    Blob(&'a [u8]),
+    Uuid(Uuid),
+    /// Encoded Json, decoded by to_value
+    Json(&'a [u8]),
+    /// Encoded array with its attribute type, decoded by to_value
+    Array(&'a AttributeType, &'a [u8]),
+    /// A Json or array value that isn't stored in the record, e.g. the
+    /// default of an attribute added after the record was written
+    Value(&'a AttributeValue),
+    /// A value stored in overflow pages. RecordRef::get_value reads it.
+    Spilled,
+}
+
+impl<'a> ValueRef<'a> {
+    pub fn is_null(&self) -> bool {
+        matches!(self, ValueRef::Null)
+    }
+
+    /// The value of a Varchar or Char.
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self {
+            ValueRef::Varchar(val) | ValueRef::Char(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    /// The value of a Varbinary or Blob.
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match self {
+            ValueRef::Varbinary(val) | ValueRef::Blob(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i32> {
+        match self {
+            ValueRef::Int(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    pub fn as_real(&self) -> Option<f64> {
+        match self {
+            ValueRef::Real(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Copy the value into an AttributeValue.
+    /// Fails for spilled values, which RecordRef::get_value reads instead.
+    pub fn to_value(&self) -> Result<AttributeValue> {
+        Ok(match self {
+            ValueRef::Null => AttributeValue::Null,
+            ValueRef::Int(val) => AttributeValue::Int(*val),
+            ValueRef::Real(val) => AttributeValue::Real(*val),
+            ValueRef::Varchar(val) => AttributeValue::Varchar(val.to_string()),
+            ValueRef::Char(val) => AttributeValue::Char(val.to_string()),
+            ValueRef::Varbinary(val) => AttributeValue::Varbinary(val.to_vec()),
+            ValueRef::Blob(val) => AttributeValue::Blob(val.to_vec()),
+            ValueRef::Uuid(val) => AttributeValue::Uuid(*val),
+            ValueRef::Json(bytes) => field::decode(&AttributeType::Json, bytes)?,
+            ValueRef::Array(attr_type, bytes) => field::decode(attr_type, bytes)?,
+            ValueRef::Value(val) => (*val).clone(),
+            ValueRef::Spilled => {
+                return Err(Error::invalid_input("Value is stored in overflow pages"))
+            }
+        })
+    }
+}
+
+impl<'a> From<&'a AttributeValue> for ValueRef<'a> {
+    fn from(val: &'a AttributeValue) -> Self {
+        match val {
+            AttributeValue::Null => ValueRef::Null,
+            AttributeValue::Int(val) => ValueRef::Int(*val),
+            AttributeValue::Real(val) => ValueRef::Real(*val),
+            AttributeValue::Varchar(val) => ValueRef::Varchar(val),
+            AttributeValue::Char(val) => ValueRef::Char(val),
+            AttributeValue::Varbinary(val) => ValueRef::Varbinary(val),
+            AttributeValue::Blob(val) => ValueRef::Blob(val),
+            AttributeValue::Uuid(val) => ValueRef::Uuid(*val),
+            AttributeValue::Json(_) | AttributeValue::Array(_) => ValueRef::Value(val),
+        }
+    }
+}
+
+/// A view of a record, borrowed from its page.
+/// Fields are indexed by attribute, in schema order, like rows.
+pub struct RecordRef<'a> {
+    buf: &'a [u8],
+    hdr: RecordHeader<'a>,
+    attributes: &'a [Attribute],
+    // Index of each attribute's field within the record
+    field_idxs: &'a [usize],
+    // For reading spilled values
+    paged_file: RefCell<&'a mut Pf>,
+}
+
+impl<'a> RecordRef<'a> {
+    pub(crate) fn new(
+        buf: &'a [u8],
+        attributes: &'a [Attribute],
+        field_idxs: &'a [usize],
+        paged_file: &'a mut Pf,
+    ) -> Result<Self> {
+        Ok(Self {
+            buf,
+            hdr: RecordHeader::parse(buf)?,
+            attributes,
+            field_idxs,
+            paged_file: RefCell::new(paged_file),
+        })
+    }
+
+    /// Number of attributes.
+    pub fn len(&self) -> usize {
+        self.attributes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attributes.is_empty()
+    }
+
+    /// The file's schema.
+    pub fn attributes(&self) -> &'a [Attribute] {
+        self.attributes
+    }
+
+    /// The value of the attribute at attr_idx, decoded without copying.
+    pub fn get(&self, attr_idx: usize) -> Result<ValueRef<'a>> {
+        let attr = self
+            .attributes
+            .get(attr_idx)
+            .ok_or_else(|| Error::invalid_input("Attribute index out of bounds"))?;
+        let field_idx = self.field_idxs[attr_idx];
+        // Records written before the attribute was added don't have the field
+        if field_idx >= self.hdr.num_attributes {
+            return Ok(match &attr.default {
+                Some(default) => ValueRef::from(default),
+                None => ValueRef::Null,
+            });
+        }
+        match self.hdr.field_range(field_idx, self.buf.len())? {
+            None => Ok(ValueRef::Null),
+            Some((_, true)) => Ok(ValueRef::Spilled),
+            Some((range, false)) => field::decode_ref(&attr.attribute_type, &self.buf[range]),
+        }
+    }
+
+    /// The value of the attribute at attr_idx, copied. Unlike get, spilled
+    /// values are read from their overflow pages.
+    pub fn get_value(&self, attr_idx: usize) -> Result<AttributeValue> {
+        match self.get(attr_idx)? {
+            ValueRef::Spilled => {
+                let bytes = RecordBasedFileMgr::read_field_bytes(
+                    &mut self.paged_file.borrow_mut(),
+                    self.buf,
+                    &self.hdr,
+                    self.field_idxs[attr_idx],
+                )?;
+                match bytes {
+                    Some(bytes) => field::decode(&self.attributes[attr_idx].attribute_type, &bytes),
+                    None => Ok(AttributeValue::Null),
+                }
+            }
+            attr_val => attr_val.to_value(),
+        }
+    }
+
+    /// The value of the Varchar or Char attribute at attr_idx, borrowed from
+    /// the page. None if it's null.
+    pub fn get_str(&self, attr_idx: usize) -> Result<Option<&'a str>> {
+        match self.get(attr_idx)? {
+            ValueRef::Null => Ok(None),
+            ValueRef::Varchar(val) | ValueRef::Char(val) => Ok(Some(val)),
+            ValueRef::Spilled => Err(Error::invalid_input(format!(
+                "Attribute {} is stored in overflow pages",
+                self.attributes[attr_idx].name
+            ))),
+            _ => Err(Error::invalid_input(format!(
+                "Attribute {} is not a string",
+                self.attributes[attr_idx].name
+            ))),
+        }
+    }
+
+    /// Copy every attribute into a row, reading spilled values from their
+    /// overflow pages.
+    pub fn to_row(&self) -> Result<Row> {
+        let values = (0..self.len())
+            .map(|attr_idx| {
+                Ok(match self.get_value(attr_idx)? {
+                    AttributeValue::Null => None,
+                    attr_val => Some(attr_val),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Row::new(values))
+    }
+}
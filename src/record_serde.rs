@@ -0,0 +1,735 @@
+// Serde records
+//
+// Converts serde-compatible types to and from records, so existing serde
+// types can be stored without a hand-written mapping.
+//
+// A record is serialized from a struct or map. Each field becomes the value
+// of the attribute with the same name:
+// - bool, and integers that fit in an i32: Int
+// - Floats: Real
+// - Strings and chars: Varchar
+// - Bytes: Varbinary. Sequences of u8, such as Vec<u8>, also fill Varbinary
+//   and Blob attributes.
+// - None and unit: Null
+// - Unit enum variants: Varchar holding the variant name
+// - Sequences and tuples: Array
+// - Nested structs and maps: Json objects
+// Values are then converted to their attribute's type using the explicit
+// casting rules from the cast module, e.g. a String can fill a Uuid attribute.
+//
+// Deserializing accepts the same forms back, and Json attributes deserialize
+// as the equivalent serde data.
+
+use crate::attribute::*;
+use crate::cast;
+use crate::error::{self, Result};
+use crate::json::Json;
+use crate::record_based_file_mgr::RecordBasedFileMgr;
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::ser::{self, Impossible, Serialize};
+use serde::{forward_to_deserialize_any, Deserializer, Serializer};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::convert::TryInto;
+use std::fmt;
+
+/// Serialize value, a struct or map, to a record in file's byte format,
+/// including the fields its dropped attributes leave unused.
+/// Records that don't fit on a page are rejected, since they need the file's
+/// overflow pages.
+pub fn to_bytes<T: Serialize + ?Sized>(value: &T, file: &RecordBasedFileMgr) -> Result<Vec<u8>> {
+    let vals = to_attribute_values(value, file.attributes())?;
+    file.encode_inline(&vals)
+}
+
+/// Deserialize a record in file's byte format. Records with values in
+/// overflow pages are rejected.
+pub fn from_bytes<T: DeserializeOwned>(bytes: &[u8], file: &RecordBasedFileMgr) -> Result<T> {
+    from_values(file.decode_inline(bytes)?)
+}
+
+/// Serialize value, a struct or map, to attribute values keyed by name.
+/// Values aren't converted to any schema's types.
+pub fn to_values<T: Serialize + ?Sized>(value: &T) -> Result<HashMap<String, AttributeValue>> {
+    match value.serialize(ValueSerializer)? {
+        Serialized::Fields(fields) => Ok(fields.into_iter().collect()),
+        Serialized::Value(_) => Err(Error::new("a record must be a struct or map").into()),
+    }
+}
+
+/// Deserialize attribute values keyed by name, such as the result of
+/// RecordBasedFileMgr::read.
+pub fn from_values<T: DeserializeOwned>(vals: HashMap<String, AttributeValue>) -> Result<T> {
+    let deserializer = MapDeserializer::new(
+        vals.into_iter()
+            .map(|(name, attr_val)| (name, ValueDeserializer(attr_val))),
+    );
+    Ok(T::deserialize(deserializer)?)
+}
+
+/// Serialize value and resolve it against attributes, as insert would.
+/// Returns the values in attribute order.
+pub(crate) fn to_attribute_values<T: Serialize + ?Sized>(
+    value: &T,
+    attributes: &[Attribute],
+) -> Result<Vec<AttributeValue>> {
+    let mut vals = to_values(value)?;
+    for attr in attributes.iter() {
+        if let Some(attr_val) = vals.get_mut(&attr.name) {
+            if let Some(bytes) = byte_array(&attr.attribute_type, attr_val) {
+                *attr_val = AttributeValue::Varbinary(bytes);
+            }
+        }
+    }
+    RecordBasedFileMgr::prepare_values(attributes, &vals, true)
+}
+
+/// Sequences of u8 serialize as Arrays of Ints. Binary attributes take their bytes.
+fn byte_array(attr_type: &AttributeType, attr_val: &AttributeValue) -> Option<Vec<u8>> {
+    match (attr_type, attr_val) {
+        (AttributeType::Varbinary { .. }, AttributeValue::Array(elems))
+        | (AttributeType::Blob, AttributeValue::Array(elems)) => elems
+            .iter()
+            .map(|elem| match elem {
+                Some(AttributeValue::Int(val)) => u8::try_from(*val).ok(),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Serde errors. Converts into an InvalidInput error.
+#[derive(Debug)]
+pub struct Error(String);
+
+impl Error {
+    fn new(msg: &str) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl From<Error> for error::Error {
+    fn from(e: Error) -> Self {
+        error::Error::InvalidInput(e.0)
+    }
+}
+
+type SerdeResult<T> = std::result::Result<T, Error>;
+
+// Structs and maps keep their fields, so the outermost one can become a
+// record. Nested ones become Json objects.
+enum Serialized {
+    Value(AttributeValue),
+    Fields(Vec<(String, AttributeValue)>),
+}
+
+impl Serialized {
+    fn into_value(self) -> SerdeResult<AttributeValue> {
+        match self {
+            Serialized::Value(attr_val) => Ok(attr_val),
+            Serialized::Fields(fields) => Ok(AttributeValue::Json(Json::Object(
+                fields
+                    .into_iter()
+                    .map(|(name, attr_val)| Ok((name, to_json(attr_val)?)))
+                    .collect::<SerdeResult<_>>()?,
+            ))),
+        }
+    }
+}
+
+fn to_json(attr_val: AttributeValue) -> SerdeResult<Json> {
+    match cast::cast(&attr_val, &AttributeType::Json) {
+        Ok(AttributeValue::Json(json)) => Ok(json),
+        // Casting Null to Json gives Null
+        Ok(_) => Ok(Json::Null),
+        Err(e) => Err(Error(e.to_string())),
+    }
+}
+
+fn serialize_value<T: Serialize + ?Sized>(value: &T) -> SerdeResult<AttributeValue> {
+    value.serialize(ValueSerializer)?.into_value()
+}
+
+struct ValueSerializer;
+
+impl ValueSerializer {
+    fn int<T: TryInto<i32> + fmt::Display + Copy>(val: T) -> SerdeResult<Serialized> {
+        val.try_into()
+            .map(|val| Serialized::Value(AttributeValue::Int(val)))
+            .map_err(|_| Error(format!("{} is out of range for an Int", val)))
+    }
+}
+
+impl Serializer for ValueSerializer {
+    type Ok = Serialized;
+    type Error = Error;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = Impossible<Serialized, Error>;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = Impossible<Serialized, Error>;
+
+    fn serialize_bool(self, v: bool) -> SerdeResult<Serialized> {
+        Self::int(v as i32)
+    }
+
+    fn serialize_i8(self, v: i8) -> SerdeResult<Serialized> {
+        Self::int(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> SerdeResult<Serialized> {
+        Self::int(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> SerdeResult<Serialized> {
+        Self::int(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> SerdeResult<Serialized> {
+        Self::int(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> SerdeResult<Serialized> {
+        Self::int(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> SerdeResult<Serialized> {
+        Self::int(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> SerdeResult<Serialized> {
+        Self::int(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> SerdeResult<Serialized> {
+        Self::int(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> SerdeResult<Serialized> {
+        Ok(Serialized::Value(AttributeValue::Real(v as f64)))
+    }
+
+    fn serialize_f64(self, v: f64) -> SerdeResult<Serialized> {
+        Ok(Serialized::Value(AttributeValue::Real(v)))
+    }
+
+    fn serialize_char(self, v: char) -> SerdeResult<Serialized> {
+        Ok(Serialized::Value(AttributeValue::Varchar(v.to_string())))
+    }
+
+    fn serialize_str(self, v: &str) -> SerdeResult<Serialized> {
+        Ok(Serialized::Value(AttributeValue::Varchar(v.to_string())))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> SerdeResult<Serialized> {
+        Ok(Serialized::Value(AttributeValue::Varbinary(v.to_vec())))
+    }
+
+    fn serialize_none(self) -> SerdeResult<Serialized> {
+        Ok(Serialized::Value(AttributeValue::Null))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> SerdeResult<Serialized> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> SerdeResult<Serialized> {
+        Ok(Serialized::Value(AttributeValue::Null))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> SerdeResult<Serialized> {
+        Ok(Serialized::Value(AttributeValue::Null))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> SerdeResult<Serialized> {
+        Ok(Serialized::Value(AttributeValue::Varchar(
+            variant.to_string(),
+        )))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> SerdeResult<Serialized> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _value: &T,
+    ) -> SerdeResult<Serialized> {
+        Err(unsupported_variant(name, variant))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> SerdeResult<SeqSerializer> {
+        Ok(SeqSerializer {
+            elems: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> SerdeResult<SeqSerializer> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> SerdeResult<SeqSerializer> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> SerdeResult<Self::SerializeTupleVariant> {
+        Err(unsupported_variant(name, variant))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> SerdeResult<MapSerializer> {
+        Ok(MapSerializer {
+            fields: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> SerdeResult<MapSerializer> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> SerdeResult<Self::SerializeStructVariant> {
+        Err(unsupported_variant(name, variant))
+    }
+}
+
+fn unsupported_variant(name: &str, variant: &str) -> Error {
+    Error(format!(
+        "{}::{} can't be stored, only unit variants are supported",
+        name, variant
+    ))
+}
+
+struct SeqSerializer {
+    // None for null elements
+    elems: Vec<Option<AttributeValue>>,
+}
+
+impl SeqSerializer {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        self.elems.push(match serialize_value(value)? {
+            AttributeValue::Null => None,
+            attr_val => Some(attr_val),
+        });
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Serialized;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> SerdeResult<Serialized> {
+        Ok(Serialized::Value(AttributeValue::Array(self.elems)))
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Serialized;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> SerdeResult<Serialized> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Serialized;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> SerdeResult<Serialized> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+struct MapSerializer {
+    fields: Vec<(String, AttributeValue)>,
+    // Key of the entry being serialized
+    key: Option<String>,
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Serialized;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> SerdeResult<()> {
+        match serialize_value(key)? {
+            AttributeValue::Varchar(key) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            _ => Err(Error::new("map keys must be strings")),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| Error::new("map value without a key"))?;
+        self.fields.push((key, serialize_value(value)?));
+        Ok(())
+    }
+
+    fn end(self) -> SerdeResult<Serialized> {
+        Ok(Serialized::Fields(self.fields))
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Serialized;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> SerdeResult<()> {
+        self.fields.push((key.to_string(), serialize_value(value)?));
+        Ok(())
+    }
+
+    fn end(self) -> SerdeResult<Serialized> {
+        Ok(Serialized::Fields(self.fields))
+    }
+}
+
+struct ValueDeserializer(AttributeValue);
+
+impl<'de> IntoDeserializer<'de, Error> for ValueDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+fn visit_elems<'de, V, I>(visitor: V, elems: I) -> SerdeResult<V::Value>
+where
+    V: Visitor<'de>,
+    I: Iterator<Item = ValueDeserializer>,
+{
+    let mut seq = SeqDeserializer::new(elems);
+    let val = visitor.visit_seq(&mut seq)?;
+    seq.end()?;
+    Ok(val)
+}
+
+fn visit_json<'de, V: Visitor<'de>>(json: Json, visitor: V) -> SerdeResult<V::Value> {
+    match json {
+        Json::Null => visitor.visit_unit(),
+        Json::Bool(val) => visitor.visit_bool(val),
+        Json::Int(val) => visitor.visit_i64(val),
+        Json::Real(val) => visitor.visit_f64(val),
+        Json::String(val) => visitor.visit_string(val),
+        Json::Array(elems) => visit_elems(
+            visitor,
+            elems
+                .into_iter()
+                .map(|elem| ValueDeserializer(AttributeValue::Json(elem))),
+        ),
+        Json::Object(members) => {
+            let mut map = MapDeserializer::new(
+                members
+                    .into_iter()
+                    .map(|(key, val)| (key, ValueDeserializer(AttributeValue::Json(val)))),
+            );
+            let val = visitor.visit_map(&mut map)?;
+            map.end()?;
+            Ok(val)
+        }
+    }
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        match self.0 {
+            AttributeValue::Null => visitor.visit_unit(),
+            AttributeValue::Int(val) => visitor.visit_i32(val),
+            AttributeValue::Real(val) => visitor.visit_f64(val),
+            AttributeValue::Varchar(val) | AttributeValue::Char(val) => visitor.visit_string(val),
+            AttributeValue::Varbinary(val) | AttributeValue::Blob(val) => {
+                visitor.visit_byte_buf(val)
+            }
+            AttributeValue::Uuid(val) => visitor.visit_string(val.to_string()),
+            AttributeValue::Json(json) => visit_json(json, visitor),
+            AttributeValue::Array(elems) => visit_elems(
+                visitor,
+                elems
+                    .into_iter()
+                    .map(|elem| ValueDeserializer(elem.unwrap_or(AttributeValue::Null))),
+            ),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        match self.0 {
+            AttributeValue::Null | AttributeValue::Json(Json::Null) => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    // Bools are stored as 0 or 1
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        match self.0 {
+            AttributeValue::Int(0) => visitor.visit_bool(false),
+            AttributeValue::Int(1) => visitor.visit_bool(true),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    // Lets Vec<u8> read back binary attributes
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        match self.0 {
+            AttributeValue::Varbinary(val) | AttributeValue::Blob(val) => {
+                let mut seq = SeqDeserializer::new(val.into_iter());
+                let val = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(val)
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> SerdeResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> SerdeResult<V::Value> {
+        match self.0 {
+            AttributeValue::Varchar(variant) | AttributeValue::Char(variant) => {
+                visitor.visit_enum(variant.into_deserializer())
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record_based_file_mgr::{RecordHeader, RecordId};
+    use crate::uuid::Uuid;
+    use serde::{Deserialize, Serialize};
+    use tempfile::tempdir;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Class {
+        Warrior,
+        Mage,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Stats {
+        strength: i32,
+        speed: f64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Player {
+        name: String,
+        level: u16,
+        active: bool,
+        class: Class,
+        guild: Option<String>,
+        id: Uuid,
+        avatar: Vec<u8>,
+        scores: Vec<Option<f64>>,
+        stats: Stats,
+    }
+
+    fn attrs() -> Vec<Attribute> {
+        vec![
+            Attribute::new("name", AttributeType::Char { len: 10 }),
+            Attribute::new("level", AttributeType::Int),
+            Attribute::new("active", AttributeType::Int),
+            Attribute::new("class", AttributeType::Varchar { len: 10 }),
+            Attribute::new("guild", AttributeType::Varchar { len: 10 }),
+            Attribute::new("id", AttributeType::Uuid),
+            Attribute::new("avatar", AttributeType::Blob),
+            Attribute::new(
+                "scores",
+                AttributeType::Array(Box::new(AttributeType::Real)),
+            ),
+            Attribute::new("stats", AttributeType::Json),
+        ]
+    }
+
+    fn player() -> Player {
+        Player {
+            name: "Cow".to_string(),
+            level: 28,
+            active: true,
+            class: Class::Mage,
+            guild: None,
+            id: "67e55044-10b1-426f-9247-bb680e5fe0c8".parse().unwrap(),
+            avatar: vec![1, 2, 3],
+            scores: vec![Some(1.5), None],
+            stats: Stats {
+                strength: 10,
+                speed: 2.5,
+            },
+        }
+    }
+
+    #[test]
+    fn record_serde_insert_read_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs()).unwrap();
+
+        let rid = file.insert_serialized(&player()).unwrap();
+        let read_result = file.read(&rid).unwrap();
+        // Values are stored with the schema's types
+        assert_eq!(read_result["name"], AttributeValue::Char("Cow".to_string()));
+        assert_eq!(read_result["active"], AttributeValue::Int(1));
+        assert_eq!(read_result["guild"], AttributeValue::Null);
+        assert_eq!(read_result["avatar"], AttributeValue::Blob(vec![1, 2, 3]));
+        assert_eq!(
+            read_result["stats"],
+            AttributeValue::Json(r#"{"strength":10,"speed":2.5}"#.parse().unwrap())
+        );
+        assert_eq!(file.read_deserialized::<Player>(&rid).unwrap(), player());
+
+        // Values that can't be converted are rejected
+        #[derive(Serialize)]
+        struct BadLevel {
+            level: &'static str,
+        }
+        assert!(file.insert_serialized(&BadLevel { level: "high" }).is_err());
+        assert!(file.insert_serialized(&5).is_err());
+    }
+
+    #[test]
+    fn record_serde_bytes_test() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("testfile");
+        let mut file = RecordBasedFileMgr::create(&file_path, attrs()).unwrap();
+        let bytes = to_bytes(&player(), &file).unwrap();
+        let player_read: Player = from_bytes(&bytes, &file).unwrap();
+        assert_eq!(player_read, player());
+
+        // Records use the file's field layout, which changes with its schema
+        file.drop_attribute("guild").unwrap();
+        file.add_attribute(Attribute::new("guild", AttributeType::Varchar { len: 10 }))
+            .unwrap();
+        let mut guild_player = player();
+        guild_player.guild = Some("Herd".to_string());
+        let bytes = to_bytes(&guild_player, &file).unwrap();
+        assert_eq!(RecordHeader::parse(&bytes).unwrap().num_attributes, 10);
+        let player_read: Player = from_bytes(&bytes, &file).unwrap();
+        assert_eq!(player_read, guild_player);
+
+        // Records that need overflow pages are rejected
+        let mut large_player = player();
+        large_player.avatar = vec![0; 10_000];
+        assert!(to_bytes(&large_player, &file).is_err());
+    }
+
+    #[test]
+    fn attribute_serde_test() {
+        let attr = Attribute::new(
+            "Scores",
+            AttributeType::Array(Box::new(AttributeType::Real)),
+        )
+        .with_default(AttributeValue::Array(vec![Some(AttributeValue::Real(1.0))]));
+        let bytes = bincode::serialize(&attr).unwrap();
+        let attr_read: Attribute = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(attr_read.attribute_type, attr.attribute_type);
+        assert_eq!(attr_read.default, attr.default);
+
+        let vals = vec![
+            AttributeValue::Uuid("67e55044-10b1-426f-9247-bb680e5fe0c8".parse().unwrap()),
+            AttributeValue::Json(r#"{"a":[1,null,"x"]}"#.parse().unwrap()),
+            AttributeValue::Null,
+        ];
+        let bytes = bincode::serialize(&vals).unwrap();
+        assert_eq!(
+            bincode::deserialize::<Vec<AttributeValue>>(&bytes).unwrap(),
+            vals
+        );
+
+        let rid = RecordId {
+            page_num: 1,
+            slot_num: 2,
+        };
+        let bytes = bincode::serialize(&rid).unwrap();
+        assert_eq!(bincode::deserialize::<RecordId>(&bytes).unwrap(), rid);
+    }
+}
@@ -0,0 +1,63 @@
+// Rows
+//
+// A record's values by position, in the order of the file's attributes, for
+// code that would rather not build a HashMap keyed by attribute name for
+// every record. RecordBasedFileMgr::attribute_index maps names to positions.
+
+use crate::attribute::AttributeValue;
+
+/// A record's values in schema order. None is NULL: Some(AttributeValue::Null)
+/// is stored as None, so there's one way to write NULL and rows compare
+/// equal however they were built.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Row {
+    values: Vec<Option<AttributeValue>>,
+}
+
+impl Row {
+    pub fn new(values: Vec<Option<AttributeValue>>) -> Self {
+        Self {
+            values: values.into_iter().map(normalize).collect(),
+        }
+    }
+
+    /// The value at idx. None if it's NULL or idx is out of range.
+    pub fn get(&self, idx: usize) -> Option<&AttributeValue> {
+        self.values.get(idx)?.as_ref()
+    }
+
+    /// Set the value at idx.
+    /// Panics if idx is out of range, like indexing a Vec.
+    pub fn set(&mut self, idx: usize, val: Option<AttributeValue>) {
+        self.values[idx] = normalize(val);
+    }
+
+    pub fn values(&self) -> &[Option<AttributeValue>] {
+        &self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn into_values(self) -> Vec<Option<AttributeValue>> {
+        self.values
+    }
+}
+
+fn normalize(val: Option<AttributeValue>) -> Option<AttributeValue> {
+    match val {
+        Some(AttributeValue::Null) => None,
+        val => val,
+    }
+}
+
+impl From<Vec<Option<AttributeValue>>> for Row {
+    fn from(values: Vec<Option<AttributeValue>>) -> Self {
+        Self::new(values)
+    }
+}
@@ -0,0 +1,163 @@
+use crate::error::{Error, Result};
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+
+pub const UUID_SIZE: usize = 16;
+
+/// A UUID stored as its 16 raw bytes.
+/// Bytes are kept in the order they appear in the canonical form, so
+/// comparing the bytes orders UUIDs the same way as their string forms.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Uuid([u8; UUID_SIZE]);
+
+impl Uuid {
+    pub fn from_bytes(bytes: [u8; UUID_SIZE]) -> Self {
+        Uuid(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; UUID_SIZE] {
+        &self.0
+    }
+}
+
+/// Parses the canonical hyphenated form, e.g. 67e55044-10b1-426f-9247-bb680e5fe0c8.
+/// Hex digits may be upper or lower case.
+impl FromStr for Uuid {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::invalid_input(format!("Invalid UUID: {}", s));
+        let groups: Vec<&str> = s.split('-').collect();
+        let group_lens = [8, 4, 4, 4, 12];
+        if groups.len() != group_lens.len()
+            || groups
+                .iter()
+                .zip(group_lens.iter())
+                .any(|(g, l)| g.len() != *l)
+        {
+            return Err(invalid());
+        }
+
+        let hex = groups.concat();
+        // from_str_radix alone would accept a leading '+'
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let mut bytes = [0; UUID_SIZE];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Uuid(bytes))
+    }
+}
+
+/// Formats as the lower case canonical hyphenated form.
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i == 4 || i == 6 || i == 8 || i == 10 {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Serializes as the canonical string for human readable formats, and as the
+/// 16 bytes otherwise.
+impl Serialize for Uuid {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Uuid {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct UuidVisitor;
+
+        impl<'de> Visitor<'de> for UuidVisitor {
+            type Value = Uuid;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a UUID string or 16 bytes")
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> std::result::Result<Uuid, E> {
+                s.parse().map_err(E::custom)
+            }
+
+            fn visit_bytes<E: de::Error>(self, b: &[u8]) -> std::result::Result<Uuid, E> {
+                b.try_into()
+                    .map(Uuid)
+                    .map_err(|_| E::invalid_length(b.len(), &self))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(UuidVisitor)
+        } else {
+            deserializer.deserialize_bytes(UuidVisitor)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uuid_parse_test() {
+        let s = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        let uuid: Uuid = s.parse().unwrap();
+        assert_eq!(uuid.as_bytes()[0], 0x67);
+        assert_eq!(uuid.as_bytes()[15], 0xc8);
+        assert_eq!(uuid.to_string(), s);
+        // Upper case parses, but always formats as lower case
+        let upper: Uuid = s.to_uppercase().parse().unwrap();
+        assert_eq!(upper, uuid);
+        assert_eq!(upper.to_string(), s);
+
+        assert!("67e55044-10b1-426f-9247".parse::<Uuid>().is_err());
+        assert!("67e5504410b1426f9247bb680e5fe0c8".parse::<Uuid>().is_err());
+        assert!("67e55044-10b1-426f-9247-bb680e5fe0cg"
+            .parse::<Uuid>()
+            .is_err());
+        assert!("+7e55044-10b1-426f-9247-bb680e5fe0c8"
+            .parse::<Uuid>()
+            .is_err());
+        assert!("67e55044-10b1-426f-9247-bb680e5fe0é"
+            .parse::<Uuid>()
+            .is_err());
+    }
+
+    #[test]
+    fn uuid_order_test() {
+        let a: Uuid = "00000000-0000-0000-0000-0000000000ff".parse().unwrap();
+        let b: Uuid = "00000000-0000-0000-0000-000000000100".parse().unwrap();
+        let c: Uuid = "10000000-0000-0000-0000-000000000000".parse().unwrap();
+        assert!(a < b);
+        assert!(b < c);
+        assert_eq!(a.to_string() < b.to_string(), a < b);
+
+        // Strings for human readable formats, bytes for others
+        assert_eq!(
+            serde_json::to_string(&a).unwrap(),
+            "\"00000000-0000-0000-0000-0000000000ff\""
+        );
+        assert_eq!(
+            serde_json::from_str::<Uuid>(&serde_json::to_string(&a).unwrap()).unwrap(),
+            a
+        );
+        assert_eq!(bincode::serialize(&a).unwrap().len(), 8 + UUID_SIZE);
+        let bytes = bincode::serialize(&a).unwrap();
+        assert_eq!(bincode::deserialize::<Uuid>(&bytes).unwrap(), a);
+    }
+}